@@ -126,7 +126,7 @@ impl Feed {
         if items.is_empty() {
             return String::new();
         }
-        items.sort_by(|a, b| b.pub_date.cmp(&a.pub_date));
+        items.sort_by_key(|item| std::cmp::Reverse(item.pub_date));
         let most_recent = items.first().unwrap();
         let months = months_range(most_recent.pub_date.unwrap(), config.max_age_in_months);
         let mut out = String::new();
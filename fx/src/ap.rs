@@ -1,26 +1,620 @@
 //! ActivityPub
-
+//!
+//! The site is exposed as a single actor with an outbox containing the posts.
+//! Other servers can follow the actor via the inbox.
+use crate::data::Kv;
+use crate::data::Post;
+use crate::data::SqliteDateTime;
+use crate::replies::BlockedDomain;
+use crate::replies::Reaction;
+use crate::replies::ReactionKind;
+use crate::replies::Reply;
 use crate::serve::ServerContext;
+use crate::serve::content_type;
+use crate::serve::iso8601;
+use crate::serve::response;
+use crate::signature::Keypair;
+use axum::Router;
+use axum::body::Body;
+use axum::body::Bytes;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Method;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::routing::get;
+use axum::routing::post;
+use chrono::DateTime;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::Result;
 use serde_json::Value;
 use serde_json::json;
+use sha2::Digest;
+
+pub const ACTIVITY_JSON: &str = "application/activity+json";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Returns the domain without scheme, for example "example.com".
+fn host(ctx: &ServerContext) -> String {
+    ctx.args.domain.trim().trim_end_matches('/').to_string()
+}
+
+/// Returns the URL of the image that represents the site, if any.
+///
+/// This is the icon from the extra head (the favicon) or otherwise the first
+/// image in the about section.
+pub fn avatar(ctx: &ServerContext) -> Option<String> {
+    let conn = ctx.conn();
+    let extra_head = Kv::get_or_empty_string(&conn, "extra_head");
+    let icon =
+        regex::Regex::new(r#"<link[^>]*rel=['"](?:shortcut )?icon['"][^>]*href=['"]([^'"]+)['"]"#)
+            .unwrap();
+    let image = regex::Regex::new(r"!\[[^\]]*\]\(([^)\s]+)").unwrap();
+    let url = match icon.captures(&extra_head) {
+        Some(captures) => captures[1].to_string(),
+        None => {
+            let about = Kv::get_or_empty_string(&conn, "about");
+            image.captures(&about)?[1].to_string()
+        }
+    };
+    if url.starts_with('/') {
+        Some(format!("{}{url}", ctx.base_url()))
+    } else {
+        Some(url)
+    }
+}
 
 /// Returns a JSON object that can be used as WebFinger response.
 ///
+/// Returns `None` if the resource is not the account of this site.
+///
 /// And do some basic verification via <https://webfinger.net/>.
-pub async fn webfinger(ctx: &ServerContext) -> Option<Value> {
-    let domain = ctx.base_url();
+pub async fn webfinger(ctx: &ServerContext, resource: &str) -> Option<Value> {
+    let base = ctx.base_url();
     let username = &ctx.args.username;
+    let account = format!("{username}@{}", host(ctx));
+    let subject = format!("acct:{account}");
+    let actor = actor_url(ctx);
+    let known = [&subject, &account, &actor, &base, &format!("{base}/")];
+    if !known
+        .iter()
+        .any(|known| known.eq_ignore_ascii_case(resource))
+    {
+        return None;
+    }
+    let mut links = vec![
+        json!({
+            "rel": "http://webfinger.net/rel/profile-page",
+            "type": "text/html",
+            "href": base,
+        }),
+        json!({
+            "rel": "self",
+            "type": ACTIVITY_JSON,
+            "href": actor,
+        }),
+    ];
+    if let Some(avatar) = avatar(ctx) {
+        links.push(json!({
+            "rel": "http://webfinger.net/rel/avatar",
+            "href": avatar,
+        }));
+    }
     Some(json!({
-        "subject": format!("acct:{username}@{domain}"),
+        "subject": subject,
         "aliases": [
-            domain,
-        ],
-        "links": [
-            {
-                "rel": "http://webfinger.net/rel/profile-page",
-                "type": "text/html",
-                "href": domain,
-            },
+            base,
+            actor,
         ],
+        "links": links,
     }))
 }
+
+/// An actor on another server that follows this site.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Follower {
+    /// The id of the remote actor.
+    pub actor: String,
+    pub inbox: String,
+    /// Servers can offer a shared inbox to receive one request for multiple
+    /// followers on the same server.
+    pub shared_inbox: Option<String>,
+    pub created: DateTime<Utc>,
+}
+
+impl Follower {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS followers (
+                actor TEXT PRIMARY KEY,
+                inbox TEXT NOT NULL,
+                shared_inbox TEXT,
+                created DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    pub fn insert(&self, conn: &Connection) -> Result<usize> {
+        let stmt = "
+            INSERT OR REPLACE INTO followers (actor, inbox, shared_inbox, created)
+            VALUES (?, ?, ?, ?);
+        ";
+        let params = rusqlite::params![
+            self.actor,
+            self.inbox,
+            self.shared_inbox,
+            self.created.to_sqlite()
+        ];
+        conn.execute(stmt, params)
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<Follower>> {
+        let stmt = "
+            SELECT actor, inbox, shared_inbox, created
+            FROM followers
+            ORDER BY created;
+        ";
+        conn.prepare(stmt)?
+            .query_map([], |row| {
+                let created: String = row.get("created")?;
+                Ok(Follower {
+                    actor: row.get("actor")?,
+                    inbox: row.get("inbox")?,
+                    shared_inbox: row.get("shared_inbox")?,
+                    created: DateTime::from_sqlite(&created),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    pub fn delete(conn: &Connection, actor: &str) -> Result<usize> {
+        let stmt = "DELETE FROM followers WHERE actor = ?";
+        conn.execute(stmt, [actor])
+    }
+    /// Remove all followers that receive activities via the given inbox.
+    pub fn delete_by_inbox(conn: &Connection, inbox: &str) -> Result<usize> {
+        let stmt = "DELETE FROM followers WHERE inbox = ?1 OR shared_inbox = ?1";
+        conn.execute(stmt, [inbox])
+    }
+}
+
+pub fn actor_url(ctx: &ServerContext) -> String {
+    format!("{}/ap/actor", ctx.base_url())
+}
+
+pub fn key_id(ctx: &ServerContext) -> String {
+    format!("{}#main-key", actor_url(ctx))
+}
+
+fn note_url(ctx: &ServerContext, id: i64) -> String {
+    format!("{}/ap/notes/{id}", ctx.base_url())
+}
+
+/// Return the post as an ActivityStreams `Note`.
+pub fn note(ctx: &ServerContext, post: &Post) -> Value {
+    let base = ctx.base_url();
+    let slug = crate::md::extract_slug(post);
+    let url = format!("{base}{}", crate::html::post_link(post, &slug));
+    let mut note = json!({
+        "id": note_url(ctx, post.id),
+        "type": "Note",
+        "attributedTo": actor_url(ctx),
+        "url": url,
+        "published": iso8601(&post.created),
+        "to": [PUBLIC],
+        "cc": [format!("{base}/ap/followers")],
+        "content": crate::md::content_to_html(post.body()),
+        "mediaType": "text/html",
+    });
+    if post.created != post.updated {
+        note["updated"] = json!(iso8601(&post.updated));
+    }
+    note
+}
+
+fn create(ctx: &ServerContext, post: &Post) -> Value {
+    let note = note(ctx, post);
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/activity", note_url(ctx, post.id)),
+        "type": "Create",
+        "actor": actor_url(ctx),
+        "published": note["published"],
+        "to": note["to"],
+        "cc": note["cc"],
+        "object": note,
+    })
+}
+
+fn update(ctx: &ServerContext, post: &Post) -> Value {
+    let note = note(ctx, post);
+    // Each update needs a new id or receivers will ignore it as a duplicate.
+    let updated = post.updated.timestamp();
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/activity/update/{updated}", note_url(ctx, post.id)),
+        "type": "Update",
+        "actor": actor_url(ctx),
+        "published": iso8601(&post.updated),
+        "to": note["to"],
+        "cc": note["cc"],
+        "object": note,
+    })
+}
+
+fn delete(ctx: &ServerContext, id: i64) -> Value {
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}/activity/delete", note_url(ctx, id)),
+        "type": "Delete",
+        "actor": actor_url(ctx),
+        "to": [PUBLIC],
+        "object": {
+            "id": note_url(ctx, id),
+            "type": "Tombstone",
+        },
+    })
+}
+
+/// Send a `Create` for the new post to the followers.
+pub async fn send_create(ctx: &ServerContext, id: i64) {
+    match Post::get(&ctx.conn(), id) {
+        Ok(post) => crate::delivery::enqueue_followers(ctx, &create(ctx, &post)).await,
+        Err(e) => tracing::error!("Failed to get post {id} for delivery: {e}"),
+    }
+}
+
+/// Send an `Update` for the edited post to the followers.
+pub async fn send_update(ctx: &ServerContext, id: i64) {
+    match Post::get(&ctx.conn(), id) {
+        Ok(post) => crate::delivery::enqueue_followers(ctx, &update(ctx, &post)).await,
+        Err(e) => tracing::error!("Failed to get post {id} for delivery: {e}"),
+    }
+}
+
+/// Send a `Delete` for the deleted post to the followers.
+pub async fn send_delete(ctx: &ServerContext, id: i64) {
+    crate::delivery::enqueue_followers(ctx, &delete(ctx, id)).await;
+}
+
+fn context() -> Value {
+    json!([
+        "https://www.w3.org/ns/activitystreams",
+        "https://w3id.org/security/v1",
+    ])
+}
+
+fn response_activity(ctx: &ServerContext, status: StatusCode, body: &Value) -> Response<Body> {
+    let mut headers = HeaderMap::new();
+    content_type(&mut headers, ACTIVITY_JSON);
+    response(status, headers, body.to_string(), ctx)
+}
+
+fn status(ctx: &ServerContext, status: StatusCode) -> Response<Body> {
+    let body = status.canonical_reason().unwrap_or_default();
+    response(status, HeaderMap::new(), body, ctx)
+}
+
+async fn get_actor(State(ctx): State<ServerContext>) -> Response<Body> {
+    let keypair = match Keypair::obtain(&ctx.conn()) {
+        Ok(keypair) => keypair,
+        Err(e) => {
+            tracing::error!("Failed to obtain keypair: {e}");
+            return status(&ctx, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let conn = ctx.conn();
+    let base = ctx.base_url();
+    let actor = actor_url(&ctx);
+    let about = Kv::get_or_empty_string(&conn, "about");
+    let mut body = json!({
+        "@context": context(),
+        "id": actor,
+        "type": "Person",
+        "preferredUsername": ctx.args.username,
+        "name": Kv::get_or_empty_string(&conn, "author_name"),
+        "summary": crate::md::content_to_html(&about),
+        "url": base,
+        "inbox": format!("{base}/ap/inbox"),
+        "outbox": format!("{base}/ap/outbox"),
+        "followers": format!("{base}/ap/followers"),
+        "endpoints": {
+            "sharedInbox": format!("{base}/ap/inbox"),
+        },
+        "manuallyApprovesFollowers": false,
+        "discoverable": true,
+        "publicKey": {
+            "id": key_id(&ctx),
+            "owner": actor,
+            "publicKeyPem": keypair.public_key_pem,
+        },
+    });
+    if let Some(url) = avatar(&ctx) {
+        body["icon"] = json!({ "type": "Image", "url": url });
+    }
+    response_activity(&ctx, StatusCode::OK, &body)
+}
+
+async fn get_outbox(State(ctx): State<ServerContext>) -> Response<Body> {
+    let posts = match Post::list(&ctx.conn()) {
+        Ok(posts) => posts,
+        Err(e) => {
+            tracing::error!("Failed to list posts: {e}");
+            return status(&ctx, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    let items = posts
+        .iter()
+        .map(|post| create(&ctx, post))
+        .collect::<Vec<Value>>();
+    let body = json!({
+        "@context": context(),
+        "id": format!("{}/ap/outbox", ctx.base_url()),
+        "type": "OrderedCollection",
+        "totalItems": items.len(),
+        "orderedItems": items,
+    });
+    response_activity(&ctx, StatusCode::OK, &body)
+}
+
+async fn get_followers(State(ctx): State<ServerContext>) -> Response<Body> {
+    let followers = match Follower::list(&ctx.conn()) {
+        Ok(followers) => followers,
+        Err(e) => {
+            tracing::error!("Failed to list followers: {e}");
+            return status(&ctx, StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    };
+    // Only showing the count like Mastodon does to not expose the followers.
+    let body = json!({
+        "@context": context(),
+        "id": format!("{}/ap/followers", ctx.base_url()),
+        "type": "OrderedCollection",
+        "totalItems": followers.len(),
+    });
+    response_activity(&ctx, StatusCode::OK, &body)
+}
+
+async fn get_note(State(ctx): State<ServerContext>, Path(id): Path<i64>) -> Response<Body> {
+    let post = match Post::get(&ctx.conn(), id) {
+        Ok(post) if post.content != "<DELETED>" => post,
+        _ => return status(&ctx, StatusCode::NOT_FOUND),
+    };
+    let mut body = note(&ctx, &post);
+    body["@context"] = json!("https://www.w3.org/ns/activitystreams");
+    response_activity(&ctx, StatusCode::OK, &body)
+}
+
+/// Fetch an ActivityPub object such as an actor from another server.
+///
+/// The request is signed since servers in "secure mode" reject unsigned
+/// requests.
+pub async fn fetch(ctx: &ServerContext, url: &str) -> Result<Value, String> {
+    let public_url = crate::net::public_url(ctx, url)?;
+    let keypair = Keypair::obtain(&ctx.conn())?;
+    let headers = crate::signature::sign(&keypair.private_key, &key_id(ctx), "GET", url, None)?;
+    let response = crate::net::client(ctx)
+        .get(public_url)
+        .headers(headers)
+        .header("Accept", ACTIVITY_JSON)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("fetching {url} returned {}", response.status()));
+    }
+    let body = crate::net::text(response, crate::net::MAX_BODY).await?;
+    serde_json::from_str(&body).map_err(|e| e.to_string())
+}
+
+/// Why a delivery to an inbox failed.
+#[derive(Debug, PartialEq)]
+pub enum DeliveryError {
+    /// The inbox responded with 410 Gone, so retrying is pointless.
+    Gone,
+    Failed(String),
+}
+
+impl std::fmt::Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryError::Gone => write!(f, "inbox is gone"),
+            DeliveryError::Failed(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Send a signed activity to the inbox of another server.
+///
+/// Use `crate::delivery::enqueue` instead to retry when the other server is
+/// temporarily unavailable.
+pub async fn deliver(
+    ctx: &ServerContext,
+    inbox: &str,
+    activity: &str,
+) -> Result<(), DeliveryError> {
+    // The inbox comes from the actor document of the follower.
+    let url = crate::net::public_url(ctx, inbox).map_err(DeliveryError::Failed)?;
+    let keypair = Keypair::obtain(&ctx.conn()).map_err(DeliveryError::Failed)?;
+    let key_id = key_id(ctx);
+    let body_bytes = Some(activity.as_bytes());
+    let headers = crate::signature::sign(&keypair.private_key, &key_id, "POST", inbox, body_bytes)
+        .map_err(DeliveryError::Failed)?;
+    let response = crate::net::client(ctx)
+        .post(url)
+        .headers(headers)
+        .header("Content-Type", ACTIVITY_JSON)
+        .body(activity.to_string())
+        .send()
+        .await
+        .map_err(|e| DeliveryError::Failed(e.to_string()))?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else if status == reqwest::StatusCode::GONE {
+        Err(DeliveryError::Gone)
+    } else {
+        Err(DeliveryError::Failed(format!(
+            "delivery to {inbox} returned {status}"
+        )))
+    }
+}
+
+/// Return the id of an object that can either be inlined or referenced by id.
+fn id_of(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(id) => Some(id),
+        Value::Object(object) => object.get("id").and_then(|id| id.as_str()),
+        _ => None,
+    }
+}
+
+fn host_of(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    url.host_str().map(|host| host.to_string())
+}
+
+/// Verify the HTTP signature and return the actor that signed the request.
+async fn verified_actor(
+    ctx: &ServerContext,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &[u8],
+    activity: &Value,
+) -> Result<Value, String> {
+    let signature = crate::signature::SignatureHeader::parse(headers)
+        .ok_or("missing or invalid signature header")?;
+    let actor_id = signature.key_id.split('#').next().unwrap_or_default();
+    let key_host = host_of(actor_id).ok_or("key id is not a URL")?;
+    let activity_actor = id_of(&activity["actor"]).unwrap_or_default();
+    if host_of(activity_actor).as_ref() != Some(&key_host) {
+        return Err(format!("activity actor is not on {key_host}"));
+    }
+    let actor = fetch(ctx, actor_id).await?;
+    // Otherwise, any document could claim to be another actor with its own key.
+    if id_of(&actor) != Some(actor_id) {
+        return Err(format!("actor at {actor_id} has another id"));
+    }
+    let pem = actor["publicKey"]["publicKeyPem"]
+        .as_str()
+        .ok_or("actor has no public key")?;
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or(uri.path());
+    crate::signature::verify(headers, method.as_str(), path, body, pem)?;
+    Ok(actor)
+}
+
+async fn accept_follow(ctx: &ServerContext, actor: &Value, activity: &Value) -> Result<(), String> {
+    let inbox = actor["inbox"].as_str().ok_or("actor has no inbox")?;
+    let follower = Follower {
+        actor: id_of(actor).ok_or("actor has no id")?.to_string(),
+        inbox: inbox.to_string(),
+        shared_inbox: actor["endpoints"]["sharedInbox"]
+            .as_str()
+            .map(|s| s.to_string()),
+        created: Utc::now(),
+    };
+    follower.insert(&ctx.conn()).map_err(|e| e.to_string())?;
+    tracing::info!("New follower: {}", follower.actor);
+    let follow_id = id_of(activity).unwrap_or_default();
+    let hash = hex::encode(sha2::Sha256::digest(follow_id.as_bytes()));
+    let accept = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#accepts/{}", actor_url(ctx), &hash[..16]),
+        "type": "Accept",
+        "actor": actor_url(ctx),
+        "object": activity,
+    });
+    crate::delivery::enqueue(ctx, &follower.inbox, &accept).await;
+    Ok(())
+}
+
+async fn handle_activity(
+    ctx: &ServerContext,
+    actor: &Value,
+    activity: &Value,
+) -> Result<(), String> {
+    let actor_id = id_of(actor).unwrap_or_default();
+    if id_of(&activity["actor"]) != Some(actor_id) {
+        return Err("activity actor does not match signature".to_string());
+    }
+    if BlockedDomain::is_blocked(&ctx.conn(), actor_id) {
+        tracing::info!("Ignored activity from blocked actor {actor_id}");
+        return Ok(());
+    }
+    match activity["type"].as_str() {
+        Some("Follow") => {
+            if id_of(&activity["object"]) != Some(actor_url(ctx).as_str()) {
+                return Err("follow is not for this actor".to_string());
+            }
+            accept_follow(ctx, actor, activity).await
+        }
+        Some("Undo") if activity["object"]["type"] == "Follow" => {
+            Follower::delete(&ctx.conn(), actor_id).map_err(|e| e.to_string())?;
+            tracing::info!("Removed follower: {actor_id}");
+            Ok(())
+        }
+        Some("Undo") => {
+            let object_id = id_of(&activity["object"]).unwrap_or_default();
+            Reaction::delete(&ctx.conn(), object_id, actor_id).map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        Some("Create") | Some("Update") => {
+            crate::replies::handle_create(ctx, actor, &activity["object"])
+        }
+        Some("Like") => {
+            crate::replies::handle_reaction(ctx, actor_id, activity, ReactionKind::Like)
+        }
+        Some("Announce") => {
+            crate::replies::handle_reaction(ctx, actor_id, activity, ReactionKind::Announce)
+        }
+        Some("Delete") => {
+            let object_id = id_of(&activity["object"]).unwrap_or_default();
+            Reply::delete_by_object_id(&ctx.conn(), object_id, actor_id)
+                .map_err(|e| e.to_string())?;
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+async fn post_inbox(
+    State(ctx): State<ServerContext>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let activity = match serde_json::from_slice::<Value>(&body) {
+        Ok(activity) => activity,
+        Err(_) => return status(&ctx, StatusCode::BAD_REQUEST),
+    };
+    let actor = match verified_actor(&ctx, &method, &uri, &headers, &body, &activity).await {
+        Ok(actor) => actor,
+        Err(e) => {
+            tracing::warn!("Rejected inbox request: {e}");
+            return status(&ctx, StatusCode::UNAUTHORIZED);
+        }
+    };
+    match handle_activity(&ctx, &actor, &activity).await {
+        Ok(()) => status(&ctx, StatusCode::ACCEPTED),
+        Err(e) => {
+            tracing::warn!("Failed to handle activity: {e}");
+            status(&ctx, StatusCode::BAD_REQUEST)
+        }
+    }
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/ap/actor", get(get_actor))
+        .route("/ap/outbox", get(get_outbox))
+        .route("/ap/followers", get(get_followers))
+        .route("/ap/inbox", post(post_inbox))
+        .route("/ap/notes/{id}", get(get_note))
+}
@@ -1,69 +1,137 @@
 //! API endpoints at `/api`.
+use crate::changes::Change;
+use crate::changes::Kind;
 use crate::data::Post;
+use crate::data::SqliteDateTime;
 use crate::files::File;
+use crate::files::FileInfo;
+use crate::openapi::Content;
+use crate::openapi::Endpoint;
+use crate::redirects::Redirect;
+use crate::restore::FileIndex;
+use crate::restore::FileIndexEntry;
+use crate::restore::RestoreReport;
 use crate::serve::ServerContext;
 use crate::serve::response;
 use crate::serve::response_json;
 use crate::settings::Settings;
 use axum::Router;
 use axum::body::Body;
+use axum::extract::FromRequest;
+use axum::extract::Multipart;
+use axum::extract::Path;
+use axum::extract::Query;
+use axum::extract::Request;
 use axum::extract::State;
+use axum::extract::rejection::QueryRejection;
+use axum::handler::Handler;
+use axum::http::Method;
 use axum::http::Response;
 use axum::http::StatusCode;
 use axum::http::header::HeaderMap;
 use axum::http::header::HeaderValue;
-use axum::routing::get;
-use axum::routing::put;
-use serde_json::json;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::SubsecRound;
+use chrono::Utc;
+use futures_util::StreamExt;
+use rusqlite::Connection;
+use rusqlite::MAIN_DB;
+use rusqlite::Params;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io;
 use std::io::Read;
+use std::io::Write;
+use std::time::Duration;
 use subtle::ConstantTimeEq;
 use tar::Builder;
 use tar::Header;
-use xz2::read::XzEncoder;
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use xz2::write::XzEncoder;
+
+/// Links to the API resources.
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct ApiResponse {
+    pub openapi_url: String,
+    pub download_all_url: String,
+    pub posts_url: String,
+    pub files_url: String,
+    pub settings_url: String,
+    pub redirects_url: String,
+}
 
 async fn get_api(State(ctx): State<ServerContext>) -> Response<Body> {
     let domain = ctx.base_url();
-    let body = json!({
-        "download_all_url": format!("{domain}/api/download/all.tar.xz"),
-    })
-    .to_string();
+    let body = ApiResponse {
+        openapi_url: format!("{domain}/api/openapi.json"),
+        download_all_url: format!("{domain}/api/download/all.tar.xz"),
+        posts_url: format!("{domain}/api/posts"),
+        files_url: format!("{domain}/api/files"),
+        settings_url: format!("{domain}/api/settings"),
+        redirects_url: format!("{domain}/api/redirects"),
+    };
+    let body = serde_json::to_string(&body).unwrap();
     response_json(StatusCode::OK, body, &ctx)
 }
 
-fn is_authenticated(ctx: &ServerContext, headers: &HeaderMap) -> bool {
-    let password = &ctx.args.password;
-    let password = if let Some(password) = password {
-        password
-    } else {
-        tracing::warn!("admin password not set");
-        return false;
-    };
-    let header = if let Some(cookie) = headers.get("Authorization") {
-        cookie
-    } else {
-        return false;
-    };
+async fn get_openapi(State(ctx): State<ServerContext>) -> Response<Body> {
+    let body = crate::openapi::document(&ctx, &endpoints()).to_string();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+/// Return the token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    let header = headers.get("Authorization")?;
     let parts = header
         .to_str()
-        .unwrap()
+        .ok()?
         .split_ascii_whitespace()
         .collect::<Vec<&str>>();
     if parts.len() != 2 {
-        return false;
+        return None;
     }
     if parts[0] != "Bearer" {
-        return false;
+        return None;
     }
-    let token = parts[1];
+    Some(parts[1])
+}
+
+/// Whether the token is the admin password.
+///
+/// IndieAuth tokens are not accepted since their scopes do not cover the API.
+pub fn is_valid_token(ctx: &ServerContext, token: &str) -> bool {
+    let password = &ctx.args.password;
+    let password = if let Some(password) = password {
+        password
+    } else {
+        tracing::warn!("admin password not set");
+        return false;
+    };
     token.as_bytes().ct_eq(password.as_bytes()).into()
 }
 
+pub fn is_authenticated(ctx: &ServerContext, headers: &HeaderMap) -> bool {
+    match bearer_token(headers) {
+        Some(token) => is_valid_token(ctx, token),
+        None => false,
+    }
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct ErrorResponse {
+    pub status: u16,
+    pub message: String,
+}
+
 fn error(ctx: &ServerContext, status: StatusCode, message: &str) -> Response<Body> {
-    let body = json!({
-        "status": status.as_u16(),
-        "message": message,
-    })
-    .to_string();
+    let body = ErrorResponse {
+        status: status.as_u16(),
+        message: message.to_string(),
+    };
+    let body = serde_json::to_string(&body).unwrap();
     response_json(status, body, ctx)
 }
 
@@ -71,129 +139,420 @@ fn unauthorized(ctx: &ServerContext) -> Response<Body> {
     error(ctx, StatusCode::UNAUTHORIZED, "unauthorized")
 }
 
-struct SiteData<'a> {
-    posts: &'a [Post],
-    settings: &'a Settings,
-    files: &'a [File],
+/// Size of the chunks in which the archive is sent to the client.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// How long to wait for a client that stopped reading or sending a body.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Sends everything that is written to it in chunks to the response body.
+///
+/// The channel is bounded, so the archive is only produced as fast as the
+/// client reads it and memory usage does not grow with the size of the site.
+struct BodyWriter {
+    tx: mpsc::Sender<io::Result<Bytes>>,
+    buf: Vec<u8>,
 }
 
-fn create_archive(site_data: &SiteData) -> Vec<u8> {
-    let mut ar = Builder::new(Vec::new());
+impl BodyWriter {
+    fn new(tx: mpsc::Sender<io::Result<Bytes>>) -> Self {
+        Self {
+            tx,
+            buf: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+    fn send(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buf, Vec::with_capacity(CHUNK_SIZE));
+        let send = self.tx.send(Ok(Bytes::from(chunk)));
+        let sent = Handle::current().block_on(tokio::time::timeout(CLIENT_TIMEOUT, send));
+        match sent {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "client disconnected",
+            )),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "client stopped reading",
+            )),
+        }
+    }
+}
 
-    for post in site_data.posts {
-        let mut header = Header::new_gnu();
+impl Write for BodyWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        if CHUNK_SIZE <= self.buf.len() {
+            self.send()?;
+        }
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.send()
+    }
+}
+
+/// Reads the request body that is received in chunks from the channel.
+///
+/// The counterpart of `BodyWriter`, so that an upload can be processed by
+/// blocking code while it is received.
+struct BodyReader {
+    rx: mpsc::Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl BodyReader {
+    /// Forward the body to the reader, at most a few chunks ahead.
+    fn new(body: Body) -> Self {
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let mut stream = body.into_data_stream();
+            while let Some(chunk) = stream.next().await {
+                if tx.send(chunk.map_err(io::Error::other)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            rx,
+            chunk: Bytes::new(),
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            let recv = tokio::time::timeout(CLIENT_TIMEOUT, self.rx.recv());
+            match Handle::current().block_on(recv) {
+                Ok(Some(chunk)) => self.chunk = chunk?,
+                Ok(None) => return Ok(0),
+                Err(_) => {
+                    let msg = "client stopped sending";
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+                }
+            }
+        }
+        let n = buf.len().min(self.chunk.len());
+        buf[..n].copy_from_slice(&self.chunk.split_to(n));
+        Ok(n)
+    }
+}
+
+fn append<W: Write, R: Read>(
+    ar: &mut Builder<W>,
+    path: &str,
+    size: u64,
+    data: R,
+) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_path(path)?;
+    // Without this, the file is not even readable by the user.
+    header.set_mode(0o644);
+    header.set_size(size);
+    header.set_cksum();
+    ar.append_data(&mut header, path, data)
+}
+
+fn append_posts<W: Write, P: Params>(
+    ar: &mut Builder<W>,
+    conn: &Connection,
+    stmt: &str,
+    params: P,
+) -> io::Result<()> {
+    let mut stmt = conn.prepare(stmt).map_err(io::Error::other)?;
+    let posts = stmt
+        .query_map(params, Post::from_row)
+        .map_err(io::Error::other)?;
+    for post in posts {
+        let post = post.map_err(io::Error::other)?;
         let path = format!("posts/{}.md", post.id);
-        header.set_path(&path).unwrap();
-        // Without this, the file is not even readable by the user.
-        header.set_mode(0o644);
         // Using `---` for the frontmatter because that is yaml and the GitHub
         // Markdown renderer supports it. `+++` is toml in Hugo but not
         // supported by the GitHub renderer.
+        let slug = match &post.slug {
+            Some(slug) => format!("slug: '{slug}'\n"),
+            None => String::new(),
+        };
         let content = indoc::formatdoc! {"
             ---
             created: '{}'
             updated: '{}'
-            ---
+            {slug}---
 
             {}
         ", post.created, post.updated, post.content};
         let data = content.as_bytes();
-        header.set_size(data.len() as u64);
-        header.set_cksum();
-        ar.append_data(&mut header, &path, data).unwrap();
+        append(ar, &path, data.len() as u64, data)?;
     }
+    Ok(())
+}
 
-    let mut header = Header::new_gnu();
+fn append_settings<W: Write>(ar: &mut Builder<W>, conn: &Connection) -> io::Result<()> {
     // Putting it in `settings/` to be more consistent with the other files that
     // are also put in directories.
-    let path = "settings/settings.toml";
-    header.set_path(path).unwrap();
-    header.set_mode(0o644);
-    let data = toml::to_string(&site_data.settings).unwrap();
+    let settings = Settings::from_db(conn).map_err(io::Error::other)?;
+    let data = toml::to_string(&settings).map_err(io::Error::other)?;
     let data = data.as_bytes();
-    header.set_size(data.len() as u64);
-    header.set_cksum();
-    ar.append_data(&mut header, path, data).unwrap();
+    append(ar, "settings/settings.toml", data.len() as u64, data)
+}
 
-    for file in site_data.files {
-        let mut header = Header::new_gnu();
-        let path = format!("files/{}", file.filename);
-        header.set_path(&path).unwrap();
-        header.set_mode(0o644);
-        let data = file.data.as_ref();
-        header.set_size(data.len() as u64);
-        header.set_cksum();
-        ar.append_data(&mut header, &path, data).unwrap();
+/// Append `files/index.toml` with the metadata of all files.
+fn append_file_index<W: Write>(ar: &mut Builder<W>, conn: &Connection) -> io::Result<()> {
+    let stmt = "
+        SELECT sha, mime_type, filename
+        FROM files
+        ORDER BY rowid ASC;
+    ";
+    let mut stmt = conn.prepare(stmt).map_err(io::Error::other)?;
+    let files = stmt
+        .query_map([], |row| {
+            Ok(FileIndexEntry {
+                sha: row.get("sha")?,
+                mime_type: row.get("mime_type")?,
+                filename: row.get("filename")?,
+            })
+        })
+        .map_err(io::Error::other)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(io::Error::other)?;
+    let index = FileIndex { files };
+    let data = toml::to_string(&index).map_err(io::Error::other)?;
+    let data = data.as_bytes();
+    append(ar, "files/index.toml", data.len() as u64, data)
+}
+
+/// Append the files selected by `stmt` which returns `rowid`, `sha`,
+/// `filename` and `size`.
+fn append_files<W: Write, P: Params>(
+    ar: &mut Builder<W>,
+    conn: &Connection,
+    stmt: &str,
+    params: P,
+) -> io::Result<()> {
+    let mut stmt = conn.prepare(stmt).map_err(io::Error::other)?;
+    let mut rows = stmt.query(params).map_err(io::Error::other)?;
+    while let Some(row) = rows.next().map_err(io::Error::other)? {
+        let rowid: i64 = row.get("rowid").map_err(io::Error::other)?;
+        let sha: String = row.get("sha").map_err(io::Error::other)?;
+        let filename: String = row.get("filename").map_err(io::Error::other)?;
+        let size: i64 = row.get("size").map_err(io::Error::other)?;
+        let blob = conn
+            .blob_open(MAIN_DB, "files", "data", rowid, true)
+            .map_err(io::Error::other)?;
+        let path = crate::files::archive_path(&sha, &filename);
+        append(ar, &path, size as u64, blob)?;
     }
+    Ok(())
+}
 
-    ar.into_inner().unwrap()
+/// Write the site as tar archive to `writer`.
+///
+/// Posts and files are read one row at a time and file data is streamed from
+/// the BLOB, so at most one post is held in memory.
+fn create_archive<W: Write>(conn: &Connection, writer: W) -> io::Result<W> {
+    let mut ar = Builder::new(writer);
+    let stmt = "
+        SELECT id, created, updated, content, slug
+        FROM posts
+        WHERE content != '<DELETED>'
+        ORDER BY id ASC;
+    ";
+    append_posts(&mut ar, conn, stmt, [])?;
+    append_settings(&mut ar, conn)?;
+    append_file_index(&mut ar, conn)?;
+    let stmt = "
+        SELECT rowid, sha, filename, length(data) AS size
+        FROM files
+        ORDER BY rowid ASC;
+    ";
+    append_files(&mut ar, conn, stmt, [])?;
+    ar.into_inner()
 }
 
-fn compress(data: &[u8]) -> Vec<u8> {
-    let mut compressor = XzEncoder::new(data, 6);
-    let mut compressed = Vec::new();
-    compressor.read_to_end(&mut compressed).unwrap();
-    compressed
+/// The `manifest.toml` at the root of an incremental archive.
+#[derive(Debug, Serialize)]
+struct ChangesManifest {
+    since: DateTime<Utc>,
+    /// Pass this as `since` on the next request to get the following changes.
+    until: DateTime<Utc>,
+    deleted_posts: Vec<i64>,
+    deleted_files: Vec<String>,
 }
 
-fn download_all(ctx: &ServerContext) -> Response<Body> {
-    let conn = ctx.conn();
-    let posts = Post::list(&conn);
-    let posts = if let Ok(posts) = posts {
-        posts
-    } else {
-        return error(
-            ctx,
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "failed to get posts",
-        );
-    };
-    let settings = Settings::from_db(&conn);
-    let settings = if let Ok(settings) = settings {
-        settings
-    } else {
-        return error(
-            ctx,
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "failed to get settings",
-        );
+/// Write the posts, settings and files that changed at or after `since` as
+/// tar archive to `writer`.
+///
+/// The archive has the same layout as the full archive plus a `manifest.toml`
+/// listing the posts and files that were deleted.
+fn create_changes_archive<W: Write>(
+    conn: &Connection,
+    since: DateTime<Utc>,
+    writer: W,
+) -> io::Result<W> {
+    // Changes are stored with second resolution, so round down to not miss
+    // changes that happen during the export.
+    let until = Utc::now().trunc_subsecs(0);
+    let changes = Change::since(conn, since).map_err(io::Error::other)?;
+    let deleted = |kind: Kind| {
+        changes
+            .iter()
+            .filter(move |c| c.kind == kind.as_str() && c.deleted)
+            .map(|c| c.key.clone())
     };
-    let files = File::list(&conn);
-    drop(conn);
-    let files = if let Ok(files) = files {
-        files
-    } else {
-        return error(
-            ctx,
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "failed to get files",
-        );
-    };
-    let site_data = SiteData {
-        posts: &posts,
-        settings: &settings,
-        files: &files,
+    let manifest = ChangesManifest {
+        since,
+        until,
+        deleted_posts: deleted(Kind::Post).filter_map(|k| k.parse().ok()).collect(),
+        deleted_files: deleted(Kind::File).collect(),
     };
-    let data = create_archive(&site_data);
-    let body = compress(&data);
+    let mut ar = Builder::new(writer);
+    let data = toml::to_string(&manifest).map_err(io::Error::other)?;
+    let data = data.as_bytes();
+    append(&mut ar, "manifest.toml", data.len() as u64, data)?;
+
+    let since = since.to_sqlite();
+    // Also select by `updated` for posts that were changed before the changes
+    // were logged.
+    let stmt = "
+        SELECT id, created, updated, content, slug
+        FROM posts
+        WHERE content != '<DELETED>'
+          AND (updated >= ?1 OR id IN (
+            SELECT CAST(key AS INTEGER) FROM changes
+            WHERE kind = 'post' AND time >= ?1
+          ))
+        ORDER BY id ASC;
+    ";
+    append_posts(&mut ar, conn, stmt, [&since])?;
+    let settings = Kind::Settings.as_str();
+    if changes.iter().any(|c| c.kind == settings) {
+        append_settings(&mut ar, conn)?;
+    }
+    // The index is small and always complete so that it reflects renames
+    // and deletions.
+    append_file_index(&mut ar, conn)?;
+    let stmt = "
+        SELECT files.rowid AS rowid, sha, filename, length(data) AS size
+        FROM files
+        JOIN changes ON changes.kind = 'file' AND changes.key = files.sha
+        WHERE changes.time >= ?
+        ORDER BY files.rowid ASC;
+    ";
+    append_files(&mut ar, conn, stmt, [&since])?;
+    ar.into_inner()
+}
+
+/// Stream the xz-compressed archive that `create` writes to the response body.
+fn stream_archive<F>(ctx: &ServerContext, create: F) -> Response<Body>
+where
+    F: FnOnce(&Connection, XzEncoder<BodyWriter>) -> io::Result<XzEncoder<BodyWriter>>,
+    F: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+    tokio::task::spawn_blocking({
+        let ctx = ctx.clone();
+        move || {
+            let writer = BodyWriter::new(tx.clone());
+            let write = |conn: &Connection| {
+                create(conn, XzEncoder::new(writer, 6))
+                    .and_then(|encoder| encoder.finish())
+                    .and_then(|mut writer| writer.flush())
+            };
+            let result = match crate::data::open_reader(&ctx.args) {
+                Some(conn) => write(&conn),
+                None => write(&ctx.conn()),
+            };
+            if let Err(e) = result {
+                // The status has already been sent, so the best we can do is to
+                // abort the body such that the client does not get a truncated
+                // archive.
+                tracing::error!("failed to create archive: {e}");
+                let _ = tx.blocking_send(Err(e));
+            }
+        }
+    });
+    let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    });
     let mut headers = HeaderMap::new();
     headers.insert(
         "Content-Type",
         HeaderValue::from_static("application/octet-stream"),
     );
-    response::<Vec<u8>>(StatusCode::OK, headers, body, ctx)
+    response(StatusCode::OK, headers, Body::from_stream(stream), ctx)
 }
 
 async fn get_download_all(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
     if !is_authenticated(&ctx, &headers) {
         return unauthorized(&ctx);
     }
-    tokio::task::spawn_blocking({
+    stream_archive(&ctx, create_archive)
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct ChangesQuery {
+    /// Only include changes at or after this time (RFC 3339).
+    pub since: DateTime<Utc>,
+}
+
+async fn get_download_changes(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    query: Result<Query<ChangesQuery>, QueryRejection>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let since = match query {
+        Ok(Query(query)) => query.since,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    stream_archive(&ctx, move |conn, writer| {
+        create_changes_archive(conn, since, writer)
+    })
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RestoreQuery {
+    /// Only report what would change.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+async fn restore(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Query(query): Query<RestoreQuery>,
+    body: Body,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let reader = BodyReader::new(body);
+    let result = tokio::task::spawn_blocking({
         let ctx = ctx.clone();
-        move || download_all(&ctx)
+        move || crate::restore::restore(&mut ctx.conn(), reader, query.dry_run)
     })
     .await
-    .unwrap()
+    .unwrap();
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e),
+    };
+    let changed = !report.posts_created.is_empty()
+        || !report.posts_updated.is_empty()
+        || report.settings_changed
+        || !report.files_added.is_empty();
+    if !query.dry_run && changed {
+        tracing::info!("restored archive: {report:?}");
+        crate::trigger::trigger_github_backup(&ctx).await;
+    }
+    let body = serde_json::to_string(&report).unwrap();
+    response_json(StatusCode::OK, body, &ctx)
 }
 
 async fn update_about(
@@ -224,10 +583,795 @@ async fn update_about(
     response_json(StatusCode::OK, "ok", &ctx)
 }
 
+fn wants_toml(headers: &HeaderMap, header: &str) -> bool {
+    headers
+        .get(header)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("toml"))
+}
+
+fn settings_response(ctx: &ServerContext, settings: &Settings, toml: bool) -> Response<Body> {
+    if toml {
+        let mut headers = HeaderMap::new();
+        headers.insert("Content-Type", HeaderValue::from_static("application/toml"));
+        let body = toml::to_string(settings).unwrap();
+        response(StatusCode::OK, headers, body, ctx)
+    } else {
+        let body = serde_json::to_string(settings).unwrap();
+        response_json(StatusCode::OK, body, ctx)
+    }
+}
+
+/// Apply the fields in the body to the current settings.
+fn merge_settings(current: &Settings, body: &str, toml: bool) -> Result<Settings, String> {
+    let patch = if toml {
+        let value =
+            toml::from_str::<toml::Value>(body).map_err(|e| format!("invalid TOML: {e}"))?;
+        serde_json::to_value(value).unwrap()
+    } else {
+        serde_json::from_str::<serde_json::Value>(body).map_err(|e| format!("invalid JSON: {e}"))?
+    };
+    let patch = match patch {
+        serde_json::Value::Object(patch) => patch,
+        _ => return Err("expected an object".to_string()),
+    };
+    let mut settings = match serde_json::to_value(current).unwrap() {
+        serde_json::Value::Object(settings) => settings,
+        _ => unreachable!(),
+    };
+    for (key, value) in patch {
+        if !settings.contains_key(&key) {
+            return Err(format!("unknown setting: {key}"));
+        }
+        settings.insert(key, value);
+    }
+    serde_json::from_value(serde_json::Value::Object(settings)).map_err(|e| e.to_string())
+}
+
+async fn get_settings(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    match Settings::from_db(&ctx.conn()) {
+        Ok(settings) => settings_response(&ctx, &settings, wants_toml(&headers, "Accept")),
+        Err(e) => internal_error(&ctx, "failed to get settings", e),
+    }
+}
+
+async fn update_settings(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let current = match Settings::from_db(&ctx.conn()) {
+        Ok(settings) => settings,
+        Err(e) => return internal_error(&ctx, "failed to get settings", e),
+    };
+    let settings = match merge_settings(&current, &body, wants_toml(&headers, "Content-Type")) {
+        Ok(settings) => settings.normalize(),
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e),
+    };
+    if let Err(e) = settings.validate() {
+        return error(&ctx, StatusCode::BAD_REQUEST, &e);
+    }
+    let toml = wants_toml(&headers, "Accept");
+    // Avoid update and backup trigger when no change to avoid infinite loop.
+    if settings == current.clone().normalize() {
+        tracing::info!("ignoring settings update because no change");
+        return settings_response(&ctx, &current, toml);
+    }
+    if let Err(e) = settings.save(&ctx.conn()) {
+        return internal_error(&ctx, "failed to update settings", e);
+    }
+    if settings.blogroll_feeds != current.blogroll_feeds {
+        let ctx = ctx.clone();
+        tokio::spawn(async move { crate::settings::update_feeds(&ctx).await });
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    settings_response(&ctx, &settings, toml)
+}
+
+/// A post as returned by the API.
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct PostResponse {
+    pub id: i64,
+    /// The Markdown source.
+    pub content: String,
+    pub created: String,
+    pub updated: String,
+    pub slug: String,
+    pub url: String,
+    /// The content rendered to HTML.
+    pub html: String,
+}
+
+impl PostResponse {
+    fn new(ctx: &ServerContext, post: &Post) -> Self {
+        let slug = crate::md::extract_slug(post);
+        let url = format!("{}{}", ctx.base_url(), crate::html::post_link(post, &slug));
+        Self {
+            id: post.id,
+            content: post.content.clone(),
+            created: crate::serve::iso8601(&post.created),
+            updated: crate::serve::iso8601(&post.updated),
+            slug,
+            url,
+            html: crate::md::content_to_html(post.body()),
+        }
+    }
+}
+
+/// Returned when an update is based on an outdated version of the post.
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct ConflictResponse {
+    pub status: u16,
+    pub message: String,
+    /// The saved version of the post.
+    pub current: PostResponse,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct PostListResponse {
+    pub posts: Vec<PostResponse>,
+    pub page: i64,
+    pub per_page: i64,
+    /// The total number of posts over all pages.
+    pub total: i64,
+}
+
+/// The body to create or update a post.
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct PostRequest {
+    pub content: String,
+    /// Defaults to now for new posts and is unchanged for updates.
+    pub created: Option<DateTime<Utc>>,
+    /// For updates, the `updated` timestamp of the post on which the edit is
+    /// based. An alternative to the `If-Match` header.
+    pub updated: Option<DateTime<Utc>>,
+    /// The slug in the URL, which stays the same when the title changes.
+    /// Unchanged for updates when omitted and derived from the title when
+    /// empty.
+    pub slug: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Pagination {
+    /// The page number, starting at 1.
+    pub page: Option<i64>,
+    /// The number of posts per page, at most 100.
+    pub per_page: Option<i64>,
+}
+
+fn internal_error(ctx: &ServerContext, msg: &str, e: rusqlite::Error) -> Response<Body> {
+    tracing::error!("{msg}: {e}");
+    error(ctx, StatusCode::INTERNAL_SERVER_ERROR, msg)
+}
+
+fn not_found(ctx: &ServerContext) -> Response<Body> {
+    error(ctx, StatusCode::NOT_FOUND, "post not found")
+}
+
+fn get_existing_post(ctx: &ServerContext, id: i64) -> Option<Post> {
+    Post::get(&ctx.conn(), id)
+        .ok()
+        .filter(|post| post.content != "<DELETED>")
+}
+
+fn post_response(ctx: &ServerContext, status: StatusCode, post: &Post) -> Response<Body> {
+    let body = PostResponse::new(ctx, post);
+    let mut headers = HeaderMap::new();
+    if status == StatusCode::CREATED {
+        headers.insert("Location", HeaderValue::from_str(&body.url).unwrap());
+    }
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+    let etag = format!("\"{}\"", post.version());
+    headers.insert("ETag", HeaderValue::from_str(&etag).unwrap());
+    let body = serde_json::to_string(&body).unwrap();
+    response(status, headers, body, ctx)
+}
+
+/// Return the reason why the update is based on an outdated version, if any.
+fn outdated(headers: &HeaderMap, request: &PostRequest, post: &Post) -> Option<StatusCode> {
+    if let Some(if_match) = headers.get("If-Match") {
+        let matches = if_match.to_str().unwrap_or("").split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/").trim_matches('"') == post.version()
+        });
+        if !matches {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+    if let Some(updated) = request.updated
+        && updated.timestamp() != post.updated.timestamp()
+    {
+        return Some(StatusCode::CONFLICT);
+    }
+    None
+}
+
+/// Return the saved version so that the client can show both versions.
+fn conflict(ctx: &ServerContext, status: StatusCode, post: &Post) -> Response<Body> {
+    let body = ConflictResponse {
+        status: status.as_u16(),
+        message: "post was changed since the given version".to_string(),
+        current: PostResponse::new(ctx, post),
+    };
+    let body = serde_json::to_string(&body).unwrap();
+    response_json(status, body, ctx)
+}
+
+fn parse_post_request(body: &str) -> Result<PostRequest, String> {
+    let request =
+        serde_json::from_str::<PostRequest>(body).map_err(|e| format!("invalid body: {e}"))?;
+    if request.content.trim().is_empty() {
+        return Err("content is empty".to_string());
+    }
+    Ok(request)
+}
+
+async fn list_posts(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Query(pagination): Query<Pagination>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let page = pagination.page.unwrap_or(1).max(1);
+    let per_page = pagination.per_page.unwrap_or(20).clamp(1, 100);
+    let conn = ctx.conn();
+    let posts = match Post::list_page(&conn, per_page, (page - 1) * per_page) {
+        Ok(posts) => posts,
+        Err(e) => return internal_error(&ctx, "failed to get posts", e),
+    };
+    let total = match Post::count(&conn) {
+        Ok(total) => total,
+        Err(e) => return internal_error(&ctx, "failed to count posts", e),
+    };
+    drop(conn);
+    let body = PostListResponse {
+        posts: posts
+            .iter()
+            .map(|post| PostResponse::new(&ctx, post))
+            .collect(),
+        page,
+        per_page,
+        total,
+    };
+    let body = serde_json::to_string(&body).unwrap();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+async fn get_post(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let post = match get_existing_post(&ctx, id) {
+        Some(post) => post,
+        None => return not_found(&ctx),
+    };
+    post_response(&ctx, StatusCode::OK, &post)
+}
+
+async fn create_post(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let request = match parse_post_request(&body) {
+        Ok(request) => request,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e),
+    };
+    let now = Utc::now();
+    let created = request.created.unwrap_or(now);
+    let slug = request.slug.as_deref().and_then(crate::md::normalize_slug);
+    let id = match Post::insert(&ctx.conn(), created, now, &request.content, slug.as_deref()) {
+        Ok(id) => id,
+        Err(e) => return internal_error(&ctx, "failed to insert post", e),
+    };
+    crate::trigger::trigger_github_backup(&ctx).await;
+    crate::ap::send_create(&ctx, id).await;
+    crate::webmention::send_mentions(&ctx, id, None).await;
+    let post = match get_existing_post(&ctx, id) {
+        Some(post) => post,
+        None => return not_found(&ctx),
+    };
+    post_response(&ctx, StatusCode::CREATED, &post)
+}
+
+async fn update_post(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+    body: String,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let request = match parse_post_request(&body) {
+        Ok(request) => request,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e),
+    };
+    let mut post = match get_existing_post(&ctx, id) {
+        Some(post) => post,
+        None => return not_found(&ctx),
+    };
+    if let Some(status) = outdated(&headers, &request, &post) {
+        return conflict(&ctx, status, &post);
+    }
+    let previous = post.clone();
+    post.content = request.content;
+    post.created = request.created.unwrap_or(post.created);
+    post.updated = Utc::now();
+    if let Some(slug) = &request.slug {
+        post.slug = crate::md::normalize_slug(slug);
+    }
+    // Checked again in the update since another edit may have been saved
+    // after the check above.
+    match post.update_if_unchanged(&ctx.conn(), &previous) {
+        Ok(0) => {
+            return match get_existing_post(&ctx, id) {
+                Some(saved) => conflict(&ctx, StatusCode::PRECONDITION_FAILED, &saved),
+                None => not_found(&ctx),
+            };
+        }
+        Ok(_) => (),
+        Err(e) => return internal_error(&ctx, "failed to update post", e),
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    crate::ap::send_update(&ctx, id).await;
+    crate::webmention::send_mentions(&ctx, id, Some(&previous.content)).await;
+    let post = match get_existing_post(&ctx, id) {
+        Some(post) => post,
+        None => return not_found(&ctx),
+    };
+    post_response(&ctx, StatusCode::OK, &post)
+}
+
+async fn delete_post(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    if get_existing_post(&ctx, id).is_none() {
+        return not_found(&ctx);
+    }
+    if let Err(e) = Post::delete(&ctx.conn(), id) {
+        return internal_error(&ctx, "failed to delete post", e);
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    crate::ap::send_delete(&ctx, id).await;
+    response(StatusCode::NO_CONTENT, HeaderMap::new(), "", &ctx)
+}
+
+/// A file as returned by the API, without its data.
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct FileResponse {
+    #[serde(flatten)]
+    pub info: FileInfo,
+    pub url: String,
+    /// The Markdown link to paste into a post.
+    pub md_link: String,
+}
+
+impl FileResponse {
+    fn new(ctx: &ServerContext, info: FileInfo) -> Self {
+        Self {
+            url: format!("{}{}", ctx.base_url(), info.path()),
+            md_link: info.md_link(),
+            info,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct UploadQuery {
+    /// Required for raw body uploads and overrides the multipart filename.
+    pub filename: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RenameRequest {
+    pub filename: String,
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct FileListResponse {
+    pub files: Vec<FileResponse>,
+}
+
+fn file_not_found(ctx: &ServerContext) -> Response<Body> {
+    error(ctx, StatusCode::NOT_FOUND, "file not found")
+}
+
+fn file_response(ctx: &ServerContext, status: StatusCode, sha: &str) -> Response<Body> {
+    let info = match FileInfo::get(&ctx.conn(), sha) {
+        Ok(info) => info,
+        Err(_) => return file_not_found(ctx),
+    };
+    let file = FileResponse::new(ctx, info);
+    let mut headers = HeaderMap::new();
+    if status == StatusCode::CREATED {
+        headers.insert("Location", HeaderValue::from_str(&file.url).unwrap());
+    }
+    headers.insert("Content-Type", HeaderValue::from_static("application/json"));
+    let body = serde_json::to_string(&file).unwrap();
+    response(status, headers, body, ctx)
+}
+
+async fn read_multipart(mut multipart: Multipart) -> Result<Option<File>, String> {
+    while let Some(field) = multipart.next_field().await.map_err(|e| e.to_string())? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let filename = field.file_name().unwrap_or("upload").to_string();
+        let mime_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let data = field
+            .bytes()
+            .await
+            .map_err(|e| format!("failed to read file: {e}"))?;
+        return Ok(Some(File::new(&mime_type, &filename, data)));
+    }
+    Ok(None)
+}
+
+async fn list_files(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let files = match FileInfo::list(&ctx.conn()) {
+        Ok(files) => files,
+        Err(e) => return internal_error(&ctx, "failed to get files", e),
+    };
+    let files = files
+        .into_iter()
+        .map(|info| FileResponse::new(&ctx, info))
+        .collect::<Vec<_>>();
+    let body = serde_json::to_string(&FileListResponse { files }).unwrap();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+async fn upload_file(
+    State(ctx): State<ServerContext>,
+    Query(query): Query<UploadQuery>,
+    req: Request,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, req.headers()) {
+        return unauthorized(&ctx);
+    }
+    let content_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    let file = if content_type.starts_with("multipart/form-data") {
+        let multipart = match Multipart::from_request(req, &ctx).await {
+            Ok(multipart) => multipart,
+            Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e.body_text()),
+        };
+        match read_multipart(multipart).await {
+            Ok(Some(file)) => file,
+            Ok(None) => return error(&ctx, StatusCode::BAD_REQUEST, "missing file field"),
+            Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e),
+        }
+    } else {
+        let filename = match &query.filename {
+            Some(filename) => filename.clone(),
+            None => return error(&ctx, StatusCode::BAD_REQUEST, "missing filename"),
+        };
+        let data = match Bytes::from_request(req, &ctx).await {
+            Ok(data) => data,
+            Err(e) => return error(&ctx, e.status(), &e.body_text()),
+        };
+        File::new(&content_type, &filename, data)
+    };
+    let file = match query.filename {
+        Some(filename) if filename != file.filename => {
+            File::new(&file.mime_type, &filename, file.data)
+        }
+        _ => file,
+    };
+    if file.filename.trim().is_empty() {
+        return error(&ctx, StatusCode::BAD_REQUEST, "filename is empty");
+    }
+    if let Err(e) = File::insert(&ctx.conn(), &file) {
+        return internal_error(&ctx, "failed to insert file", e);
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    file_response(&ctx, StatusCode::CREATED, &file.sha)
+}
+
+async fn rename_file(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Path(sha): Path<String>,
+    body: String,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let request = match serde_json::from_str::<RenameRequest>(&body) {
+        Ok(request) => request,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &format!("invalid body: {e}")),
+    };
+    if request.filename.trim().is_empty() {
+        return error(&ctx, StatusCode::BAD_REQUEST, "filename is empty");
+    }
+    match File::rename(&ctx.conn(), &sha, &request.filename) {
+        Ok(0) => return file_not_found(&ctx),
+        Ok(_) => (),
+        Err(e) => return internal_error(&ctx, "failed to rename file", e),
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    file_response(&ctx, StatusCode::OK, &sha)
+}
+
+async fn delete_file(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Path(sha): Path<String>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    match File::delete(&ctx.conn(), &sha) {
+        Ok(0) => return file_not_found(&ctx),
+        Ok(_) => (),
+        Err(e) => return internal_error(&ctx, "failed to delete file", e),
+    }
+    crate::trigger::trigger_github_backup(&ctx).await;
+    response(StatusCode::NO_CONTENT, HeaderMap::new(), "", &ctx)
+}
+
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct RedirectListResponse {
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct RedirectQuery {
+    /// The path that is redirected.
+    pub from: String,
+}
+
+async fn list_redirects(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let redirects = match Redirect::list(&ctx.conn()) {
+        Ok(redirects) => redirects,
+        Err(e) => return internal_error(&ctx, "failed to get redirects", e),
+    };
+    let body = serde_json::to_string(&RedirectListResponse { redirects }).unwrap();
+    response_json(StatusCode::OK, body, &ctx)
+}
+
+async fn create_redirect(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let request = match serde_json::from_str::<Redirect>(&body) {
+        Ok(request) => request,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &format!("invalid body: {e}")),
+    };
+    let redirect = match Redirect::new(&request.from, &request.to, request.status) {
+        Ok(redirect) => redirect,
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e),
+    };
+    if let Err(e) = Redirect::insert(&ctx.conn(), &redirect) {
+        return internal_error(&ctx, "failed to insert redirect", e);
+    }
+    let body = serde_json::to_string(&redirect).unwrap();
+    response_json(StatusCode::CREATED, body, &ctx)
+}
+
+async fn delete_redirect(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    query: Result<Query<RedirectQuery>, QueryRejection>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers) {
+        return unauthorized(&ctx);
+    }
+    let from = match query {
+        Ok(Query(query)) => crate::redirects::normalize_path(&query.from),
+        Err(e) => return error(&ctx, StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    match Redirect::delete(&ctx.conn(), &from) {
+        Ok(0) => error(&ctx, StatusCode::NOT_FOUND, "redirect not found"),
+        Ok(_) => response(StatusCode::NO_CONTENT, HeaderMap::new(), "", &ctx),
+        Err(e) => internal_error(&ctx, "failed to delete redirect", e),
+    }
+}
+
+fn authenticated<H, T>(
+    method: Method,
+    path: &'static str,
+    summary: &'static str,
+    handler: H,
+) -> Endpoint
+where
+    H: Handler<T, ServerContext>,
+    T: 'static,
+{
+    Endpoint::new(method, path, summary, handler)
+        .authenticated()
+        .response(StatusCode::UNAUTHORIZED, Content::json::<ErrorResponse>())
+}
+
+fn endpoints() -> Vec<Endpoint> {
+    let error = Content::json::<ErrorResponse>;
+    vec![
+        Endpoint::new(Method::GET, "/api", "Links to the API resources", get_api)
+            .response(StatusCode::OK, Content::json::<ApiResponse>()),
+        Endpoint::new(
+            Method::GET,
+            "/api/openapi.json",
+            "This OpenAPI document",
+            get_openapi,
+        )
+        .response(StatusCode::OK, Content::Other("application/json")),
+        authenticated(
+            Method::GET,
+            "/api/download/all.tar.xz",
+            "Download all posts, settings and files as archive",
+            get_download_all,
+        )
+        .response(StatusCode::OK, Content::Other("application/octet-stream")),
+        authenticated(
+            Method::GET,
+            "/api/download/changes.tar.xz",
+            "Download the posts, settings and files that changed since a time",
+            get_download_changes,
+        )
+        .query::<ChangesQuery>()
+        .response(StatusCode::OK, Content::Other("application/octet-stream"))
+        .response(StatusCode::BAD_REQUEST, error()),
+        authenticated(
+            Method::POST,
+            "/api/restore",
+            "Restore posts, settings and files from an all.tar.xz archive",
+            restore,
+        )
+        .query::<RestoreQuery>()
+        .request(Content::Other("application/octet-stream"))
+        .response(StatusCode::OK, Content::json::<RestoreReport>())
+        .response(StatusCode::BAD_REQUEST, error()),
+        authenticated(
+            Method::GET,
+            "/api/settings",
+            "Get all settings",
+            get_settings,
+        )
+        .response(StatusCode::OK, Content::json::<Settings>())
+        .response(StatusCode::OK, Content::Other("application/toml")),
+        authenticated(
+            Method::PATCH,
+            "/api/settings",
+            "Change the given settings; the other settings are unchanged",
+            update_settings,
+        )
+        .request(Content::json::<Settings>())
+        .request(Content::Other("application/toml"))
+        .response(StatusCode::OK, Content::json::<Settings>())
+        .response(StatusCode::OK, Content::Other("application/toml"))
+        .response(StatusCode::BAD_REQUEST, error()),
+        authenticated(
+            Method::PUT,
+            "/api/settings/about",
+            "Set the about text",
+            update_about,
+        )
+        .request(Content::Other("text/plain"))
+        .response(StatusCode::OK, Content::json::<String>()),
+        authenticated(Method::GET, "/api/posts", "List posts", list_posts)
+            .query::<Pagination>()
+            .response(StatusCode::OK, Content::json::<PostListResponse>()),
+        authenticated(Method::POST, "/api/posts", "Create a post", create_post)
+            .request(Content::json::<PostRequest>())
+            .response(StatusCode::CREATED, Content::json::<PostResponse>())
+            .response(StatusCode::BAD_REQUEST, error()),
+        authenticated(Method::GET, "/api/posts/{id}", "Get a post", get_post)
+            .response(StatusCode::OK, Content::json::<PostResponse>())
+            .response(StatusCode::NOT_FOUND, error()),
+        authenticated(Method::PUT, "/api/posts/{id}", "Update a post", update_post)
+            .request(Content::json::<PostRequest>())
+            .response(StatusCode::OK, Content::json::<PostResponse>())
+            .response(StatusCode::BAD_REQUEST, error())
+            .response(StatusCode::NOT_FOUND, error())
+            .response(StatusCode::CONFLICT, Content::json::<ConflictResponse>())
+            .response(
+                StatusCode::PRECONDITION_FAILED,
+                Content::json::<ConflictResponse>(),
+            ),
+        authenticated(
+            Method::DELETE,
+            "/api/posts/{id}",
+            "Delete a post",
+            delete_post,
+        )
+        .response(StatusCode::NO_CONTENT, Content::Empty)
+        .response(StatusCode::NOT_FOUND, error()),
+        authenticated(Method::GET, "/api/files", "List files", list_files)
+            .response(StatusCode::OK, Content::json::<FileListResponse>()),
+        authenticated(Method::POST, "/api/files", "Upload a file", upload_file)
+            .query::<UploadQuery>()
+            .request(Content::Other("multipart/form-data"))
+            .request(Content::Other("application/octet-stream"))
+            .response(StatusCode::CREATED, Content::json::<FileResponse>())
+            .response(StatusCode::BAD_REQUEST, error()),
+        authenticated(
+            Method::PATCH,
+            "/api/files/{sha}",
+            "Rename a file",
+            rename_file,
+        )
+        .request(Content::json::<RenameRequest>())
+        .response(StatusCode::OK, Content::json::<FileResponse>())
+        .response(StatusCode::BAD_REQUEST, error())
+        .response(StatusCode::NOT_FOUND, error()),
+        authenticated(
+            Method::DELETE,
+            "/api/files/{sha}",
+            "Delete a file",
+            delete_file,
+        )
+        .response(StatusCode::NO_CONTENT, Content::Empty)
+        .response(StatusCode::NOT_FOUND, error()),
+        authenticated(
+            Method::GET,
+            "/api/redirects",
+            "List redirects",
+            list_redirects,
+        )
+        .response(StatusCode::OK, Content::json::<RedirectListResponse>()),
+        authenticated(
+            Method::POST,
+            "/api/redirects",
+            "Create a redirect or replace the one with the same from",
+            create_redirect,
+        )
+        .request(Content::json::<Redirect>())
+        .response(StatusCode::CREATED, Content::json::<Redirect>())
+        .response(StatusCode::BAD_REQUEST, error()),
+        authenticated(
+            Method::DELETE,
+            "/api/redirects",
+            "Delete a redirect",
+            delete_redirect,
+        )
+        .query::<RedirectQuery>()
+        .response(StatusCode::NO_CONTENT, Content::Empty)
+        .response(StatusCode::BAD_REQUEST, error())
+        .response(StatusCode::NOT_FOUND, error()),
+    ]
+}
+
 pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
-    router
-        .clone()
-        .route("/api", get(get_api))
-        .route("/api/download/all.tar.xz", get(get_download_all))
-        .route("/api/settings/about", put(update_about))
+    endpoints()
+        .into_iter()
+        .fold(router.clone(), |router, endpoint| {
+            router.route(endpoint.path, endpoint.handler)
+        })
 }
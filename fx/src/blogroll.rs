@@ -90,7 +90,7 @@ impl BlogCache {
             .filter(|item| item.pub_date.is_some())
             .collect::<Vec<_>>();
         filter_old_items(&mut items);
-        items.sort_by(|a, b| b.pub_date.cmp(&a.pub_date));
+        items.sort_by_key(|item| std::cmp::Reverse(item.pub_date));
         self.items = items.into_iter().cloned().collect::<Vec<_>>();
         self.last_updated = Utc::now();
     }
@@ -115,7 +115,7 @@ async fn get_blogroll(State(ctx): State<ServerContext>, jar: CookieJar) -> Respo
         .iter()
         .filter(|item| item.pub_date.is_some())
         .collect::<Vec<_>>();
-    items.sort_by(|a, b| b.pub_date.cmp(&a.pub_date));
+    items.sort_by_key(|item| std::cmp::Reverse(item.pub_date));
     let items = items
         .iter()
         .filter_map(|item| show_item(item))
@@ -0,0 +1,100 @@
+//! Log of changes to posts, files and settings for incremental backups.
+//!
+//! Files have no timestamps and are removed from the database when deleted, so
+//! the only way to know what changed since a backup is to record it.
+use crate::data::SqliteDateTime;
+use chrono::DateTime;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::Result;
+use rusqlite::params;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Post,
+    File,
+    Settings,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Post => "post",
+            Kind::File => "file",
+            Kind::Settings => "settings",
+        }
+    }
+}
+
+/// The latest change to an item.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    pub kind: String,
+    /// The post id, file sha or `settings`.
+    pub key: String,
+    pub deleted: bool,
+    pub time: DateTime<Utc>,
+}
+
+impl Change {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS changes (
+                kind TEXT NOT NULL,
+                key TEXT NOT NULL,
+                deleted INTEGER NOT NULL,
+                time DATETIME NOT NULL,
+                PRIMARY KEY (kind, key)
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Record that an item was changed or deleted.
+    ///
+    /// Only the latest change per item is kept, so the table does not grow
+    /// with the number of edits.
+    pub fn record(conn: &Connection, kind: Kind, key: &str, deleted: bool) -> Result<usize> {
+        let stmt = "
+            INSERT OR REPLACE INTO changes (kind, key, deleted, time)
+            VALUES (?, ?, ?, ?);
+        ";
+        let time = Utc::now().to_sqlite();
+        conn.execute(stmt, params![kind.as_str(), key, deleted, time])
+    }
+    /// Return the items that were changed at or after `since`.
+    pub fn since(conn: &Connection, since: DateTime<Utc>) -> Result<Vec<Change>> {
+        let stmt = "
+            SELECT kind, key, deleted, time
+            FROM changes
+            WHERE time >= ?
+            ORDER BY time ASC;
+        ";
+        let mut stmt = conn.prepare(stmt)?;
+        let changes = stmt.query_map([since.to_sqlite()], |row| {
+            let time: String = row.get("time")?;
+            Ok(Change {
+                kind: row.get("kind")?,
+                key: row.get("key")?,
+                deleted: row.get("deleted")?,
+                time: DateTime::from_sqlite(&time),
+            })
+        })?;
+        changes.collect()
+    }
+}
+
+#[test]
+fn test_record() {
+    let conn = Connection::open_in_memory().unwrap();
+    Change::create_table(&conn).unwrap();
+    let before = Utc::now() - chrono::Duration::seconds(1);
+    Change::record(&conn, Kind::File, "abc", false).unwrap();
+    Change::record(&conn, Kind::File, "abc", true).unwrap();
+    Change::record(&conn, Kind::Post, "1", false).unwrap();
+    let changes = Change::since(&conn, before).unwrap();
+    assert_eq!(changes.len(), 2);
+    let file = changes.iter().find(|c| c.kind == "file").unwrap();
+    assert!(file.deleted);
+    let later = Utc::now() + chrono::Duration::seconds(2);
+    assert!(Change::since(&conn, later).unwrap().is_empty());
+}
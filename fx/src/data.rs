@@ -1,5 +1,16 @@
 use crate::ServeArgs;
+use crate::ap::Follower;
+use crate::changes::Change;
+use crate::changes::Kind;
+use crate::delivery::Delivery;
 use crate::files::File;
+use crate::front_matter::FrontMatter;
+use crate::indieauth::AuthCode;
+use crate::indieauth::Token;
+use crate::redirects::Redirect;
+use crate::replies::BlockedDomain;
+use crate::replies::Reaction;
+use crate::replies::Reply;
 use bytes::Bytes;
 use chrono::DateTime;
 use chrono::NaiveDateTime;
@@ -8,7 +19,10 @@ use chrono::Utc;
 use r2d2::Pool;
 use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::Connection;
+use rusqlite::OpenFlags;
 use rusqlite::Result;
+use rusqlite::params;
+use sha2::Digest;
 
 pub trait SqliteDateTime {
     const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
@@ -92,6 +106,16 @@ pub struct Post {
     pub updated: chrono::DateTime<chrono::Utc>,
     /// The content of the post.
     pub content: String,
+    /// The slug set by the author, which overrides the one from the title.
+    pub slug: Option<String>,
+}
+
+/// Return the `created` from the front matter of the content, if any.
+fn backdated(content: &str, created: DateTime<Utc>) -> DateTime<Utc> {
+    crate::front_matter::split(content)
+        .0
+        .created
+        .unwrap_or(created)
 }
 
 /// Cleanup user-provided content before storing it.
@@ -113,90 +137,195 @@ impl Post {
                 id INTEGER PRIMARY KEY AUTOINCREMENT,
                 created DATETIME NOT NULL,
                 updated DATETIME NOT NULL,
-                content TEXT NOT NULL
+                content TEXT NOT NULL,
+                slug TEXT
             );
         ";
         conn.execute(stmt, [])
     }
+    /// Add the `slug` column to databases from before slugs were stored.
+    fn add_slug_column(conn: &Connection) -> Result<()> {
+        let stmt = "SELECT 1 FROM pragma_table_info('posts') WHERE name = 'slug'";
+        if !conn.prepare(stmt)?.exists([])? {
+            conn.execute("ALTER TABLE posts ADD COLUMN slug TEXT", [])?;
+        }
+        Ok(())
+    }
     pub fn insert(
         conn: &Connection,
         created: DateTime<Utc>,
         updated: DateTime<Utc>,
         content: &str,
+        slug: Option<&str>,
     ) -> Result<i64> {
         let stmt = "
-            INSERT INTO posts (created, updated, content)
-            VALUES (?, ?, ?);
+            INSERT INTO posts (created, updated, content, slug)
+            VALUES (?, ?, ?, ?);
         ";
-        let created = created.to_sqlite();
-        let updated = updated.to_sqlite();
         let content = cleanup_content(content);
-        conn.execute(stmt, [created, updated, content])?;
+        let created = backdated(&content, created).to_sqlite();
+        let updated = updated.to_sqlite();
+        conn.execute(stmt, params![created, updated, content, slug])?;
         let id = conn.last_insert_rowid();
+        Change::record(conn, Kind::Post, &id.to_string(), false)?;
         Ok(id)
     }
     pub fn list(conn: &Connection) -> Result<Vec<Post>> {
         let stmt = "
-            SELECT id, created, updated, content
+            SELECT id, created, updated, content, slug
             FROM posts
             WHERE content != '<DELETED>'
             ORDER BY created DESC;
         ";
         let posts = conn
             .prepare(stmt)?
-            .query_map([], |row| {
-                let created_str: String = row.get("created")?;
-                let created = DateTime::from_sqlite(&created_str);
-                let updated_str: String = row.get("updated")?;
-                let updated = DateTime::from_sqlite(&updated_str);
-                let content = row.get("content")?;
-                Ok(Post {
-                    id: row.get("id")?,
-                    created,
-                    updated,
-                    content,
-                })
-            })?
+            .query_map([], Post::from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(posts)
+    }
+    /// Read a post from a row with `id`, `created`, `updated`, `content` and
+    /// `slug`.
+    pub fn from_row(row: &rusqlite::Row) -> Result<Post> {
+        let created_str: String = row.get("created")?;
+        let created = DateTime::from_sqlite(&created_str);
+        let updated_str: String = row.get("updated")?;
+        let updated = DateTime::from_sqlite(&updated_str);
+        Ok(Post {
+            id: row.get("id")?,
+            created,
+            updated,
+            content: row.get("content")?,
+            slug: row.get("slug")?,
+        })
+    }
+    /// Return the front matter at the start of the content, if any.
+    pub fn front_matter(&self) -> FrontMatter {
+        crate::front_matter::split(&self.content).0
+    }
+    /// Return the content without the front matter.
+    pub fn body(&self) -> &str {
+        crate::front_matter::split(&self.content).1
+    }
+    /// Return a short hash that changes whenever the post is saved.
+    ///
+    /// Used as ETag and in the edit form to detect conflicting edits. The
+    /// content is included because `updated` only has second resolution.
+    pub fn version(&self) -> String {
+        let data = format!("{}\n{}", self.updated.to_sqlite(), self.content);
+        let digest = sha2::Sha256::digest(data.as_bytes());
+        hex::encode(&digest[..8])
+    }
+    /// Return one page of posts, newest first.
+    pub fn list_page(conn: &Connection, limit: i64, offset: i64) -> Result<Vec<Post>> {
+        let stmt = "
+            SELECT id, created, updated, content, slug
+            FROM posts
+            WHERE content != '<DELETED>'
+            ORDER BY created DESC, id DESC
+            LIMIT ? OFFSET ?;
+        ";
+        let posts = conn
+            .prepare(stmt)?
+            .query_map([limit, offset], Post::from_row)?
             .collect::<Result<Vec<_>, _>>()?;
         Ok(posts)
     }
+    pub fn count(conn: &Connection) -> Result<i64> {
+        let stmt = "SELECT COUNT(*) FROM posts WHERE content != '<DELETED>'";
+        conn.query_row(stmt, [], |row| row.get(0))
+    }
     pub fn get(conn: &Connection, id: i64) -> Result<Post> {
         let stmt = "
-            SELECT id, created, updated, content FROM posts WHERE id = ?;
+            SELECT id, created, updated, content, slug FROM posts WHERE id = ?;
         ";
-        conn.prepare(stmt)?.query_row([id], |row| {
-            let created_str: String = row.get("created")?;
-            let created = DateTime::from_sqlite(&created_str);
-            let updated_str: String = row.get("updated")?;
-            let updated = DateTime::from_sqlite(&updated_str);
-            let content = row.get("content")?;
-            Ok(Post {
-                id: row.get("id")?,
-                created,
-                updated,
-                content,
-            })
-        })
+        conn.prepare(stmt)?.query_row([id], Post::from_row)
     }
     pub fn update(&self, conn: &Connection) -> Result<usize> {
         let stmt = "
-            UPDATE posts SET created = ?, updated = ?, content = ?
+            UPDATE posts SET created = ?, updated = ?, content = ?, slug = ?
             WHERE id = ?;
         ";
-        let created = self.created.to_sqlite();
+        let content = cleanup_content(&self.content);
+        let created = backdated(&content, self.created).to_sqlite();
         let updated = self.updated.to_sqlite();
+        let id = self.id.to_string();
+        let n = conn.execute(stmt, params![created, updated, content, self.slug, id])?;
+        Change::record(conn, Kind::Post, &id, false)?;
+        Ok(n)
+    }
+    /// Update the post unless it was changed since `previous` was read.
+    ///
+    /// Returns the number of updated rows, so 0 means that another edit came
+    /// first.
+    pub fn update_if_unchanged(&self, conn: &Connection, previous: &Post) -> Result<usize> {
+        let stmt = "
+            UPDATE posts SET created = ?, updated = ?, content = ?, slug = ?
+            WHERE id = ? AND updated = ? AND content = ?;
+        ";
         let content = cleanup_content(&self.content);
+        let created = backdated(&content, self.created).to_sqlite();
+        let updated = self.updated.to_sqlite();
         let id = self.id.to_string();
-        conn.execute(stmt, [created, updated, content, id])
+        let params = params![
+            created,
+            updated,
+            content,
+            self.slug,
+            id,
+            previous.updated.to_sqlite(),
+            previous.content
+        ];
+        let n = conn.execute(stmt, params)?;
+        if 0 < n {
+            Change::record(conn, Kind::Post, &id, false)?;
+        }
+        Ok(n)
+    }
+    /// Insert or replace the post while keeping its id.
+    pub fn upsert(conn: &Connection, post: &Post) -> Result<usize> {
+        let stmt = "
+            INSERT OR REPLACE INTO posts (id, created, updated, content, slug)
+            VALUES (?, ?, ?, ?, ?);
+        ";
+        let created = post.created.to_sqlite();
+        let updated = post.updated.to_sqlite();
+        let content = cleanup_content(&post.content);
+        let params = params![post.id, created, updated, content, post.slug];
+        let n = conn.execute(stmt, params)?;
+        Change::record(conn, Kind::Post, &post.id.to_string(), false)?;
+        Ok(n)
     }
     pub fn delete(conn: &Connection, id: i64) -> Result<usize> {
         let stmt = "UPDATE posts SET content = '<DELETED>' WHERE id = ?";
-        conn.execute(stmt, [id])
+        let n = conn.execute(stmt, [id])?;
+        if 0 < n {
+            Change::record(conn, Kind::Post, &id.to_string(), true)?;
+        }
+        Ok(n)
     }
 }
 
 pub type DbPool = Pool<SqliteConnectionManager>;
 
+/// Open a read-only connection outside the pool for long reads such as
+/// downloads, so that slow clients do not hold on to pooled connections.
+///
+/// Returns `None` for the in-memory database, which is only reachable via the
+/// pool.
+pub fn open_reader(args: &ServeArgs) -> Option<Connection> {
+    if !args.production {
+        return None;
+    }
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    match Connection::open_with_flags(&args.database_path, flags) {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            tracing::error!("Failed to open read-only connection: {e}");
+            None
+        }
+    }
+}
+
 pub fn connect(args: &ServeArgs) -> Result<DbPool> {
     let pool = if args.production {
         let path = &args.database_path;
@@ -229,10 +358,20 @@ pub fn connect(args: &ServeArgs) -> Result<DbPool> {
     Ok(pool)
 }
 
-fn init_tables(conn: &Connection) {
+pub(crate) fn init_tables(conn: &Connection) {
     Post::create_table(conn).expect("Failed to create posts table");
+    Post::add_slug_column(conn).expect("Failed to add slug column to posts table");
     Kv::create_table(conn).expect("Failed to create kv table");
     File::create_table(conn).expect("Failed to create files table");
+    Change::create_table(conn).expect("Failed to create changes table");
+    Redirect::create_table(conn).expect("Failed to create redirects table");
+    Follower::create_table(conn).expect("Failed to create followers table");
+    Delivery::create_table(conn).expect("Failed to create deliveries table");
+    Reply::create_table(conn).expect("Failed to create replies table");
+    Reaction::create_table(conn).expect("Failed to create reactions table");
+    BlockedDomain::create_table(conn).expect("Failed to create blocked domains table");
+    AuthCode::create_table(conn).expect("Failed to create auth codes table");
+    Token::create_table(conn).expect("Failed to create tokens table");
 }
 
 pub const BLOGROLL_SETTINGS_KEY: &str = "blogroll_settings";
@@ -270,7 +409,7 @@ fn init_data(args: &ServeArgs, conn: &Connection) {
         let now = chrono::Utc::now();
         let content = "[Lorem](https://example.com/lorem) ipsum ut enim ad \
         minim veniam sit amet ipsum lorem consectetur adipiscing elit sed do eiusmod";
-        Post::insert(conn, now, now, content).unwrap();
+        Post::insert(conn, now, now, content, None).unwrap();
         let now = chrono::Utc::now();
         let content = indoc::indoc! {r#"
             # Code
@@ -306,7 +445,7 @@ fn init_data(args: &ServeArgs, conn: &Connection) {
             [^simple]: A simple footnote.
         "#}
         .trim();
-        Post::insert(conn, now, now, content).unwrap();
+        Post::insert(conn, now, now, content, None).unwrap();
 
         let sha = "69b83ddf8f65695f";
         let file = File {
@@ -0,0 +1,255 @@
+//! Persistent queue for delivering ActivityPub activities to other servers.
+//!
+//! Deliveries are stored in SQLite so that they survive restarts. Failed
+//! deliveries are retried with exponential backoff and inboxes that keep
+//! failing are removed from the followers.
+use crate::ap::DeliveryError;
+use crate::ap::Follower;
+use crate::data::SqliteDateTime;
+use crate::serve::ServerContext;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::Result;
+use rusqlite::params;
+use serde_json::Value;
+
+/// Number of attempts before an inbox is considered dead.
+///
+/// With the backoff below, this means that an inbox is pruned after it has
+/// been unreachable for roughly four days.
+pub const MAX_ATTEMPTS: i64 = 18;
+
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    pub id: i64,
+    pub inbox: String,
+    /// The serialized activity.
+    pub activity: String,
+    /// The number of failed attempts so far.
+    pub attempts: i64,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+impl Delivery {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS deliveries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                inbox TEXT NOT NULL,
+                activity TEXT NOT NULL,
+                attempts INTEGER NOT NULL,
+                next_attempt DATETIME NOT NULL,
+                last_error TEXT
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    pub fn insert(conn: &Connection, inbox: &str, activity: &str) -> Result<i64> {
+        let stmt = "
+            INSERT INTO deliveries (inbox, activity, attempts, next_attempt)
+            VALUES (?, ?, 0, ?);
+        ";
+        conn.execute(stmt, params![inbox, activity, Utc::now().to_sqlite()])?;
+        Ok(conn.last_insert_rowid())
+    }
+    fn query(conn: &Connection, stmt: &str, params: impl rusqlite::Params) -> Result<Vec<Self>> {
+        conn.prepare(stmt)?
+            .query_map(params, |row| {
+                let next_attempt: String = row.get("next_attempt")?;
+                Ok(Delivery {
+                    id: row.get("id")?,
+                    inbox: row.get("inbox")?,
+                    activity: row.get("activity")?,
+                    attempts: row.get("attempts")?,
+                    next_attempt: DateTime::from_sqlite(&next_attempt),
+                    last_error: row.get("last_error")?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT id, inbox, activity, attempts, next_attempt, last_error
+            FROM deliveries
+            ORDER BY id;
+        ";
+        Self::query(conn, stmt, [])
+    }
+    /// Return the deliveries that should be attempted at `now`.
+    pub fn due(conn: &Connection, now: DateTime<Utc>) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT id, inbox, activity, attempts, next_attempt, last_error
+            FROM deliveries
+            WHERE next_attempt <= ?
+            ORDER BY id;
+        ";
+        Self::query(conn, stmt, [now.to_sqlite()])
+    }
+    pub fn delete(conn: &Connection, id: i64) -> Result<usize> {
+        conn.execute("DELETE FROM deliveries WHERE id = ?", [id])
+    }
+    pub fn delete_by_inbox(conn: &Connection, inbox: &str) -> Result<usize> {
+        conn.execute("DELETE FROM deliveries WHERE inbox = ?", [inbox])
+    }
+    fn reschedule(&self, conn: &Connection, error: &str) -> Result<usize> {
+        let attempts = self.attempts + 1;
+        let next_attempt = Utc::now() + backoff(attempts);
+        let stmt = "
+            UPDATE deliveries SET attempts = ?, next_attempt = ?, last_error = ?
+            WHERE id = ?;
+        ";
+        let params = params![attempts, next_attempt.to_sqlite(), error, self.id];
+        conn.execute(stmt, params)
+    }
+}
+
+/// Return how long to wait after the given number of failed attempts.
+///
+/// Starts at one minute and doubles up to a maximum of twelve hours.
+pub fn backoff(attempts: i64) -> Duration {
+    let exponent = attempts.clamp(1, 32) - 1;
+    let minutes = 2_i64.saturating_pow(exponent as u32);
+    std::cmp::min(Duration::minutes(minutes), Duration::hours(12))
+}
+
+#[test]
+fn test_backoff() {
+    assert_eq!(backoff(1), Duration::minutes(1));
+    assert_eq!(backoff(2), Duration::minutes(2));
+    assert_eq!(backoff(5), Duration::minutes(16));
+    assert_eq!(backoff(11), Duration::hours(12));
+    assert_eq!(backoff(100), Duration::hours(12));
+    let total = (1..MAX_ATTEMPTS).map(backoff).sum::<Duration>();
+    assert!(Duration::days(3) < total && total < Duration::days(5));
+}
+
+/// Return the inboxes that together reach all followers.
+///
+/// Followers on the same server usually share an inbox so the activity only
+/// has to be sent once per server.
+fn inboxes(followers: &[Follower]) -> Vec<String> {
+    let mut inboxes = followers
+        .iter()
+        .map(|f| f.shared_inbox.clone().unwrap_or(f.inbox.clone()))
+        .collect::<Vec<String>>();
+    inboxes.sort();
+    inboxes.dedup();
+    inboxes
+}
+
+#[test]
+fn test_inboxes() {
+    let follower = |actor: &str, inbox: &str, shared: Option<&str>| Follower {
+        actor: actor.to_string(),
+        inbox: inbox.to_string(),
+        shared_inbox: shared.map(|s| s.to_string()),
+        created: Utc::now(),
+    };
+    let followers = vec![
+        follower(
+            "a",
+            "https://a.example/a/inbox",
+            Some("https://a.example/inbox"),
+        ),
+        follower(
+            "b",
+            "https://a.example/b/inbox",
+            Some("https://a.example/inbox"),
+        ),
+        follower("c", "https://c.example/c/inbox", None),
+    ];
+    let expected = vec!["https://a.example/inbox", "https://c.example/c/inbox"];
+    assert_eq!(inboxes(&followers), expected);
+}
+
+/// Store the activity for delivery to the inbox and start processing.
+pub async fn enqueue(ctx: &ServerContext, inbox: &str, activity: &Value) {
+    let activity = activity.to_string();
+    if let Err(e) = Delivery::insert(&ctx.conn(), inbox, &activity) {
+        tracing::error!("Failed to queue delivery to {inbox}: {e}");
+        return;
+    }
+    spawn_process(ctx);
+}
+
+/// Store the activity for delivery to all followers and start processing.
+pub async fn enqueue_followers(ctx: &ServerContext, activity: &Value) {
+    let followers = match Follower::list(&ctx.conn()) {
+        Ok(followers) => followers,
+        Err(e) => {
+            tracing::error!("Failed to list followers: {e}");
+            return;
+        }
+    };
+    if followers.is_empty() {
+        return;
+    }
+    let activity = activity.to_string();
+    {
+        let conn = ctx.conn();
+        for inbox in inboxes(&followers) {
+            if let Err(e) = Delivery::insert(&conn, &inbox, &activity) {
+                tracing::error!("Failed to queue delivery to {inbox}: {e}");
+            }
+        }
+    }
+    spawn_process(ctx);
+}
+
+fn spawn_process(ctx: &ServerContext) {
+    let ctx = ctx.clone();
+    // Like the backup trigger, the request should not wait for other servers.
+    tokio::spawn(async move {
+        process(&ctx).await;
+    });
+}
+
+/// Remove the inbox from the followers and drop its pending deliveries.
+fn prune(conn: &Connection, inbox: &str) -> Result<()> {
+    let removed = Follower::delete_by_inbox(conn, inbox)?;
+    Delivery::delete_by_inbox(conn, inbox)?;
+    tracing::warn!("Pruned dead inbox {inbox} ({removed} followers)");
+    Ok(())
+}
+
+/// Attempt all deliveries that are due.
+///
+/// This is called after enqueueing and periodically by the scheduler so that
+/// deliveries that were pending during a restart are also sent.
+pub async fn process(ctx: &ServerContext) {
+    // Another task is already sending. It will not pick up deliveries that were
+    // added in the meantime, but the scheduler will.
+    let _guard = match ctx.delivery_lock.try_lock() {
+        Ok(guard) => guard,
+        Err(_) => return,
+    };
+    let due = match Delivery::due(&ctx.conn(), Utc::now()) {
+        Ok(due) => due,
+        Err(e) => {
+            tracing::error!("Failed to get due deliveries: {e}");
+            return;
+        }
+    };
+    for delivery in due {
+        let result = crate::ap::deliver(ctx, &delivery.inbox, &delivery.activity).await;
+        let conn = ctx.conn();
+        let result = match result {
+            Ok(()) => Delivery::delete(&conn, delivery.id).map(|_| ()),
+            Err(DeliveryError::Gone) => prune(&conn, &delivery.inbox),
+            Err(DeliveryError::Failed(e)) => {
+                tracing::warn!("Delivery to {} failed: {e}", delivery.inbox);
+                if MAX_ATTEMPTS <= delivery.attempts + 1 {
+                    prune(&conn, &delivery.inbox)
+                } else {
+                    delivery.reschedule(&conn, &e).map(|_| ())
+                }
+            }
+        };
+        if let Err(e) = result {
+            tracing::error!("Failed to update delivery {}: {e}", delivery.id);
+        }
+    }
+}
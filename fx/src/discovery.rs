@@ -1,4 +1,4 @@
-//! Discovery protocols such as sitemap.xml, rss and robots.
+//! Discovery protocols such as sitemap.xml, robots and NodeInfo.
 use crate::data::Post;
 use crate::serve::ServerContext;
 use crate::serve::content_type;
@@ -11,10 +11,7 @@ use axum::http::HeaderMap;
 use axum::http::Response;
 use axum::http::StatusCode;
 use axum::routing::get;
-
-fn rfc822_datetime(dt: &chrono::DateTime<chrono::Utc>) -> String {
-    dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
-}
+use serde_json::json;
 
 fn xml_header() -> &'static str {
     "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"
@@ -26,71 +23,6 @@ fn test_xml_header() {
     assert_ne!(xml_header(), xml_header().trim());
 }
 
-fn escape_xml(s: &str) -> String {
-    s.replace("&", "&amp;")
-        .replace("<", "&lt;")
-        .replace(">", "&gt;")
-        .replace("\"", "&quot;")
-        .replace("'", "&apos;")
-}
-
-#[test]
-fn test_escape_xml() {
-    assert_eq!(escape_xml("foo&bar"), "foo&amp;bar");
-}
-
-async fn rss(ctx: &ServerContext, posts: &[Post]) -> String {
-    let settings = Settings::from_db(&ctx.conn()).unwrap();
-    let site_name = escape_xml(&settings.site_name);
-    let author_name = escape_xml(&settings.author_name);
-    let base = ctx.base_url();
-    let mut body = String::new();
-    body.push_str(xml_header());
-    body.push_str("<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\">\n");
-    body.push_str("<channel>\n");
-    body.push_str(&format!("<title>{site_name}</title>\n"));
-    body.push_str(&format!("<link>{base}</link>\n"));
-    body.push_str(&format!(
-        "<description>Posts by {author_name}</description>\n"
-    ));
-    let atom_link = format!("{base}/feed.xml");
-    body.push_str(&format!(
-        "<atom:link rel=\"self\" href=\"{atom_link}\" \
-      type=\"application/rss+xml\"/>\n"
-    ));
-    for post in posts {
-        let title = escape_xml(&crate::md::extract_html_title(post));
-        let description = &crate::md::extract_rss_description(post);
-        let url = format!("{base}/posts/{}", post.id);
-        let created = rfc822_datetime(&post.created);
-        let entry = format!(
-            "
-            <item>
-            <title>{title}</title>
-            <link>{url}</link>
-            <guid>{url}</guid>
-            <pubDate>{created}</pubDate>
-            <description><![CDATA[{description}]]</description>
-            </item>
-            "
-        );
-        body.push_str(&entry);
-    }
-    body.push_str("</channel>\n");
-    body.push_str("</rss>\n");
-    crate::html::minify(&body)
-}
-
-async fn get_rss(State(ctx): State<ServerContext>) -> Response<Body> {
-    let posts = Post::list(&ctx.conn()).unwrap();
-    let body = rss(&ctx, &posts).await;
-    let mut headers = HeaderMap::new();
-    // Forces download in Firefox unfortunately:
-    // https://www.petefreitag.com/blog/content-type-xml-feeds/
-    content_type(&mut headers, "application/rss+xml; charset=utf-8");
-    response(StatusCode::OK, headers, body, &ctx)
-}
-
 async fn get_robots(State(ctx): State<ServerContext>) -> Response<Body> {
     let base = ctx.base_url();
     let sitemap_url = format!("{base}/sitemap.xml");
@@ -118,7 +50,9 @@ fn sitemap(ctx: &ServerContext, posts: &[Post]) -> String {
     let base = ctx.base_url();
     body.push_str(&format!("<url><loc>{base}/</loc></url>\n"));
     for post in posts {
-        let url = format!("{}/posts/{}", base, post.id);
+        // The canonical URL, which only changes when the slug is edited.
+        let slug = crate::md::extract_slug(post);
+        let url = format!("{base}{}", crate::html::post_link(post, &slug));
         let updated = w3_datetime(&post.updated);
         let entry = format!(
             "
@@ -142,10 +76,78 @@ async fn get_sitemap(State(ctx): State<ServerContext>) -> Response<Body> {
     response(StatusCode::OK, headers, body, &ctx)
 }
 
+const NODEINFO_SCHEMA: &str = "http://nodeinfo.diaspora.software/ns/schema/2.1";
+
+/// Points to the NodeInfo document, see <https://nodeinfo.diaspora.software/>.
+async fn get_nodeinfo_links(State(ctx): State<ServerContext>) -> Response<Body> {
+    let body = json!({
+        "links": [
+            {
+                "rel": NODEINFO_SCHEMA,
+                "href": format!("{}/nodeinfo/2.1", ctx.base_url()),
+            },
+        ],
+    });
+    let mut headers = HeaderMap::new();
+    content_type(&mut headers, "application/json");
+    response(StatusCode::OK, headers, body.to_string(), &ctx)
+}
+
+fn nodeinfo(ctx: &ServerContext) -> rusqlite::Result<serde_json::Value> {
+    let conn = ctx.conn();
+    let posts = Post::count(&conn)?;
+    let settings = Settings::from_db(&conn)?;
+    let version = include_str!("version.txt").trim();
+    Ok(json!({
+        "version": "2.1",
+        "software": {
+            "name": "fx",
+            "version": version,
+            "repository": env!("CARGO_PKG_REPOSITORY"),
+            "homepage": env!("CARGO_PKG_REPOSITORY"),
+        },
+        "protocols": ["activitypub"],
+        "services": {
+            "inbound": [],
+            "outbound": ["rss2.0"],
+        },
+        // The site has one user, the admin, and registration is not possible.
+        "openRegistrations": false,
+        "usage": {
+            "users": {
+                "total": 1,
+                "activeMonth": 1,
+                "activeHalfyear": 1,
+            },
+            "localPosts": posts,
+        },
+        "metadata": {
+            "nodeName": settings.site_name,
+            "nodeDescription": settings.site_description,
+        },
+    }))
+}
+
+async fn get_nodeinfo(State(ctx): State<ServerContext>) -> Response<Body> {
+    let body = match nodeinfo(&ctx) {
+        Ok(body) => body,
+        Err(e) => {
+            let msg = "Could not create NodeInfo";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    let mut headers = HeaderMap::new();
+    let profile = format!("application/json; profile=\"{NODEINFO_SCHEMA}#\"");
+    content_type(&mut headers, &profile);
+    response(StatusCode::OK, headers, body.to_string(), &ctx)
+}
+
 pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
     router
         .clone()
-        .route("/feed.xml", get(get_rss))
         .route("/robots.txt", get(get_robots))
         .route("/sitemap.xml", get(get_sitemap))
+        .route("/.well-known/nodeinfo", get(get_nodeinfo_links))
+        .route("/nodeinfo/2.1", get(get_nodeinfo))
 }
@@ -0,0 +1,254 @@
+//! Feeds with the latest posts in RSS 2.0, Atom and JSON Feed 1.1.
+//!
+//! All three contain the same entries and are written with the `rss` and
+//! `atom_syndication` crates or `serde_json` instead of by hand, so that the
+//! content of posts cannot break the XML.
+use crate::data::Post;
+use crate::serve::ServerContext;
+use crate::serve::content_type;
+use crate::serve::response;
+use crate::settings::Settings;
+use atom_syndication::Text;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use chrono::DateTime;
+use chrono::Utc;
+use serde_json::json;
+
+/// A post as it is shown in the feeds.
+struct Entry {
+    /// Identifies the post in feed readers and never changes.
+    id: String,
+    url: String,
+    title: String,
+    html: String,
+    created: DateTime<Utc>,
+    updated: DateTime<Utc>,
+}
+
+impl Entry {
+    fn new(base: &str, post: &Post) -> Self {
+        let slug = crate::md::extract_slug(post);
+        Entry {
+            id: format!("{base}/posts/{}", post.id),
+            url: format!("{base}{}", crate::html::post_link(post, &slug)),
+            title: crate::md::extract_html_title(post),
+            html: crate::md::extract_rss_description(post),
+            created: post.created,
+            updated: post.updated,
+        }
+    }
+}
+
+struct Feed {
+    base: String,
+    site_name: String,
+    author_name: String,
+    entries: Vec<Entry>,
+}
+
+impl Feed {
+    fn new(ctx: &ServerContext, posts: &[Post]) -> Self {
+        let settings = Settings::from_db(&ctx.conn()).unwrap();
+        let base = ctx.base_url();
+        let entries = posts.iter().map(|post| Entry::new(&base, post)).collect();
+        Feed {
+            base,
+            site_name: settings.site_name,
+            author_name: settings.author_name,
+            entries,
+        }
+    }
+    fn description(&self) -> String {
+        format!("Posts by {}", self.author_name)
+    }
+    /// The last time that any of the entries changed.
+    fn updated(&self) -> DateTime<Utc> {
+        let updated = self.entries.iter().map(|entry| entry.updated).max();
+        updated.unwrap_or(DateTime::UNIX_EPOCH)
+    }
+}
+
+fn rss(feed: &Feed) -> String {
+    let base = &feed.base;
+    let items = feed
+        .entries
+        .iter()
+        .map(|entry| rss::Item {
+            title: Some(entry.title.clone()),
+            link: Some(entry.url.clone()),
+            guid: Some(rss::Guid {
+                value: entry.id.clone(),
+                permalink: true,
+            }),
+            pub_date: Some(entry.created.to_rfc2822()),
+            description: Some(entry.html.clone()),
+            ..Default::default()
+        })
+        .collect();
+    let self_link = rss::extension::atom::Link {
+        href: format!("{base}/feed.xml"),
+        rel: "self".to_string(),
+        mime_type: Some("application/rss+xml".to_string()),
+        ..Default::default()
+    };
+    let channel = rss::Channel {
+        title: feed.site_name.clone(),
+        link: format!("{base}/"),
+        description: feed.description(),
+        items,
+        atom_ext: Some(rss::extension::atom::AtomExtension {
+            links: vec![self_link],
+        }),
+        ..Default::default()
+    };
+    channel.to_string()
+}
+
+fn atom(feed: &Feed) -> String {
+    let base = &feed.base;
+    let author = atom_syndication::Person {
+        name: feed.author_name.clone(),
+        uri: Some(format!("{base}/")),
+        ..Default::default()
+    };
+    let entries = feed
+        .entries
+        .iter()
+        .map(|entry| atom_syndication::Entry {
+            id: entry.id.clone(),
+            title: Text::plain(entry.title.clone()),
+            // Unlike RSS, Atom tells feed readers that an edited post was
+            // updated, so that they can show the new version.
+            updated: entry.updated.fixed_offset(),
+            published: Some(entry.created.fixed_offset()),
+            links: vec![atom_syndication::Link {
+                href: entry.url.clone(),
+                mime_type: Some("text/html".to_string()),
+                ..Default::default()
+            }],
+            content: Some(atom_syndication::Content {
+                value: Some(entry.html.clone()),
+                content_type: Some("html".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .collect();
+    let links = vec![
+        atom_syndication::Link {
+            href: format!("{base}/"),
+            mime_type: Some("text/html".to_string()),
+            ..Default::default()
+        },
+        atom_syndication::Link {
+            href: format!("{base}/atom.xml"),
+            rel: "self".to_string(),
+            mime_type: Some("application/atom+xml".to_string()),
+            ..Default::default()
+        },
+    ];
+    let atom = atom_syndication::Feed {
+        id: format!("{base}/"),
+        title: Text::plain(feed.site_name.clone()),
+        subtitle: Some(Text::plain(feed.description())),
+        updated: feed.updated().fixed_offset(),
+        authors: vec![author],
+        links,
+        entries,
+        ..Default::default()
+    };
+    atom.to_string()
+}
+
+/// JSON Feed 1.1 according to <https://www.jsonfeed.org/version/1.1/>.
+fn json_feed(feed: &Feed) -> String {
+    let base = &feed.base;
+    let items = feed
+        .entries
+        .iter()
+        .map(|entry| {
+            json!({
+                "id": entry.id,
+                "url": entry.url,
+                "title": entry.title,
+                "content_html": entry.html,
+                "date_published": entry.created.to_rfc3339(),
+                "date_modified": entry.updated.to_rfc3339(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let json = json!({
+        "version": "https://jsonfeed.org/version/1.1",
+        "title": feed.site_name,
+        "home_page_url": format!("{base}/"),
+        "feed_url": format!("{base}/feed.json"),
+        "description": feed.description(),
+        "authors": [{ "name": feed.author_name, "url": format!("{base}/") }],
+        "items": items,
+    });
+    serde_json::to_string_pretty(&json).unwrap()
+}
+
+fn feed_response(ctx: &ServerContext, mime: &str, body: String) -> Response<Body> {
+    let mut headers = HeaderMap::new();
+    content_type(&mut headers, &format!("{mime}; charset=utf-8"));
+    response(StatusCode::OK, headers, body, ctx)
+}
+
+async fn get_rss(State(ctx): State<ServerContext>) -> Response<Body> {
+    let posts = Post::list(&ctx.conn()).unwrap();
+    let body = rss(&Feed::new(&ctx, &posts));
+    // Forces download in Firefox unfortunately:
+    // https://www.petefreitag.com/blog/content-type-xml-feeds/
+    feed_response(&ctx, "application/rss+xml", body)
+}
+
+async fn get_atom(State(ctx): State<ServerContext>) -> Response<Body> {
+    let posts = Post::list(&ctx.conn()).unwrap();
+    let body = atom(&Feed::new(&ctx, &posts));
+    feed_response(&ctx, "application/atom+xml", body)
+}
+
+async fn get_json_feed(State(ctx): State<ServerContext>) -> Response<Body> {
+    let posts = Post::list(&ctx.conn()).unwrap();
+    let body = json_feed(&Feed::new(&ctx, &posts));
+    feed_response(&ctx, "application/feed+json", body)
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/feed.xml", get(get_rss))
+        .route("/atom.xml", get(get_atom))
+        .route("/feed.json", get(get_json_feed))
+}
+
+#[test]
+fn test_rss_cdata() {
+    let now = Utc::now();
+    let feed = Feed {
+        base: "https://example.com".to_string(),
+        site_name: "Site".to_string(),
+        author_name: "Author".to_string(),
+        entries: vec![Entry {
+            id: "https://example.com/posts/1".to_string(),
+            url: "https://example.com/posts/1/code".to_string(),
+            title: "Code".to_string(),
+            html: "<pre><code>let x = a[b[0]]>1;</code></pre>".to_string(),
+            created: now,
+            updated: now,
+        }],
+    };
+    let channel = rss(&feed).parse::<rss::Channel>().unwrap();
+    let description = channel.items[0].description.as_deref();
+    assert_eq!(
+        description,
+        Some("<pre><code>let x = a[b[0]]>1;</code></pre>")
+    );
+}
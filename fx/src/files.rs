@@ -1,4 +1,6 @@
 //! File upload and download at `/files`.
+use crate::changes::Change;
+use crate::changes::Kind;
 use crate::data::Kv;
 use crate::html::PageSettings;
 use crate::html::Top;
@@ -23,6 +25,7 @@ use bytes::Bytes;
 use rusqlite::Connection;
 use rusqlite::Result;
 use rusqlite::params;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 use sha2::Digest;
@@ -42,7 +45,7 @@ pub struct File {
 }
 
 impl File {
-    fn new(mime_type: &str, filename: &str, data: Bytes) -> Self {
+    pub fn new(mime_type: &str, filename: &str, data: Bytes) -> Self {
         let sha = sha2::Sha256::digest(&data);
         // Turning the 256 bit hash into a 64 bit hash. The probability of a
         // collision is roughly 1 in 2^(n/2) which means 1 in 2^32=4 billion to
@@ -62,6 +65,51 @@ impl File {
     }
 }
 
+/// A file without its data, for listings.
+#[derive(Debug, JsonSchema, Serialize)]
+pub struct FileInfo {
+    pub sha: String,
+    pub mime_type: String,
+    pub filename: String,
+    /// The size of the data in bytes.
+    pub size: i64,
+}
+
+impl FileInfo {
+    pub fn list(conn: &Connection) -> rusqlite::Result<Vec<Self>> {
+        let stmt = "
+            SELECT sha, mime_type, filename, length(data) AS size
+            FROM files
+            ORDER BY filename;
+            ";
+        let mut stmt = conn.prepare(stmt)?;
+        let files = stmt.query_map([], FileInfo::from_row)?;
+        files.collect::<Result<Vec<_>, _>>()
+    }
+    pub fn get(conn: &Connection, sha: &str) -> rusqlite::Result<Self> {
+        let stmt = "
+            SELECT sha, mime_type, filename, length(data) AS size
+            FROM files
+            WHERE sha = ?;
+            ";
+        conn.query_row(stmt, [sha], FileInfo::from_row)
+    }
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(FileInfo {
+            sha: row.get("sha")?,
+            mime_type: row.get("mime_type")?,
+            filename: row.get("filename")?,
+            size: row.get("size")?,
+        })
+    }
+    pub fn path(&self) -> String {
+        file_path(&self.sha, &self.mime_type, &self.filename)
+    }
+    pub fn md_link(&self) -> String {
+        file_md_link(&self.sha, &self.mime_type, &self.filename)
+    }
+}
+
 fn bytes_to_blob(bytes: &Bytes) -> Vec<u8> {
     bytes.to_vec()
 }
@@ -112,7 +160,9 @@ impl File {
             ";
         let data = bytes_to_blob(&file.data);
         let params = params![file.sha, file.mime_type, file.filename, data];
-        conn.execute(sql, params)
+        let n = conn.execute(sql, params)?;
+        Change::record(conn, Kind::File, &file.sha, false)?;
+        Ok(n)
     }
     pub fn get(conn: &Connection, name: &str) -> rusqlite::Result<Self> {
         let stmt = "
@@ -134,17 +184,24 @@ impl File {
     }
     pub fn delete(conn: &Connection, sha: &str) -> rusqlite::Result<usize> {
         let sql = "DELETE FROM files WHERE sha = ?";
-        conn.execute(sql, [sha])
+        let n = conn.execute(sql, [sha])?;
+        if 0 < n {
+            Change::record(conn, Kind::File, sha, true)?;
+        }
+        Ok(n)
     }
     pub fn rename(conn: &Connection, sha: &str, filename: &str) -> rusqlite::Result<usize> {
         let sql = "UPDATE files SET filename = ? WHERE sha = ?";
-        conn.execute(sql, [filename, sha])
+        let n = conn.execute(sql, [filename, sha])?;
+        if 0 < n {
+            Change::record(conn, Kind::File, sha, false)?;
+        }
+        Ok(n)
     }
 }
 
-fn file_ext(file: &File) -> String {
-    let filename = &file.filename;
-    if file.mime_type.starts_with("image/") {
+fn file_ext(mime_type: &str, filename: &str) -> String {
+    if mime_type.starts_with("image/") {
         // For images, the extension is not important since the download link
         // will automatically add the extension based on the mime type.
         "".to_string()
@@ -159,21 +216,60 @@ fn file_ext(file: &File) -> String {
     }
 }
 
-fn md_link(file: &File) -> String {
-    let filename = &file.filename;
-    let ext = file_ext(file);
-    let sha = &file.sha;
-    if file.mime_type.starts_with("image/") {
-        format!("![{filename}](/files/{sha})")
+fn file_path(sha: &str, mime_type: &str, filename: &str) -> String {
+    format!("/files/{sha}{}", file_ext(mime_type, filename))
+}
+
+fn file_md_link(sha: &str, mime_type: &str, filename: &str) -> String {
+    let path = file_path(sha, mime_type, filename);
+    if mime_type.starts_with("image/") {
+        format!("![{filename}]({path})")
+    } else {
+        format!("[{filename}]({path})")
+    }
+}
+
+/// Reduce a filename to something that is safe to extract from an archive.
+///
+/// Only the last path component is kept and leading dots are removed so that
+/// the file cannot end up outside of its directory or be hidden.
+fn sanitize_filename(filename: &str) -> String {
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or("");
+    let name = name.chars().filter(|c| !c.is_control()).collect::<String>();
+    let name = name.trim().trim_start_matches('.');
+    if name.is_empty() {
+        "file".to_string()
     } else {
-        format!("[{filename}](/files/{sha}{ext})")
+        name.to_string()
     }
 }
 
+/// Return the path of the file in a backup archive.
+///
+/// Files are put in a directory per sha so that files with the same name do
+/// not overwrite each other when the archive is extracted.
+pub fn archive_path(sha: &str, filename: &str) -> String {
+    let sha = sha
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect::<String>();
+    format!("files/{sha}/{}", sanitize_filename(filename))
+}
+
+/// Return the path at which the file is served.
+pub fn path(file: &File) -> String {
+    file_path(&file.sha, &file.mime_type, &file.filename)
+}
+
+/// Return the Markdown link (or image) that can be pasted into a post.
+pub fn md_link(file: &File) -> String {
+    file_md_link(&file.sha, &file.mime_type, &file.filename)
+}
+
 fn show_file(file: &File) -> String {
     let sha = &file.sha;
     let link = md_link(file);
-    let ext = file_ext(file);
+    let ext = file_ext(&file.mime_type, &file.filename);
     format!(
         "
         <div style='padding: 6px; padding-bottom: 0px; padding-top: 12px; \
@@ -460,3 +556,12 @@ pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
         .route("/files/rename/{sha}", get(get_rename))
         .route("/files/rename/{sha}", post(post_rename))
 }
+
+#[test]
+fn test_archive_path() {
+    assert_eq!(archive_path("abc", "a.png"), "files/abc/a.png");
+    assert_eq!(archive_path("abc", "../../etc/passwd"), "files/abc/passwd");
+    assert_eq!(archive_path("abc", "..\\x.txt"), "files/abc/x.txt");
+    assert_eq!(archive_path("abc", ".."), "files/abc/file");
+    assert_eq!(archive_path("../abc", ".env"), "files/abc/env");
+}
@@ -0,0 +1,218 @@
+//! Optional front matter at the start of a post.
+//!
+//! This is the same `---` block that backups write for `created` and `updated`:
+//!
+//! ```markdown
+//! ---
+//! title: 'Shown in search results instead of the first line'
+//! description: 'Shown in search results and link previews'
+//! image: '/files/69b83ddf8f65695f'
+//! canonical: 'https://example.com/where-this-was-first-published'
+//! lang: 'nl'
+//! created: '2020-01-02 03:04:05 UTC'
+//! ---
+//!
+//! # Title
+//! ```
+//!
+//! TOML front matter between `+++` lines, as Hugo writes it, is read too.
+//! Only top-level scalars are used, so nested values, lists and comments are
+//! skipped. A `---` block is only taken as front matter when every other line
+//! is a `key: value` pair, so that a post which starts with a horizontal rule
+//! is left alone.
+use chrono::DateTime;
+use chrono::Utc;
+use std::collections::BTreeMap;
+
+/// The top-level scalars by lowercased key.
+pub type Fields = BTreeMap<String, String>;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrontMatter {
+    /// Overrides the title from the first line of the post.
+    pub title: Option<String>,
+    /// Overrides the description from the text of the post.
+    pub description: Option<String>,
+    /// The `og:image` as path on this site or full URL.
+    pub image: Option<String>,
+    /// Where the post was first published, if not on this site.
+    pub canonical: Option<String>,
+    /// The language of the post, such as `nl` or `en-GB`.
+    pub lang: Option<String>,
+    /// Backdates the post.
+    pub created: Option<DateTime<Utc>>,
+    /// When the post was last changed, as written by backups.
+    pub updated: Option<DateTime<Utc>>,
+    /// Overrides the slug that was set in the editor.
+    pub slug: Option<String>,
+    /// All fields, including the ones above and the ones that only the
+    /// importer uses such as `date` and `draft`.
+    pub fields: Fields,
+}
+
+impl FrontMatter {
+    fn from_fields(fields: Fields) -> Self {
+        let get = |key: &str| fields.get(key).cloned();
+        let datetime = |key: &str| {
+            fields
+                .get(key)
+                .and_then(|v| crate::restore::parse_datetime(v))
+        };
+        FrontMatter {
+            title: get("title"),
+            description: get("description"),
+            image: get("image"),
+            canonical: get("canonical"),
+            lang: get("lang").filter(|value| is_lang(value)),
+            created: datetime("created"),
+            updated: datetime("updated"),
+            slug: fields
+                .get("slug")
+                .and_then(|v| crate::md::normalize_slug(v)),
+            fields,
+        }
+    }
+
+    /// Return the value of the first of `keys` that is set.
+    pub fn field(&self, keys: &[&str]) -> Option<&str> {
+        keys.iter()
+            .find_map(|key| self.fields.get(*key))
+            .map(|value| value.as_str())
+    }
+}
+
+fn unquote(value: &str) -> String {
+    let value = value.trim();
+    if let Some(value) = value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')) {
+        // YAML escapes a single quote inside single quotes by doubling it.
+        value.replace("''", "'")
+    } else if let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        value.replace("\\\"", "\"")
+    } else {
+        value.to_string()
+    }
+}
+
+fn is_lang(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn is_key(key: &str) -> bool {
+    key.starts_with(|c: char| c.is_ascii_alphabetic())
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn parse_yaml(matter: &str) -> Option<Fields> {
+    let mut fields = Fields::new();
+    let mut has_key = false;
+    for line in matter.lines().filter(|line| !line.trim().is_empty()) {
+        if line.starts_with([' ', '\t', '-', '#']) {
+            continue;
+        }
+        let (key, value) = line.split_once(':')?;
+        if !is_key(key) {
+            return None;
+        }
+        has_key = true;
+        let value = unquote(value);
+        if !value.is_empty() {
+            fields.insert(key.to_lowercase(), value);
+        }
+    }
+    has_key.then_some(fields)
+}
+
+fn parse_toml(matter: &str) -> Option<Fields> {
+    let table = matter.parse::<toml::Table>().ok()?;
+    let mut fields = Fields::new();
+    for (key, value) in table {
+        let value = match value {
+            toml::Value::String(s) => s,
+            toml::Value::Datetime(dt) => dt.to_string(),
+            toml::Value::Boolean(b) => b.to_string(),
+            toml::Value::Integer(i) => i.to_string(),
+            _ => continue,
+        };
+        fields.insert(key.to_lowercase(), value);
+    }
+    Some(fields)
+}
+
+/// Split the content of a post into the front matter and the rest.
+///
+/// Content without front matter, or with an unterminated or invalid block, is
+/// returned as is.
+pub fn split(content: &str) -> (FrontMatter, &str) {
+    let none = (FrontMatter::default(), content);
+    let Some(first) = content.lines().next() else {
+        return none;
+    };
+    let delimiter = first.trim_end();
+    if delimiter != "---" && delimiter != "+++" {
+        return none;
+    }
+    let start = (first.len() + 1).min(content.len());
+    let mut end = start;
+    for line in content[start..].split_inclusive('\n') {
+        if line.trim_end() == delimiter {
+            let matter = &content[start..end];
+            let fields = match delimiter {
+                "+++" => parse_toml(matter),
+                _ => parse_yaml(matter),
+            };
+            let Some(fields) = fields else {
+                return none;
+            };
+            let body = &content[end + line.len()..];
+            return (
+                FrontMatter::from_fields(fields),
+                body.trim_start_matches('\n'),
+            );
+        }
+        end += line.len();
+    }
+    none
+}
+
+#[test]
+fn test_split() {
+    let content =
+        "---\ntitle: 'It''s here'\nlang: nl\ncreated: '2020-01-02 03:04:05 UTC'\n---\n\n# Hi\n";
+    let (front_matter, body) = split(content);
+    assert_eq!(front_matter.title.as_deref(), Some("It's here"));
+    assert_eq!(front_matter.lang.as_deref(), Some("nl"));
+    assert_eq!(front_matter.created.unwrap().timestamp(), 1577934245);
+    assert_eq!(body, "# Hi\n");
+
+    let content = "+++\ntitle = \"Hello\"\ndate = 2020-01-02T03:04:05Z\ndraft = false\n+++\nBody\n";
+    let (front_matter, body) = split(content);
+    assert_eq!(front_matter.title.as_deref(), Some("Hello"));
+    assert_eq!(
+        front_matter.field(&["created", "date"]),
+        Some("2020-01-02T03:04:05Z")
+    );
+    assert_eq!(front_matter.field(&["draft"]), Some("false"));
+    assert_eq!(body, "Body\n");
+
+    let content =
+        "---\ntitle: \"A: B\"\ntags:\n  - rust\nDate: 2020-01-02 03:04:05 +0100\n---\nBody";
+    let (front_matter, body) = split(content);
+    assert_eq!(front_matter.title.as_deref(), Some("A: B"));
+    assert_eq!(front_matter.field(&["tags"]), None);
+    assert_eq!(
+        front_matter.field(&["date"]),
+        Some("2020-01-02 03:04:05 +0100")
+    );
+    assert_eq!(body, "Body");
+
+    let content = "---\n\nSome text after a horizontal rule.\n\n---\n";
+    assert_eq!(split(content), (FrontMatter::default(), content));
+    let content = "---\n# Heading\n---\n";
+    assert_eq!(split(content), (FrontMatter::default(), content));
+    let content = "---\ntitle: x\n";
+    assert_eq!(split(content), (FrontMatter::default(), content));
+    let content = "No front matter\n";
+    assert_eq!(split(content), (FrontMatter::default(), content));
+}
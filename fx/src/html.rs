@@ -15,6 +15,15 @@ pub fn escape_single_quote(s: &str) -> String {
     s.replace('\'', "&#39;")
 }
 
+/// Escape text from untrusted sources such as other servers.
+pub fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
 pub fn show_date<Tz: chrono::TimeZone>(datetime: &DateTime<Tz>) -> String {
     let now = chrono::Utc::now();
     let duration = now.signed_duration_since(datetime.clone());
@@ -143,7 +152,7 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
         // Front page preview is already HTML.
         post.content.clone()
     } else {
-        crate::md::content_to_html(&post.content)
+        crate::md::content_to_html(post.body())
     };
     let html = set_header_id(&html);
     let style = if is_front_page_preview {
@@ -155,12 +164,17 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
         ""
     } else {
         &format!(
-            "<div class='updated'>last update: {}</div>",
+            "<div class='updated'>last update: \
+              <time class='dt-updated' datetime='{}'>{}</time></div>",
+            crate::serve::iso8601(&post.updated),
             show_date(&post.updated)
         )
     };
     let unstyled_link = if is_front_page_preview {
-        format!("<a href='{}' class='unstyled-link'>", post_link(post, slug))
+        format!(
+            "<a href='{}' class='unstyled-link u-url'>",
+            post_link(post, slug)
+        )
     } else {
         "<span>".to_string()
     };
@@ -169,6 +183,15 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
     } else {
         "</span>".to_string()
     };
+    // The preview content is HTML, so the title cannot be extracted from it.
+    let name = if is_front_page_preview {
+        "".to_string()
+    } else {
+        format!(
+            "<data class='p-name' value='{}'></data>",
+            escape_html(&crate::md::extract_html_title(post))
+        )
+    };
     let post_preview_class = if is_front_page_preview {
         "post-preview"
     } else {
@@ -182,7 +205,7 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
             <div style='display: flex; justify-content: flex-end; \
               border-top: 1px solid var(--border); padding-top: 10px;
               font-size: var(--small-font-size);'>
-                 <a href='{}' class='unstyled-link' id='long-url'>
+                 <a href='{}' class='unstyled-link u-url' id='long-url'>
                     🔗 Link
                  </a>&nbsp;(
                  <a id='copy-long-url' href='javascript:void(0)' onclick='copyLongUrl()'>
@@ -195,19 +218,23 @@ pub fn wrap_post_content(post: &Post, slug: &str, is_front_page_preview: bool) -
     };
     format!(
         "
-        <div class='post' style='{style}'>
+        <div class='post h-entry' style='{style}'>
+            {name}
             {unstyled_link}
                 <div class='post-header'>
-                    <div class='created'>{}</div>
+                    <div class='created'>
+                        <time class='dt-published' datetime='{}'>{}</time>
+                    </div>
                     {updated}
                 </div>
             {post_link_end}
-            <div data-post-link='{}' class='post-content {post_preview_class}'>
+            <div data-post-link='{}' class='post-content e-content {post_preview_class}'>
             {html}
             </div>
             {share_link}
         </div>
         ",
+        crate::serve::iso8601(&post.created),
         show_date(&post.created),
         post_link(post, slug)
     )
@@ -236,6 +263,8 @@ pub struct PageSettings {
     show_about: bool,
     top: Top,
     extra_head: String,
+    /// The language of the page, defaults to `--html-lang`.
+    lang: Option<String>,
 }
 
 impl PageSettings {
@@ -254,8 +283,12 @@ impl PageSettings {
             show_about,
             top,
             extra_head: extra_head.to_string(),
+            lang: None,
         }
     }
+    pub fn set_lang(&mut self, lang: &str) {
+        self.lang = Some(lang.to_string());
+    }
 }
 
 pub fn edit_post_buttons(_ctx: &ServerContext, post: &Post) -> String {
@@ -300,14 +333,24 @@ fn add_post_form() -> String {
     .to_string()
 }
 
-pub fn edit_post_form(post: &Post) -> String {
+/// Return the edit form for a post.
+///
+/// The version is the `Post::version` of the stored post on which the edit is
+/// based. It is sent back on save to detect conflicting edits.
+pub fn edit_post_form(post: &Post, version: &str) -> String {
     let id = post.id;
-    let content = &post.content;
+    let content = escape_html(&post.content);
+    let slug = post.slug.as_deref().unwrap_or("");
+    let title_slug = escape_html(&crate::md::extract_slug(&Post {
+        slug: None,
+        ..post.clone()
+    }));
     let markdown_link = crate::md::markdown_link();
     format!(
         "
     <form style='width: 100%;' action='/posts/edit/{id}' \
       method='post' onchange='{SET_LEAVE_CONFIRMATION}'>
+        <input type='hidden' name='version' value='{version}'/>
         <textarea \
           style='display: block; width: 100%; height: 60vh; margin-top: 10px;' \
           class='boxsizing-border' \
@@ -317,6 +360,12 @@ pub fn edit_post_form(post: &Post) -> String {
         <div style='font-size: 0.8rem; text-align: right;'>
             This field supports {markdown_link}.
         </div>
+        <div style='font-size: 0.8rem;'>
+            <label for='slug'>Slug</label>
+            <input type='text' id='slug' name='slug' value='{slug}' \
+              placeholder='{title_slug}'/>
+            Leave empty to use the title. Links with an old slug keep working.
+        </div>
         <br>
         <div style='display: flex; justify-content: flex-end;'>
             <input type='submit' onclick='{UNSET_LEAVE_CONFIRMATION}' \
@@ -329,6 +378,37 @@ pub fn edit_post_form(post: &Post) -> String {
     )
 }
 
+/// Return both versions of a post side by side after a conflicting edit.
+///
+/// The form on the left is based on the saved version, so publishing it again
+/// deliberately overwrites the saved version.
+pub fn edit_conflict(yours: &Post, saved: &Post) -> String {
+    let form = edit_post_form(yours, &saved.version());
+    let saved_content = escape_html(&saved.content);
+    let updated = saved.updated.format("%Y-%m-%d %H:%M:%S UTC");
+    format!(
+        "
+    <div class='medium-text' style='text-align: center;'>
+        <p>This post was changed elsewhere (saved at {updated}) after you started editing.
+        Your version was not saved.</p>
+    </div>
+    <div style='display: flex; gap: 20px;'>
+        <div style='flex: 1; min-width: 0;'>
+            <b>Your version</b>
+            {form}
+        </div>
+        <div style='flex: 1; min-width: 0;'>
+            <b>Saved version</b>
+            <textarea \
+              style='display: block; width: 100%; height: 60vh; margin-top: 10px;' \
+              class='boxsizing-border' readonly>\n{saved_content}
+            </textarea>
+        </div>
+    </div>
+    "
+    )
+}
+
 /// Return formatted HTML/CSS that is small and readable.
 pub fn minify(page: &str) -> String {
     let mut lines = Vec::new();
@@ -400,6 +480,7 @@ async fn about(ctx: &ServerContext, settings: &PageSettings) -> String {
     let about = crate::md::content_to_html(&about);
     let author_name = Kv::get(&ctx.conn(), "author_name").unwrap();
     let author_name = String::from_utf8(author_name).unwrap();
+    let base = ctx.base_url();
     let style = "font-size: 0.8rem; padding-top: 0.1rem;";
     let admin_buttons = if settings.is_logged_in.unwrap_or(false) {
         &format!(
@@ -408,6 +489,9 @@ async fn about(ctx: &ServerContext, settings: &PageSettings) -> String {
                 <a href='/files' class='unstyled-link' style='{style}'>
                     📁 Files
                 </a>&nbsp;
+                <a href='/replies' class='unstyled-link' style='{style}'>
+                    💬 Replies
+                </a>&nbsp;
                 <a href='/settings' class='unstyled-link' style='{style}'>
                     ⚙️ Settings
                 </a>
@@ -459,11 +543,11 @@ async fn about(ctx: &ServerContext, settings: &PageSettings) -> String {
     };
     format!(
         "
-    <div class='introduction' style='padding: 10px; {}'>
+    <div class='introduction h-card' style='padding: 10px; {}'>
         <div style='{container_style}'>
             <div class='full-name' \
                 style='{name_style}'>
-                <a class='unstyled-link' href='/'>{author_name}</a>
+                <a class='unstyled-link p-name u-url u-uid' href='{base}/'>{author_name}</a>
             </div>
             <div>
                 <span>
@@ -472,7 +556,7 @@ async fn about(ctx: &ServerContext, settings: &PageSettings) -> String {
                 </span>
             </div>
         </div>
-        <div class='about' style='font-size: 0.9rem;'>{about}</div>
+        <div class='about p-note' style='font-size: 0.9rem;'>{about}</div>
         <div>
             {admin_buttons}
         </div>
@@ -633,15 +717,15 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
         </script>
         "#},
     };
-    let html_lang = &ctx.args.html_lang;
+    let html_lang = settings.lang.as_ref().unwrap_or(&ctx.args.html_lang);
     let extra_head = &settings.extra_head;
     let version = include_str!("version.txt").trim();
     let highlight = highlight_head(ctx, body).await;
     let katex = katex_head(body);
     let og_title = if settings.title.is_empty() {
-        &site_name
+        site_name.clone()
     } else {
-        &settings.title
+        escape_single_quote(&settings.title)
     };
     let data_theme = Kv::get(&ctx.conn(), "dark_mode").unwrap();
     let data_theme = String::from_utf8(data_theme).unwrap();
@@ -659,6 +743,13 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
             <meta name='viewport' content='width=device-width, initial-scale=1'>
             <link rel='stylesheet' href='/static/style.css'>
             <link rel='alternate' type='application/rss+xml' href='/feed.xml'>
+            <link rel='alternate' type='application/atom+xml' href='/atom.xml'>
+            <link rel='alternate' type='application/feed+json' href='/feed.json'>
+            <link rel='webmention' href='/webmention'>
+            <link rel='micropub' href='/micropub'>
+            <link rel='indieauth-metadata' href='/.well-known/oauth-authorization-server'>
+            <link rel='authorization_endpoint' href='/auth'>
+            <link rel='token_endpoint' href='/token'>
             <script src='/static/script.js' defer></script>
             <title>{full_title}</title>
             <meta name='description' content='{description}'/>
@@ -690,19 +781,26 @@ pub async fn page(ctx: &ServerContext, settings: &PageSettings, body: &str) -> S
     minify(&page)
 }
 
-pub async fn login(ctx: &ServerContext, error: Option<&str>) -> String {
+pub async fn login(ctx: &ServerContext, error: Option<&str>, next: Option<&str>) -> String {
     let top = Top::Homepage;
     let description = "Login to the website";
-    let settings = PageSettings::new("Login", None, Some(&description), false, top, "");
+    let settings = PageSettings::new("Login", None, Some(description), false, top, "");
     let error = match error {
         Some(error) => format!("<div style='font-style: italic;'>{error}</div>"),
         None => "".to_string(),
     };
+    let action = match next {
+        Some(next) => {
+            let query = serde_urlencoded::to_string([("next", next)]).unwrap();
+            escape_html(&format!("/login?{query}"))
+        }
+        None => "/login".to_string(),
+    };
     let style = "text-align: center; margin-top: 15vh;";
     let input_style = "font-size: 1rem;";
     let body = format!(
         "
-        <form style='{style}' method='post' action='/login'>
+        <form style='{style}' method='post' action='{action}'>
             <input style='{input_style}' id='username' name='username' \
               type='text' placeholder='username' required/><br>
             <input style='{input_style}' id='password' name='password' \
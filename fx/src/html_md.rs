@@ -0,0 +1,394 @@
+//! Convert HTML from other platforms to Markdown.
+//!
+//! This handles the HTML that blog engines and Mastodon produce, not arbitrary
+//! HTML. Unknown tags are dropped while keeping their text.
+use regex::Regex;
+
+fn decode_entity(entity: &str) -> Option<String> {
+    let c = match entity {
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "lt" => '<',
+        "gt" => '>',
+        "amp" => '&',
+        "hellip" => '…',
+        "ndash" => '–',
+        "mdash" => '—',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        _ => {
+            let code = if let Some(hex) = entity.strip_prefix("#x") {
+                u32::from_str_radix(hex, 16).ok()?
+            } else {
+                entity.strip_prefix('#')?.parse().ok()?
+            };
+            char::from_u32(code)?
+        }
+    };
+    Some(c.to_string())
+}
+
+/// Decode HTML entities.
+///
+/// When `keep_markup` is set, `&lt;`, `&gt;` and `&amp;` are kept since
+/// Markdown understands them and decoding them could turn text into tags.
+fn decode(text: &str, keep_markup: bool) -> String {
+    let re = Regex::new(r"&(#?[a-zA-Z0-9]+);").unwrap();
+    re.replace_all(text, |caps: &regex::Captures| {
+        let decoded = decode_entity(&caps[1]);
+        match decoded.as_deref() {
+            Some("<" | ">" | "&") if keep_markup => caps[0].to_string(),
+            Some(decoded) => decoded.to_string(),
+            None => caps[0].to_string(),
+        }
+    })
+    .to_string()
+}
+
+/// Escape characters that would otherwise be interpreted as Markdown.
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        // `$` because math is enabled.
+        if matches!(c, '\\' | '*' | '_' | '`' | '$' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(r#"\b{name}\s*=\s*(?:"([^"]*)"|'([^']*)')"#)).unwrap();
+    let caps = re.captures(tag)?;
+    let value = caps.get(1).or_else(|| caps.get(2))?.as_str();
+    Some(decode(value, false))
+}
+
+enum Frame {
+    Root,
+    /// The href and the text without escaping.
+    Link {
+        href: String,
+        plain: String,
+    },
+    Quote,
+    Pre,
+    Code,
+}
+
+struct Converter {
+    frames: Vec<(Frame, String)>,
+    /// Whether each open list is ordered and the number of its next item.
+    lists: Vec<(bool, usize)>,
+    /// The tag whose content is dropped, such as `script`.
+    skip: Option<String>,
+}
+
+impl Converter {
+    fn buf(&mut self) -> &mut String {
+        &mut self.frames.last_mut().unwrap().1
+    }
+    fn in_frame(&self, f: fn(&Frame) -> bool) -> bool {
+        self.frames.iter().any(|(frame, _)| f(frame))
+    }
+    fn is_verbatim(&self) -> bool {
+        self.in_frame(|f| matches!(f, Frame::Pre | Frame::Code))
+    }
+    fn at_line_start(&mut self) -> bool {
+        let buf = self.buf();
+        buf.is_empty() || buf.ends_with('\n')
+    }
+    /// End the current block with an empty line.
+    fn block(&mut self) {
+        if !self.lists.is_empty() {
+            return self.line();
+        }
+        let buf = self.buf();
+        let trimmed = buf.trim_end_matches([' ', '\n']).len();
+        buf.truncate(trimmed);
+        if !buf.is_empty() {
+            buf.push_str("\n\n");
+        }
+    }
+    fn line(&mut self) {
+        let buf = self.buf();
+        let trimmed = buf.trim_end_matches(' ').len();
+        buf.truncate(trimmed);
+        if !buf.is_empty() && !buf.ends_with('\n') {
+            buf.push('\n');
+        }
+    }
+    fn text(&mut self, text: &str) {
+        if self.skip.is_some() {
+            return;
+        }
+        if self.is_verbatim() {
+            let text = decode(text, false);
+            self.buf().push_str(&text);
+            return;
+        }
+        // Whitespace collapses like in HTML except that empty lines, which
+        // WordPress uses instead of paragraphs, are kept.
+        let re = Regex::new(r"\s+").unwrap();
+        let text = re.replace_all(text, |caps: &regex::Captures| {
+            if 2 <= caps[0].matches('\n').count() {
+                "\n\n"
+            } else {
+                " "
+            }
+        });
+        let mut text = decode(&text, true);
+        if self.at_line_start() {
+            text = text.trim_start().to_string();
+        }
+        if text.is_empty() {
+            return;
+        }
+        if let Some((Frame::Link { plain, .. }, _)) = self.frames.last_mut() {
+            plain.push_str(&text);
+        }
+        // Avoid text at the start of a line turning into a heading, quote or
+        // list. Link text follows the `[`.
+        let in_link = matches!(self.frames.last(), Some((Frame::Link { .. }, _)));
+        let at_line_start = !in_link && self.at_line_start();
+        let escaped = escape_markdown(&text)
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                if (0 < i || at_line_start) && line.starts_with(['#', '>', '-', '+']) {
+                    format!("\\{line}")
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.buf().push_str(&escaped);
+    }
+    fn open(&mut self, name: &str, tag: &str) {
+        match name {
+            "p" | "div" | "figure" | "figcaption" | "section" | "article" | "table" => self.block(),
+            "br" => match self.frames.last_mut() {
+                Some((Frame::Link { plain, .. }, buf)) => {
+                    plain.push(' ');
+                    buf.push(' ');
+                }
+                _ => self.buf().push_str("\\\n"),
+            },
+            "hr" => {
+                self.block();
+                self.buf().push_str("---");
+                self.block();
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.block();
+                let level = name[1..].parse().unwrap_or(1);
+                self.buf().push_str(&format!("{} ", "#".repeat(level)));
+            }
+            "strong" | "b" => self.buf().push_str("**"),
+            "em" | "i" => self.buf().push('*'),
+            "code" if !self.is_verbatim() => self.frames.push((Frame::Code, String::new())),
+            "pre" => {
+                self.block();
+                self.frames.push((Frame::Pre, String::new()));
+            }
+            "blockquote" => {
+                self.block();
+                self.frames.push((Frame::Quote, String::new()));
+            }
+            "ul" | "ol" => {
+                self.block();
+                self.lists.push((name == "ol", 1));
+            }
+            "li" => {
+                self.line();
+                let depth = self.lists.len().saturating_sub(1);
+                let indent = "   ".repeat(depth);
+                let marker = match self.lists.last_mut() {
+                    Some((true, number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "- ".to_string(),
+                };
+                self.buf().push_str(&format!("{indent}{marker}"));
+            }
+            "a" => {
+                let href = attribute(tag, "href").unwrap_or_default();
+                let plain = String::new();
+                self.frames
+                    .push((Frame::Link { href, plain }, String::new()));
+            }
+            "img" => {
+                let src = attribute(tag, "src").unwrap_or_default();
+                let alt = attribute(tag, "alt").unwrap_or_default();
+                let alt = escape_markdown(alt.trim());
+                self.buf().push_str(&format!("![{alt}]({src})"));
+            }
+            "script" | "style" => self.skip = Some(name.to_string()),
+            _ => (),
+        }
+    }
+    fn close(&mut self, name: &str) {
+        match name {
+            "p" | "div" | "figure" | "figcaption" | "section" | "article" | "table" => self.block(),
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "hr" => self.block(),
+            "strong" | "b" => self.buf().push_str("**"),
+            "em" | "i" => self.buf().push('*'),
+            "code" if matches!(self.frames.last(), Some((Frame::Code, _))) => {
+                let (_, code) = self.frames.pop().unwrap();
+                self.buf().push_str(&format!("`{code}`"));
+            }
+            "pre" if matches!(self.frames.last(), Some((Frame::Pre, _))) => {
+                let (_, code) = self.frames.pop().unwrap();
+                let code = code.trim_matches('\n');
+                self.buf().push_str(&format!("```\n{code}\n```"));
+                self.block();
+            }
+            "blockquote" if matches!(self.frames.last(), Some((Frame::Quote, _))) => {
+                let (_, quote) = self.frames.pop().unwrap();
+                let quote = quote
+                    .trim()
+                    .lines()
+                    .map(|line| format!("> {line}").trim_end().to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.buf().push_str(&quote);
+                self.block();
+            }
+            "ul" | "ol" => {
+                self.lists.pop();
+                self.block();
+            }
+            "li" => self.line(),
+            "a" if matches!(self.frames.last(), Some((Frame::Link { .. }, _))) => {
+                let (frame, text) = self.frames.pop().unwrap();
+                let Frame::Link { href, plain } = frame else {
+                    unreachable!()
+                };
+                let plain = plain.trim();
+                let text = text.trim();
+                // Mastodon shortens links in the text, but the full link is
+                // in the href.
+                let bare = href.split_once("://").map(|(_, rest)| rest);
+                let markdown = if href.is_empty() || href.starts_with('#') {
+                    text.to_string()
+                } else if plain == href || Some(plain) == bare || text.is_empty() {
+                    format!("<{href}>")
+                } else {
+                    format!("[{text}]({href})")
+                };
+                if let Some((Frame::Link { plain: outer, .. }, _)) = self.frames.last_mut() {
+                    outer.push_str(plain);
+                }
+                self.buf().push_str(&markdown);
+            }
+            _ => (),
+        }
+    }
+}
+
+/// Convert HTML to Markdown.
+pub fn html_to_markdown(html: &str) -> String {
+    let re = Regex::new(r"<!--[\s\S]*?-->|<[^>]*>|[^<]+").unwrap();
+    let mut converter = Converter {
+        frames: vec![(Frame::Root, String::new())],
+        lists: Vec::new(),
+        skip: None,
+    };
+    for token in re.find_iter(html) {
+        let token = token.as_str();
+        if token.starts_with("<!--") {
+            continue;
+        }
+        if !token.starts_with('<') {
+            converter.text(token);
+            continue;
+        }
+        let closing = token.starts_with("</");
+        let name = token
+            .trim_start_matches(['<', '/'])
+            .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+            .next()
+            .unwrap_or("")
+            .to_lowercase();
+        if let Some(skip) = &converter.skip {
+            if closing && *skip == name {
+                converter.skip = None;
+            }
+            continue;
+        }
+        if closing {
+            converter.close(&name);
+        } else {
+            converter.open(&name, token);
+        }
+    }
+    // Close anything that was left open.
+    while 1 < converter.frames.len() {
+        let (_, text) = converter.frames.pop().unwrap();
+        converter.buf().push_str(&text);
+    }
+    let (_, out) = converter.frames.pop().unwrap();
+    let re = Regex::new(r"\n{3,}").unwrap();
+    let out = out
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    re.replace_all(&out, "\n\n").trim().to_string()
+}
+
+#[test]
+fn test_mastodon_status() {
+    let html = r#"<p>Hello <a href="https://example.com/@bob" class="u-url mention">@<span>bob</span></a>, 5 * 3 costs $2 &amp; &lt;b&gt; isn&#39;t bold</p><p>Line<br />break <a href="https://example.com/a/b" rel="nofollow"><span class="invisible">https://</span><span class="">example.com/a/b</span><span class="invisible"></span></a></p><p><a href="https://example.com/tags/rust" class="mention hashtag" rel="tag">#<span>rust</span></a> # not a heading</p>"#;
+    let expected = "Hello [@bob](https://example.com/@bob), 5 \\* 3 costs \\$2 &amp; &lt;b&gt; isn't bold\n\nLine\\\nbreak <https://example.com/a/b>\n\n[#rust](https://example.com/tags/rust) # not a heading";
+    assert_eq!(html_to_markdown(html), expected);
+}
+
+#[test]
+fn test_blog_post() {
+    let html = indoc::indoc! {r#"
+        <!-- wp:heading -->
+        <h2>A <em>title</em></h2>
+        <!-- /wp:heading -->
+
+        First paragraph with <strong>bold</strong> and <code>a &lt; b</code>.
+
+        - not a list
+        <ul><li>One</li><li>Two <a href="https://example.com"><img src="/a.png" alt="A"/></a></li></ul>
+        <ol><li>First</li><li>Second</li></ol>
+        <blockquote><p>Quoted</p><p>twice</p></blockquote>
+        <pre><code>fn main() {
+            println!("&lt;hi&gt;");
+        }</code></pre>
+        <script>alert(1)</script>
+    "#};
+    let expected = indoc::indoc! {r#"
+        ## A *title*
+
+        First paragraph with **bold** and `a < b`.
+
+        \- not a list
+
+        - One
+        - Two [![A](/a.png)](https://example.com)
+
+        1. First
+        2. Second
+
+        > Quoted
+        >
+        > twice
+
+        ```
+        fn main() {
+            println!("<hi>");
+        }
+        ```"#};
+    assert_eq!(html_to_markdown(html), expected);
+}
@@ -0,0 +1,451 @@
+//! Import posts and files from other platforms.
+//!
+//! Each platform is a [`Source`] that reads its export into an [`Import`],
+//! which [`apply`] then inserts.
+use crate::data::Post;
+use crate::data::SqliteDateTime;
+use crate::files::File;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::page;
+use crate::redirects::Redirect;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::DefaultBodyLimit;
+use axum::extract::Multipart;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::Utc;
+use clap::Parser;
+use clap::Subcommand;
+use regex::Captures;
+use regex::Regex;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(Clone, Debug, Parser)]
+pub struct ImportArgs {
+    #[command(subcommand)]
+    pub source: ImportSource,
+    /// Only report what would be imported.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    #[arg(
+        long,
+        env = "FX_DATABASE_PATH",
+        default_value = "/data/db.sqlite",
+        global = true
+    )]
+    pub database_path: String,
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum ImportSource {
+    /// Import from a Mastodon account export (`archive-*.zip`).
+    Mastodon {
+        /// Path to the zip archive.
+        archive: String,
+    },
+    /// Import from a WordPress export (WXR).
+    Wordpress {
+        /// Path to the XML file from Tools → Export.
+        export: String,
+        /// Path to a copy of `wp-content/uploads` to import the attachments.
+        #[arg(long)]
+        uploads: Option<String>,
+    },
+    /// Import a directory of Markdown files with front matter.
+    ///
+    /// This reads Hugo and Jekyll sites as well as extracted fx backups.
+    Markdown {
+        /// Path to the site directory.
+        directory: String,
+    },
+}
+
+impl ImportSource {
+    pub fn source(&self) -> Result<Box<dyn Source>, String> {
+        let read = |path: &str| match std::fs::read(path) {
+            Ok(data) => Ok(Bytes::from(data)),
+            Err(e) => Err(format!("{path}: {e}")),
+        };
+        Ok(match self {
+            ImportSource::Mastodon { archive } => {
+                Box::new(crate::mastodon::MastodonExport::new(read(archive)?))
+            }
+            ImportSource::Wordpress { export, uploads } => {
+                let uploads = uploads.as_ref().map(PathBuf::from);
+                Box::new(crate::wordpress::WordpressExport::new(
+                    read(export)?,
+                    uploads,
+                ))
+            }
+            ImportSource::Markdown { directory } => {
+                Box::new(crate::markdown_dir::MarkdownDirectory::new(directory))
+            }
+        })
+    }
+}
+
+/// A platform that posts and files can be imported from.
+pub trait Source {
+    /// Read the posts and files.
+    ///
+    /// Items that cannot be imported are added to [`Import::skipped`] and only
+    /// an unreadable export as a whole is an error.
+    fn read(&self) -> Result<Import, String>;
+}
+
+/// A post that is ready to be inserted.
+#[derive(Debug)]
+pub struct ImportedPost {
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub content: String,
+    /// Where the post was on the old site.
+    pub old_url: Option<String>,
+    /// The slug on the old site, which is kept so the new URL looks the same.
+    pub slug: Option<String>,
+}
+
+/// A file that is ready to be inserted.
+#[derive(Debug)]
+pub struct ImportedFile {
+    pub file: File,
+    /// Link targets by which the posts refer to the file.
+    ///
+    /// These are replaced by the path of the file in fx.
+    pub old_urls: Vec<String>,
+}
+
+/// Everything that was read from an export.
+#[derive(Debug, Default)]
+pub struct Import {
+    pub posts: Vec<ImportedPost>,
+    pub files: Vec<ImportedFile>,
+    /// Items that were not imported and why.
+    pub skipped: Vec<String>,
+}
+
+/// Where an old URL should point to.
+///
+/// Stored as a `Redirect` once the post exists.
+#[derive(Debug, Serialize)]
+pub struct ImportedUrl {
+    pub from: String,
+    /// `None` for posts that would be created in a dry run.
+    pub to: Option<String>,
+}
+
+/// What was (or, in a dry run, would be) imported.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Ids of the created posts (empty in a dry run).
+    pub posts_created: Vec<i64>,
+    /// Number of posts that were created or would be created.
+    pub posts_imported: usize,
+    /// Posts with the same timestamp and content that already exist.
+    pub posts_existing: usize,
+    /// Shas of files that did not exist.
+    pub files_added: Vec<String>,
+    pub files_existing: usize,
+    pub skipped: Vec<String>,
+    pub redirects: Vec<ImportedUrl>,
+}
+
+impl std::fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prefix = if self.dry_run {
+            "Would import"
+        } else {
+            "Imported"
+        };
+        writeln!(f, "{prefix}:")?;
+        writeln!(f, "  posts: {}", self.posts_imported)?;
+        writeln!(f, "  posts already present: {}", self.posts_existing)?;
+        writeln!(f, "  files added: {:?}", self.files_added)?;
+        writeln!(f, "  files already present: {}", self.files_existing)?;
+        write!(f, "  skipped: {}", self.skipped.len())?;
+        for reason in &self.skipped {
+            write!(f, "\n    {reason}")?;
+        }
+        if !self.redirects.is_empty() {
+            write!(f, "\n  redirects: {}", self.redirects.len())?;
+        }
+        for redirect in &self.redirects {
+            let to = redirect.to.as_deref().unwrap_or("(new post)");
+            write!(f, "\n    {} -> {to}", redirect.from)?;
+        }
+        Ok(())
+    }
+}
+
+/// Replace the targets of Markdown links and images and of HTML `src` and
+/// `href` attributes.
+///
+/// Targets for which `f` returns `None` are kept.
+pub fn map_links(content: &str, mut f: impl FnMut(&str) -> Option<String>) -> String {
+    let re = Regex::new(r#"(\]\(\s*<?|\b(?:src|href)\s*=\s*["'])([^)\s>"']+)"#).unwrap();
+    re.replace_all(content, |caps: &Captures| match f(&caps[2]) {
+        Some(target) => format!("{}{target}", &caps[1]),
+        None => caps[0].to_string(),
+    })
+    .to_string()
+}
+
+/// Return the id of a post with the same timestamp and content.
+fn existing_post(conn: &Connection, post: &ImportedPost) -> rusqlite::Result<Option<i64>> {
+    let stmt = "SELECT id FROM posts WHERE created = ? AND content = ?";
+    let created = post.created.to_sqlite();
+    let content = crate::data::cleanup_content(&post.content);
+    conn.query_row(stmt, [created, content], |row| row.get(0))
+        .optional()
+}
+
+/// Insert the imported posts and files.
+///
+/// Posts that already exist are skipped, so importing the same export twice
+/// does not create duplicates. Links in the posts to the old locations of the
+/// files are replaced by links to the imported files, and the old paths are
+/// added to the redirects.
+pub fn apply(
+    conn: &mut Connection,
+    import: Import,
+    dry_run: bool,
+) -> rusqlite::Result<ImportReport> {
+    let mut report = ImportReport {
+        dry_run,
+        skipped: import.skipped,
+        ..Default::default()
+    };
+    let tx = conn.transaction()?;
+    let mut links = HashMap::new();
+    for imported in &import.files {
+        let file = &imported.file;
+        let path = crate::files::path(file);
+        for old_url in &imported.old_urls {
+            links.insert(old_url.clone(), path.clone());
+            report.redirects.push(ImportedUrl {
+                from: old_url.clone(),
+                to: Some(path.clone()),
+            });
+        }
+        if File::get(&tx, &file.sha).is_ok() {
+            report.files_existing += 1;
+            continue;
+        }
+        report.files_added.push(file.sha.clone());
+        if !dry_run {
+            File::insert(&tx, file)?;
+        }
+    }
+    for mut post in import.posts {
+        post.content = map_links(&post.content, |target| links.get(target).cloned());
+        let id = match existing_post(&tx, &post)? {
+            Some(id) => {
+                report.posts_existing += 1;
+                Some(id)
+            }
+            None => {
+                report.posts_imported += 1;
+                if dry_run {
+                    None
+                } else {
+                    let id = Post::insert(
+                        &tx,
+                        post.created,
+                        post.updated,
+                        &post.content,
+                        post.slug.as_deref(),
+                    )?;
+                    report.posts_created.push(id);
+                    Some(id)
+                }
+            }
+        };
+        if let Some(from) = post.old_url {
+            let to = id.map(|id| format!("/posts/{id}"));
+            report.redirects.push(ImportedUrl { from, to });
+        }
+    }
+    if !dry_run {
+        // Keep the old links working when the old domain now points to fx.
+        for redirect in &report.redirects {
+            let Some(to) = &redirect.to else { continue };
+            if let Ok(redirect) = Redirect::new(&redirect.from, to, 301) {
+                Redirect::insert(&tx, &redirect)?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+/// Import from the command line.
+pub fn run(args: &ImportArgs) {
+    let import = match args.source.source().and_then(|source| source.read()) {
+        Ok(import) => import,
+        Err(e) => {
+            eprintln!("Failed to read export: {e}");
+            std::process::exit(1);
+        }
+    };
+    let mut conn = Connection::open(&args.database_path).expect("Failed to open database");
+    crate::data::init_tables(&conn);
+    match apply(&mut conn, import, args.dry_run) {
+        Ok(report) => println!("{report}"),
+        Err(e) => {
+            eprintln!("Failed to import: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn get_import(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let body = "
+        <form method='post' action='/import' \
+          class='margin-auto' \
+          enctype='multipart/form-data' \
+          style='margin-top: 5vh; width: 80%;'>
+            <div>
+                <label for='source'>Platform</label><br>
+                <select id='source' name='source'>
+                    <option value='mastodon'>Mastodon export (archive-*.zip)</option>
+                    <option value='wordpress'>WordPress export (*.xml)</option>
+                </select>
+            </div>
+            <br>
+            <div>
+                <label for='archive'>Export</label><br>
+                <input type='file' id='archive' name='archive' accept='.zip,.xml' required />
+                <br>
+                <span style='font-size: 0.8rem; line-height: 1.2; display: inline-block;'>
+                    On Mastodon, request the export at <i>Preferences</i> →
+                    <i>Import and export</i> → <i>Request your archive</i>. Public posts
+                    are imported with their original dates and media. Boosts, replies
+                    to others and non-public posts are skipped.
+                    On WordPress, download the export at <i>Tools</i> → <i>Export</i>.
+                    Published posts and pages are imported, but attachments only with
+                    <code>fx import wordpress --uploads</code> on the command line.
+                    Posts that were already imported are not imported again.
+                </span>
+            </div>
+            <br>
+            <div>
+                <input type='checkbox' id='dry_run' name='dry_run' style='margin-left: 0;'/>
+                <label for='dry_run'>Dry run (only show what would be imported)</label>
+            </div>
+            <br>
+            <div>
+                <input style='margin-left: 0;' type='submit' value='Import'/>
+            </div>
+        </form>
+    ";
+    let page_settings =
+        PageSettings::new("Import", Some(is_logged_in), None, false, Top::GoHome, "");
+    let body = page(&ctx, &page_settings, body).await;
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+async fn post_import(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    mut multipart: Multipart,
+) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let mut data = None;
+    let mut platform = "mastodon".to_string();
+    let mut dry_run = false;
+    while let Ok(Some(field)) = multipart.next_field().await {
+        match field.name() {
+            Some("archive") => match field.bytes().await {
+                Ok(bytes) => data = Some(bytes),
+                Err(e) => {
+                    let msg = format!("Failed to read the upload: {e}");
+                    return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", &msg)
+                        .await;
+                }
+            },
+            Some("source") => platform = field.text().await.unwrap_or_default(),
+            Some("dry_run") => dry_run = true,
+            name => tracing::warn!("unknown field: {:?}", name),
+        }
+    }
+    let data = match data {
+        Some(data) => data,
+        None => {
+            let msg = "No export was uploaded";
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", msg).await;
+        }
+    };
+    let source: Box<dyn Source + Send> = match platform.as_str() {
+        "mastodon" => Box::new(crate::mastodon::MastodonExport::new(data)),
+        "wordpress" => Box::new(crate::wordpress::WordpressExport::new(data, None)),
+        _ => {
+            let msg = format!("Unknown platform: {platform}");
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", &msg).await;
+        }
+    };
+    let result = tokio::task::spawn_blocking({
+        let ctx = ctx.clone();
+        move || {
+            let import = source.read()?;
+            apply(&mut ctx.conn(), import, dry_run).map_err(|e| e.to_string())
+        }
+    })
+    .await
+    .unwrap();
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            tracing::warn!("import failed: {e}");
+            let msg = format!("Failed to import: {e}");
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", &msg).await;
+        }
+    };
+    if !report.posts_created.is_empty() || !report.files_added.is_empty() {
+        // Imported posts are not sent to followers since they are old.
+        crate::trigger::trigger_github_backup(&ctx).await;
+    }
+    let report = crate::html::escape_html(&report.to_string());
+    let body = format!("<pre style='margin-top: 5vh;'>{report}</pre>");
+    let page_settings =
+        PageSettings::new("Import", Some(is_logged_in), None, false, Top::GoHome, "");
+    let body = page(&ctx, &page_settings, &body).await;
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+/// The largest export that can be uploaded via the form.
+const MAX_UPLOAD: usize = 256 * 1024 * 1024;
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    // Exports contain all media, so allow larger uploads than for files. The
+    // upload and the imported files are both held in memory, so larger
+    // exports have to be imported with `fx import`.
+    let limit = DefaultBodyLimit::max(MAX_UPLOAD);
+    router
+        .clone()
+        .route("/import", get(get_import))
+        .route("/import", post(post_import).layer(limit))
+}
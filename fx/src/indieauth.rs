@@ -0,0 +1,530 @@
+//! IndieAuth authorization server, see <https://indieauth.spec.indieweb.org/>.
+//!
+//! This allows signing in to IndieWeb services with the site URL and allows
+//! Micropub clients to obtain a token without knowing the admin password.
+use crate::data::SqliteDateTime;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use crate::serve::response_json;
+use axum::Form;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use base64::Engine;
+use chrono::DateTime;
+use chrono::Duration;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::Result;
+use rusqlite::params;
+use serde::Deserialize;
+use serde_json::json;
+use sha2::Digest;
+
+/// How long an authorization code can be exchanged.
+const CODE_LIFETIME_MINUTES: i64 = 10;
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes).expect("failed to generate random bytes");
+    hex::encode(bytes)
+}
+
+/// Tokens are stored hashed so that a leaked database does not leak tokens.
+fn hash(token: &str) -> String {
+    hex::encode(sha2::Sha256::digest(token.as_bytes()))
+}
+
+/// Return the PKCE challenge for the verifier with the `S256` method.
+fn code_challenge(verifier: &str) -> String {
+    let digest = sha2::Sha256::digest(verifier.as_bytes());
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(digest)
+}
+
+#[test]
+fn test_code_challenge() {
+    // Example from RFC 7636 Appendix B.
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+    assert_eq!(code_challenge(verifier), challenge);
+}
+
+/// An authorization code that the client exchanges for a profile or token.
+#[derive(Clone, Debug)]
+pub struct AuthCode {
+    pub code: String,
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub scope: String,
+    pub code_challenge: String,
+    pub created: DateTime<Utc>,
+}
+
+impl AuthCode {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS auth_codes (
+                code TEXT PRIMARY KEY,
+                client_id TEXT NOT NULL,
+                redirect_uri TEXT NOT NULL,
+                scope TEXT NOT NULL,
+                code_challenge TEXT NOT NULL,
+                created DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    pub fn insert(&self, conn: &Connection) -> Result<usize> {
+        let stmt = "
+            INSERT INTO auth_codes
+              (code, client_id, redirect_uri, scope, code_challenge, created)
+            VALUES (?, ?, ?, ?, ?, ?);
+        ";
+        let params = params![
+            hash(&self.code),
+            self.client_id,
+            self.redirect_uri,
+            self.scope,
+            self.code_challenge,
+            self.created.to_sqlite(),
+        ];
+        conn.execute(stmt, params)
+    }
+    /// Return the code and remove it since codes can only be used once.
+    pub fn take(conn: &Connection, code: &str) -> Result<Self> {
+        let stmt = "
+            SELECT client_id, redirect_uri, scope, code_challenge, created
+            FROM auth_codes WHERE code = ?;
+        ";
+        let code_hash = hash(code);
+        let auth_code = conn.query_row(stmt, [&code_hash], |row| {
+            let created: String = row.get("created")?;
+            Ok(AuthCode {
+                code: code.to_string(),
+                client_id: row.get("client_id")?,
+                redirect_uri: row.get("redirect_uri")?,
+                scope: row.get("scope")?,
+                code_challenge: row.get("code_challenge")?,
+                created: DateTime::from_sqlite(&created),
+            })
+        })?;
+        conn.execute("DELETE FROM auth_codes WHERE code = ?", [&code_hash])?;
+        Ok(auth_code)
+    }
+}
+
+/// An access token that was issued to a client.
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub client_id: String,
+    pub scope: String,
+    pub created: DateTime<Utc>,
+}
+
+impl Token {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS tokens (
+                token TEXT PRIMARY KEY,
+                client_id TEXT NOT NULL,
+                scope TEXT NOT NULL,
+                created DATETIME NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    pub fn insert(&self, conn: &Connection, token: &str) -> Result<usize> {
+        let stmt = "
+            INSERT INTO tokens (token, client_id, scope, created)
+            VALUES (?, ?, ?, ?);
+        ";
+        let params = params![
+            hash(token),
+            self.client_id,
+            self.scope,
+            self.created.to_sqlite()
+        ];
+        conn.execute(stmt, params)
+    }
+    pub fn get(conn: &Connection, token: &str) -> Result<Self> {
+        let stmt = "SELECT client_id, scope, created FROM tokens WHERE token = ?";
+        conn.query_row(stmt, [hash(token)], |row| {
+            let created: String = row.get("created")?;
+            Ok(Token {
+                client_id: row.get("client_id")?,
+                scope: row.get("scope")?,
+                created: DateTime::from_sqlite(&created),
+            })
+        })
+    }
+    pub fn delete(conn: &Connection, token: &str) -> Result<usize> {
+        conn.execute("DELETE FROM tokens WHERE token = ?", [hash(token)])
+    }
+}
+
+/// The URL that identifies the site owner.
+fn me(ctx: &ServerContext) -> String {
+    format!("{}/", ctx.base_url())
+}
+
+fn error(
+    ctx: &ServerContext,
+    status: StatusCode,
+    error: &str,
+    description: &str,
+) -> Response<Body> {
+    let body = json!({
+        "error": error,
+        "error_description": description,
+    })
+    .to_string();
+    response_json(status, body, ctx)
+}
+
+fn invalid_request(ctx: &ServerContext, description: &str) -> Response<Body> {
+    error(ctx, StatusCode::BAD_REQUEST, "invalid_request", description)
+}
+
+fn origin(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return None;
+    }
+    Some(url.origin().ascii_serialization())
+}
+
+async fn get_metadata(State(ctx): State<ServerContext>) -> Response<Body> {
+    let base = ctx.base_url();
+    let body = json!({
+        "issuer": me(&ctx),
+        "authorization_endpoint": format!("{base}/auth"),
+        "token_endpoint": format!("{base}/token"),
+        "revocation_endpoint": format!("{base}/token/revoke"),
+        "code_challenge_methods_supported": ["S256"],
+        "grant_types_supported": ["authorization_code"],
+        "response_types_supported": ["code"],
+        "scopes_supported": ["profile", "create", "update", "delete", "media"],
+    });
+    response_json(StatusCode::OK, body.to_string(), &ctx)
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthorizationRequest {
+    response_type: Option<String>,
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+    #[serde(default)]
+    scope: String,
+}
+
+impl AuthorizationRequest {
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.response_type.as_deref().unwrap_or("code") != "code" {
+            return Err("response_type should be code");
+        }
+        let client = origin(&self.client_id).ok_or("invalid client_id")?;
+        let redirect = origin(&self.redirect_uri).ok_or("invalid redirect_uri")?;
+        // Otherwise, the client information would have to be fetched to see
+        // whether the redirect is allowed.
+        if client != redirect {
+            return Err("redirect_uri should be on the same host as client_id");
+        }
+        if self
+            .code_challenge
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+        {
+            return Err("code_challenge is required");
+        }
+        if self.code_challenge_method.as_deref() != Some("S256") {
+            return Err("code_challenge_method should be S256");
+        }
+        Ok(())
+    }
+}
+
+fn hidden(name: &str, value: &str) -> String {
+    let value = escape_html(value);
+    format!("<input type='hidden' name='{name}' value='{value}'/>")
+}
+
+async fn get_auth(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    uri: Uri,
+    Query(request): Query<AuthorizationRequest>,
+) -> Response<Body> {
+    if let Err(e) = request.validate() {
+        let status = StatusCode::BAD_REQUEST;
+        return crate::serve::error(&ctx, status, "Bad Request", e).await;
+    }
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    let body = if is_logged_in {
+        let client_id = escape_html(&request.client_id);
+        let redirect_uri = escape_html(&request.redirect_uri);
+        let scope = if request.scope.is_empty() {
+            "sign in only".to_string()
+        } else {
+            escape_html(&request.scope)
+        };
+        format!(
+            "
+            <div style='margin-top: 5vh;'>
+                <p>
+                    <a href='{client_id}'>{client_id}</a> wants to sign in as
+                    <code>{}</code>.
+                </p>
+                <p>Scope: <code>{scope}</code></p>
+                <p>You will be redirected to <code>{redirect_uri}</code>.</p>
+                <form action='/auth/approve' method='post'>
+                    {}
+                    {}
+                    {}
+                    {}
+                    {}
+                    <button type='submit'>Approve</button>
+                </form>
+            </div>
+            ",
+            escape_html(&me(&ctx)),
+            hidden("client_id", &request.client_id),
+            hidden("redirect_uri", &request.redirect_uri),
+            hidden("state", &request.state),
+            hidden("code_challenge", request.code_challenge.as_deref().unwrap()),
+            hidden("scope", &request.scope),
+        )
+    } else {
+        // Return to this page after logging in since the request is in the query.
+        let next = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/auth");
+        let query = serde_urlencoded::to_string([("next", next)]).unwrap();
+        let login = escape_html(&format!("/login?{query}"));
+        format!(
+            "
+            <div style='margin-top: 5vh; text-align: center;'>
+                <p>Log in to approve this sign in request.</p>
+                <a href='{login}'>Log in</a>
+            </div>
+            "
+        )
+    };
+    let settings = PageSettings::new("Sign in", Some(is_logged_in), None, false, Top::GoHome, "");
+    let body = page(&ctx, &settings, &body).await;
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+#[derive(Debug, Deserialize)]
+struct ApproveForm {
+    client_id: String,
+    redirect_uri: String,
+    state: String,
+    code_challenge: String,
+    #[serde(default)]
+    scope: String,
+}
+
+async fn post_approve(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    Form(form): Form<ApproveForm>,
+) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let request = AuthorizationRequest {
+        response_type: None,
+        client_id: form.client_id,
+        redirect_uri: form.redirect_uri,
+        state: form.state,
+        code_challenge: Some(form.code_challenge),
+        code_challenge_method: Some("S256".to_string()),
+        scope: form.scope,
+    };
+    if let Err(e) = request.validate() {
+        let status = StatusCode::BAD_REQUEST;
+        return crate::serve::error(&ctx, status, "Bad Request", e).await;
+    }
+    let code = random_token();
+    let auth_code = AuthCode {
+        code: code.clone(),
+        client_id: request.client_id,
+        redirect_uri: request.redirect_uri.clone(),
+        scope: request.scope,
+        code_challenge: request.code_challenge.unwrap(),
+        created: Utc::now(),
+    };
+    if let Err(e) = auth_code.insert(&ctx.conn()) {
+        tracing::error!("Failed to store authorization code: {e}");
+        let msg = "Failed to store authorization code";
+        return crate::serve::internal_server_error(&ctx, msg).await;
+    }
+    let mut url = match reqwest::Url::parse(&request.redirect_uri) {
+        Ok(url) => url,
+        Err(_) => return invalid_request(&ctx, "invalid redirect_uri"),
+    };
+    url.query_pairs_mut()
+        .append_pair("code", &code)
+        .append_pair("state", &request.state)
+        .append_pair("iss", &me(&ctx));
+    crate::serve::see_other(&ctx, url.as_str())
+}
+
+#[derive(Debug, Deserialize)]
+struct RedeemForm {
+    grant_type: Option<String>,
+    code: String,
+    client_id: String,
+    redirect_uri: String,
+    code_verifier: String,
+}
+
+/// Verify the code and return its details.
+///
+/// On failure, returns the OAuth error code and description.
+fn redeem(
+    ctx: &ServerContext,
+    form: &RedeemForm,
+) -> Result<AuthCode, (&'static str, &'static str)> {
+    if form.grant_type.as_deref().unwrap_or("authorization_code") != "authorization_code" {
+        let description = "grant_type should be authorization_code";
+        return Err(("unsupported_grant_type", description));
+    }
+    let auth_code = match AuthCode::take(&ctx.conn(), &form.code) {
+        Ok(auth_code) => auth_code,
+        Err(_) => return Err(("invalid_grant", "invalid code")),
+    };
+    if auth_code.created + Duration::minutes(CODE_LIFETIME_MINUTES) < Utc::now() {
+        return Err(("invalid_grant", "code expired"));
+    }
+    if auth_code.client_id != form.client_id || auth_code.redirect_uri != form.redirect_uri {
+        let description = "client_id or redirect_uri does not match";
+        return Err(("invalid_grant", description));
+    }
+    if code_challenge(&form.code_verifier) != auth_code.code_challenge {
+        return Err(("invalid_grant", "code_verifier does not match"));
+    }
+    Ok(auth_code)
+}
+
+/// Exchange the code for the profile URL only.
+async fn post_auth(
+    State(ctx): State<ServerContext>,
+    Form(form): Form<RedeemForm>,
+) -> Response<Body> {
+    if let Err((e, description)) = redeem(&ctx, &form) {
+        return error(&ctx, StatusCode::BAD_REQUEST, e, description);
+    }
+    let body = json!({ "me": me(&ctx) });
+    response_json(StatusCode::OK, body.to_string(), &ctx)
+}
+
+/// Exchange the code for an access token.
+async fn post_token(
+    State(ctx): State<ServerContext>,
+    Form(form): Form<RedeemForm>,
+) -> Response<Body> {
+    let auth_code = match redeem(&ctx, &form) {
+        Ok(auth_code) => auth_code,
+        Err((e, description)) => return error(&ctx, StatusCode::BAD_REQUEST, e, description),
+    };
+    if auth_code.scope.is_empty() {
+        let description = "no scope was requested so no token can be issued";
+        return invalid_request(&ctx, description);
+    }
+    let access_token = random_token();
+    let token = Token {
+        client_id: auth_code.client_id,
+        scope: auth_code.scope,
+        created: Utc::now(),
+    };
+    if let Err(e) = token.insert(&ctx.conn(), &access_token) {
+        tracing::error!("Failed to store token: {e}");
+        let msg = "failed to store token";
+        return error(&ctx, StatusCode::INTERNAL_SERVER_ERROR, "server_error", msg);
+    }
+    tracing::info!("Issued token to {}", token.client_id);
+    let body = json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "scope": token.scope,
+        "me": me(&ctx),
+    });
+    response_json(StatusCode::OK, body.to_string(), &ctx)
+}
+
+/// Verify a token for a resource server.
+async fn get_token(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
+    let token =
+        crate::api::bearer_token(&headers).and_then(|token| Token::get(&ctx.conn(), token).ok());
+    match token {
+        Some(token) => {
+            let body = json!({
+                "me": me(&ctx),
+                "client_id": token.client_id,
+                "scope": token.scope,
+            });
+            response_json(StatusCode::OK, body.to_string(), &ctx)
+        }
+        None => error(
+            &ctx,
+            StatusCode::UNAUTHORIZED,
+            "invalid_token",
+            "invalid token",
+        ),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeForm {
+    token: String,
+}
+
+async fn post_revoke(
+    State(ctx): State<ServerContext>,
+    Form(form): Form<RevokeForm>,
+) -> Response<Body> {
+    // Revocation responds with success even for unknown tokens.
+    if let Err(e) = Token::delete(&ctx.conn(), &form.token) {
+        tracing::error!("Failed to revoke token: {e}");
+    }
+    response(StatusCode::OK, HeaderMap::new(), "", &ctx)
+}
+
+/// Whether the token was issued via IndieAuth and is still valid.
+pub fn is_valid_token(ctx: &ServerContext, token: &str) -> bool {
+    Token::get(&ctx.conn(), token).is_ok()
+}
+
+/// Whether the token was issued via IndieAuth with the scope.
+pub fn has_scope(ctx: &ServerContext, token: &str, scope: &str) -> bool {
+    Token::get(&ctx.conn(), token)
+        .is_ok_and(|token| token.scope.split_ascii_whitespace().any(|s| s == scope))
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/.well-known/oauth-authorization-server", get(get_metadata))
+        .route("/auth", get(get_auth))
+        .route("/auth", post(post_auth))
+        .route("/auth/approve", post(post_approve))
+        .route("/token", get(get_token))
+        .route("/token", post(post_token))
+        .route("/token/revoke", post(post_revoke))
+}
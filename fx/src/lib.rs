@@ -1,16 +1,36 @@
-mod ap;
+pub mod ap;
 mod api;
 pub mod blogroll;
+mod changes;
 pub mod data;
+pub mod delivery;
 mod discovery;
+mod feed;
 mod files;
+mod front_matter;
 pub mod health;
 pub mod html;
+mod html_md;
+pub mod import;
+mod indieauth;
+mod markdown_dir;
+mod mastodon;
 mod md;
+mod micropub;
+mod net;
+mod og;
+mod openapi;
+mod redirects;
+mod replies;
+pub mod restore;
 mod search;
 pub mod serve;
 mod settings;
+pub mod signature;
+pub mod static_site;
 mod trigger;
+mod webmention;
+mod wordpress;
 
 use clap::Parser;
 
@@ -37,6 +57,10 @@ pub struct ServeArgs {
     /// The logging level.
     #[arg(long, env = "FX_LOG_LEVEL", default_value = "info")]
     pub log_level: String,
+    /// Allow ActivityPub and Webmention requests to loopback and private
+    /// addresses, for example to federate with a server on the local network.
+    #[arg(long, env = "FX_ALLOW_PRIVATE_ADDRESSES")]
+    pub allow_private_addresses: bool,
 
     /// The token for triggering GitHub Actions.
     #[arg(long, env = "FX_TRIGGER_TOKEN")]
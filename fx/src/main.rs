@@ -1,6 +1,9 @@
 use clap::Parser;
 use fx::ServeArgs;
 use fx::health::HealthArgs;
+use fx::import::ImportArgs;
+use fx::restore::RestoreArgs;
+use fx::static_site::ExportStaticArgs;
 use tracing::Level;
 use tracing::subscriber::SetGlobalDefaultError;
 
@@ -9,8 +12,14 @@ use tracing::subscriber::SetGlobalDefaultError;
 enum Task {
     /// Run a health check on the given port.
     CheckHealth(HealthArgs),
+    /// Render the public pages to a directory that can be hosted statically.
+    ExportStatic(ExportStaticArgs),
+    /// Import posts and files from another platform.
+    Import(ImportArgs),
     /// Print the project's license.
     License,
+    /// Restore posts, settings and files from an `all.tar.xz` archive.
+    Restore(RestoreArgs),
     /// Start the server.
     Serve(ServeArgs),
 }
@@ -49,10 +58,22 @@ async fn main() {
         Task::CheckHealth(args) => {
             fx::health::check_health(args).await;
         }
+        Task::ExportStatic(export_args) => {
+            init_subscriber(Level::WARN, args.ansi.unwrap_or(true)).unwrap();
+            fx::static_site::run(export_args).await;
+        }
+        Task::Import(import_args) => {
+            init_subscriber(Level::WARN, args.ansi.unwrap_or(true)).unwrap();
+            fx::import::run(import_args);
+        }
         Task::License => {
             let license_content = include_str!("../../LICENSE");
             println!("{}", license_content);
         }
+        Task::Restore(restore_args) => {
+            init_subscriber(Level::WARN, args.ansi.unwrap_or(true)).unwrap();
+            fx::restore::run(restore_args);
+        }
         Task::Serve(serve_args) => {
             let log_level = match serve_args.log_level.as_str() {
                 "error" => Level::ERROR,
@@ -0,0 +1,295 @@
+//! Read directories of Markdown files with front matter.
+//!
+//! This covers Hugo (`content/`, TOML or YAML front matter), Jekyll
+//! (`_posts/YYYY-MM-DD-slug.md`) and extracted fx backups (`posts/{id}.md`
+//! with `files/index.toml`). Other files are imported when a post links to
+//! them.
+use crate::files::File;
+use crate::front_matter::FrontMatter;
+use crate::import::Import;
+use crate::import::ImportedFile;
+use crate::import::ImportedPost;
+use crate::import::Source;
+use crate::restore::FileIndex;
+use crate::restore::parse_datetime;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::Utc;
+use std::collections::BTreeSet;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Directories with generated output or tooling instead of content.
+const IGNORED_DIRS: &[&str] = &["_site", "public", "resources", "themes", "node_modules"];
+
+/// Return the paths of all files relative to `root` with `/` as separator.
+fn walk(root: &Path, rel: &str, paths: &mut Vec<String>) -> Result<(), String> {
+    let dir = root.join(rel);
+    let entries = std::fs::read_dir(&dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+    let mut entries = entries.filter_map(|entry| entry.ok()).collect::<Vec<_>>();
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+        let path = if rel.is_empty() {
+            name.clone()
+        } else {
+            format!("{rel}/{name}")
+        };
+        let file_type = entry.file_type().map_err(|e| format!("{path}: {e}"))?;
+        if file_type.is_dir() {
+            if !IGNORED_DIRS.contains(&name.as_str()) {
+                walk(root, &path, paths)?;
+            }
+        } else if file_type.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_markdown(path: &str) -> bool {
+    path.ends_with(".md") || path.ends_with(".markdown")
+}
+
+/// Return the date in a Jekyll post filename (`YYYY-MM-DD-slug.md`).
+fn filename_date(filename: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(filename.get(..10)?, "%Y-%m-%d").ok()
+}
+
+/// Return where the post was on the old site.
+fn old_url(path: &str, front_matter: &FrontMatter) -> Option<String> {
+    if let Some(url) = front_matter.field(&["url", "permalink"]) {
+        // Jekyll permalinks can be patterns such as `/:year/:title/`.
+        if !url.contains("/:") {
+            return Some(url.to_string());
+        }
+    }
+    let stem = path
+        .strip_suffix(".md")
+        .or_else(|| path.strip_suffix(".markdown"))?;
+    if let Some(id) = stem.strip_prefix("posts/")
+        && id.parse::<i64>().is_ok()
+    {
+        return Some(format!("/posts/{id}"));
+    }
+    if let Some(page) = stem.strip_prefix("content/") {
+        let page = page.strip_suffix("/index").unwrap_or(page);
+        let page = match (front_matter.field(&["slug"]), page.rsplit_once('/')) {
+            (Some(slug), Some((section, _))) => format!("{section}/{slug}"),
+            (Some(slug), None) => slug.to_string(),
+            (None, _) => page.to_string(),
+        };
+        return Some(format!("/{page}/"));
+    }
+    if let Some((_, name)) = stem.split_once("_posts/") {
+        let name = name.rsplit('/').next()?;
+        let date = filename_date(name)?;
+        let slug = name.get(11..)?;
+        return Some(format!("/{}/{slug}.html", date.format("%Y/%m/%d")));
+    }
+    None
+}
+
+/// Resolve `a/../b` and `./b` in a relative path.
+fn normalize(path: &str) -> Option<String> {
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+/// Return the file that a link target in a post in `dir` refers to.
+fn resolve(target: &str, dir: &str, files: &BTreeSet<String>) -> Option<String> {
+    if target.contains("://") || target.starts_with("//") || target.starts_with("mailto:") {
+        return None;
+    }
+    let target = target.split(['?', '#']).next()?;
+    let options = match target.strip_prefix('/') {
+        // Hugo serves `static/` at the root.
+        Some(target) => vec![normalize(target)?, normalize(&format!("static/{target}"))?],
+        None => vec![normalize(&format!("{dir}/{target}"))?],
+    };
+    options.into_iter().find(|path| files.contains(path))
+}
+
+/// Return the URL at which the old site served the file.
+fn site_url(path: &str) -> String {
+    let path = path
+        .strip_prefix("static/")
+        .or_else(|| path.strip_prefix("content/"))
+        .unwrap_or(path);
+    format!("/{path}")
+}
+
+/// Read one post or return why it was skipped.
+fn read_post(
+    root: &Path,
+    path: &str,
+    files: &BTreeSet<String>,
+    linked: &mut BTreeSet<String>,
+) -> Result<ImportedPost, String> {
+    let text = std::fs::read_to_string(root.join(path)).map_err(|e| format!("{path}: {e}"))?;
+    let text = text.replace("\r\n", "\n");
+    let (front_matter, content) = crate::front_matter::split(&text);
+    if front_matter.fields.is_empty() {
+        return Err(format!("{path}: no front matter"));
+    }
+    let (dir, filename) = path.rsplit_once('/').unwrap_or(("", path));
+    if filename.starts_with("_index.") {
+        return Err(format!("{path}: section page"));
+    }
+    let draft = front_matter.field(&["draft"]) == Some("true")
+        || front_matter.field(&["published"]) == Some("false")
+        || path.starts_with("_drafts/");
+    if draft {
+        return Err(format!("{path}: draft"));
+    }
+    let created = match front_matter.field(&["created", "date"]) {
+        Some(date) => parse_datetime(date).ok_or_else(|| format!("{path}: invalid date"))?,
+        None => match filename_date(filename) {
+            Some(date) => date.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+            None => {
+                let metadata = std::fs::metadata(root.join(path));
+                let modified = metadata.and_then(|m| m.modified());
+                let modified = modified.map_err(|e| format!("{path}: {e}"))?;
+                DateTime::<Utc>::from(modified)
+            }
+        },
+    };
+    let updated = front_matter
+        .field(&["updated", "lastmod", "last_modified_at"])
+        .and_then(parse_datetime)
+        .filter(|updated| created <= *updated)
+        .unwrap_or(created);
+
+    let content = crate::import::map_links(content.trim(), |target| {
+        let file = resolve(target, dir, files)?;
+        let url = site_url(&file);
+        linked.insert(file);
+        Some(url)
+    });
+    let mut parts = Vec::new();
+    if let Some(title) = front_matter.field(&["title"])
+        && !content.starts_with("# ")
+    {
+        parts.push(format!("# {}", crate::html_md::escape_markdown(title)));
+    }
+    if !content.is_empty() {
+        parts.push(content);
+    }
+    if parts.is_empty() {
+        return Err(format!("{path}: empty"));
+    }
+    Ok(ImportedPost {
+        created,
+        updated,
+        content: parts.join("\n\n"),
+        old_url: old_url(path, &front_matter),
+        slug: front_matter.slug.clone(),
+    })
+}
+
+fn read_directory(root: &Path) -> Result<Import, String> {
+    let mut paths = Vec::new();
+    walk(root, "", &mut paths)?;
+    let (posts, files): (Vec<String>, Vec<String>) =
+        paths.into_iter().partition(|path| is_markdown(path));
+    let files = files.into_iter().collect::<BTreeSet<_>>();
+    // An extracted fx backup, whose files keep their sha.
+    let index = if files.contains("files/index.toml") {
+        let path = root.join("files/index.toml");
+        let text = std::fs::read_to_string(path).map_err(|e| format!("files/index.toml: {e}"))?;
+        Some(toml::from_str::<FileIndex>(&text).map_err(|e| format!("files/index.toml: {e}"))?)
+    } else {
+        None
+    };
+
+    let mut import = Import::default();
+    let mut linked = BTreeSet::new();
+    for path in &posts {
+        match read_post(root, path, &files, &mut linked) {
+            Ok(post) => import.posts.push(post),
+            Err(reason) => import.skipped.push(reason),
+        }
+    }
+    for path in &files {
+        let archived = index.as_ref().and_then(|index| {
+            let name = path.strip_prefix("files/")?;
+            index.entry(name).map(|_| (index, name))
+        });
+        if archived.is_none() && !linked.contains(path) {
+            continue;
+        }
+        let data = match std::fs::read(root.join(path)) {
+            Ok(data) => Bytes::from(data),
+            Err(e) => {
+                import.skipped.push(format!("{path}: {e}"));
+                continue;
+            }
+        };
+        let imported = match archived {
+            Some((index, name)) => ImportedFile {
+                file: crate::restore::archived_file(index, name, data),
+                old_urls: Vec::new(),
+            },
+            None => {
+                let filename = path.rsplit('/').next().unwrap_or(path);
+                let mime_type = crate::restore::guess_mime_type(filename);
+                ImportedFile {
+                    file: File::new(mime_type, filename, data),
+                    old_urls: vec![site_url(path)],
+                }
+            }
+        };
+        import.files.push(imported);
+    }
+    // Oldest first so that post ids increase with the dates.
+    import.posts.sort_by_key(|post| post.created);
+    Ok(import)
+}
+
+/// A directory with a Hugo or Jekyll site or an extracted fx backup.
+pub struct MarkdownDirectory {
+    path: PathBuf,
+}
+
+impl MarkdownDirectory {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Source for MarkdownDirectory {
+    fn read(&self) -> Result<Import, String> {
+        read_directory(&self.path)
+    }
+}
+
+#[test]
+fn test_old_url() {
+    let front_matter = FrontMatter::default();
+    let url = |path| old_url(path, &front_matter);
+    assert_eq!(url("posts/3.md").as_deref(), Some("/posts/3"));
+    assert_eq!(
+        url("content/posts/hello.md").as_deref(),
+        Some("/posts/hello/")
+    );
+    assert_eq!(
+        url("content/posts/bundle/index.md").as_deref(),
+        Some("/posts/bundle/")
+    );
+    let jekyll = url("_posts/2020-01-02-hello-world.markdown");
+    assert_eq!(jekyll.as_deref(), Some("/2020/01/02/hello-world.html"));
+    assert_eq!(url("README.md"), None);
+}
@@ -0,0 +1,186 @@
+//! Read Mastodon account exports.
+//!
+//! The export is a zip with the statuses as ActivityPub activities in
+//! `outbox.json` and the media in `media_attachments/`.
+use crate::files::File;
+use crate::html_md::escape_markdown;
+use crate::html_md::html_to_markdown;
+use crate::import::Import;
+use crate::import::ImportedFile;
+use crate::import::ImportedPost;
+use crate::import::Source;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::Utc;
+use serde_json::Value;
+use std::io::Cursor;
+use std::io::Read;
+use zip::ZipArchive;
+
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+type Zip<'a> = ZipArchive<Cursor<&'a [u8]>>;
+
+fn read_entry(zip: &mut Zip, name: &str) -> Result<Vec<u8>, String> {
+    let mut entry = zip
+        .by_name(name)
+        .map_err(|_| format!("{name} not found in the export"))?;
+    let mut data = Vec::new();
+    entry
+        .read_to_end(&mut data)
+        .map_err(|e| format!("failed to read {name}: {e}"))?;
+    Ok(data)
+}
+
+fn parse_datetime(value: &Value) -> Option<DateTime<Utc>> {
+    let text = value.as_str()?;
+    DateTime::parse_from_rfc3339(text)
+        .ok()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+fn is_public(activity: &Value) -> bool {
+    let object = &activity["object"];
+    ["to", "cc"].iter().any(|key| {
+        [&activity[key], &object[key]]
+            .iter()
+            .filter_map(|audience| audience.as_array())
+            .flatten()
+            .any(|audience| audience.as_str() == Some(PUBLIC))
+    })
+}
+
+/// Return the path in the export of a media attachment.
+///
+/// The url is usually `/media_attachments/...`, but servers that store media
+/// elsewhere use full URLs.
+fn media_path(url: &str) -> &str {
+    match url.find("media_attachments/") {
+        Some(start) => &url[start..],
+        None => url.trim_start_matches('/'),
+    }
+}
+
+fn read_attachment(zip: &mut Zip, attachment: &Value) -> Result<(File, String), String> {
+    let url = attachment["url"].as_str().unwrap_or("");
+    let path = media_path(url);
+    let data = read_entry(zip, path)?;
+    let mime_type = attachment["mediaType"]
+        .as_str()
+        .unwrap_or("application/octet-stream");
+    let filename = path.rsplit('/').next().unwrap_or(path);
+    let file = File::new(mime_type, filename, Bytes::from(data));
+    let description = attachment["name"].as_str().unwrap_or("").trim();
+    let link = if mime_type.starts_with("image/") && !description.is_empty() {
+        let alt = escape_markdown(&description.replace('\n', " "));
+        format!("![{alt}]({})", crate::files::path(&file))
+    } else {
+        crate::files::md_link(&file)
+    };
+    Ok((file, link))
+}
+
+/// Read one activity from the outbox or return why it was skipped.
+fn read_activity(zip: &mut Zip, activity: &Value, import: &mut Import) -> Result<(), String> {
+    let object = &activity["object"];
+    let id = object["id"]
+        .as_str()
+        .or(activity["id"].as_str())
+        .unwrap_or("unknown status");
+    match activity["type"].as_str() {
+        Some("Create") => (),
+        Some("Announce") => return Err(format!("{id}: boost")),
+        other => return Err(format!("{id}: unsupported activity {other:?}")),
+    }
+    let actor = activity["actor"].as_str().unwrap_or("");
+    if let Some(in_reply_to) = object["inReplyTo"].as_str() {
+        // Replies to own statuses are threads and are kept.
+        if actor.is_empty() || !in_reply_to.starts_with(&format!("{actor}/")) {
+            return Err(format!("{id}: reply to {in_reply_to}"));
+        }
+    }
+    if !is_public(activity) {
+        return Err(format!("{id}: not public"));
+    }
+    let created = parse_datetime(&object["published"])
+        .or_else(|| parse_datetime(&activity["published"]))
+        .ok_or_else(|| format!("{id}: invalid published date"))?;
+    let updated = parse_datetime(&object["updated"]).unwrap_or(created);
+
+    let mut parts = Vec::new();
+    if let Some(summary) = object["summary"].as_str().filter(|s| !s.trim().is_empty()) {
+        // The content warning.
+        parts.push(format!("**{}**", escape_markdown(summary.trim())));
+    }
+    let content = html_to_markdown(object["content"].as_str().unwrap_or(""));
+    if !content.is_empty() {
+        parts.push(content);
+    }
+    let attachments = object["attachment"].as_array().into_iter().flatten();
+    for attachment in attachments {
+        match read_attachment(zip, attachment) {
+            Ok((file, link)) => {
+                parts.push(link);
+                let old_urls = Vec::new();
+                import.files.push(ImportedFile { file, old_urls });
+            }
+            Err(e) => import.skipped.push(format!("{id}: media: {e}")),
+        }
+    }
+    if parts.is_empty() {
+        return Err(format!("{id}: empty"));
+    }
+    import.posts.push(ImportedPost {
+        created,
+        updated,
+        content: parts.join("\n\n"),
+        old_url: object["url"].as_str().map(str::to_string),
+        slug: None,
+    });
+    Ok(())
+}
+
+/// Read the public statuses and their media from a Mastodon export.
+fn read_export(data: &[u8]) -> Result<Import, String> {
+    let mut zip = ZipArchive::new(Cursor::new(data)).map_err(|e| format!("invalid zip: {e}"))?;
+    let outbox = read_entry(&mut zip, "outbox.json")?;
+    let outbox: Value =
+        serde_json::from_slice(&outbox).map_err(|e| format!("invalid outbox.json: {e}"))?;
+    let activities = outbox["orderedItems"]
+        .as_array()
+        .ok_or("outbox.json contains no orderedItems")?;
+    let mut import = Import::default();
+    for activity in activities {
+        if let Err(reason) = read_activity(&mut zip, activity, &mut import) {
+            import.skipped.push(reason);
+        }
+    }
+    // Oldest first so that post ids increase with the dates.
+    import.posts.sort_by_key(|post| post.created);
+    Ok(import)
+}
+
+/// A Mastodon account export.
+pub struct MastodonExport {
+    data: Bytes,
+}
+
+impl MastodonExport {
+    pub fn new(data: Bytes) -> Self {
+        Self { data }
+    }
+}
+
+impl Source for MastodonExport {
+    fn read(&self) -> Result<Import, String> {
+        read_export(&self.data)
+    }
+}
+
+#[test]
+fn test_media_path() {
+    let url = "/media_attachments/files/1/original/a.png";
+    assert_eq!(media_path(url), "media_attachments/files/1/original/a.png");
+    let url = "https://files.example.com/media_attachments/files/1/original/a.png";
+    assert_eq!(media_path(url), "media_attachments/files/1/original/a.png");
+}
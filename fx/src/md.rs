@@ -179,10 +179,39 @@ pub fn content_to_html(content: &str) -> String {
     markdown::to_html_with_options(content, &options).unwrap()
 }
 
+/// Return the URL of the first image in the content, if any.
+pub fn first_image(content: &str) -> Option<String> {
+    fn find(node: &Node) -> Option<String> {
+        match node {
+            Node::Image(image) => Some(image.url.clone()),
+            Node::Html(html) => {
+                let re = regex::Regex::new(r#"<img\s[^>]*src\s*=\s*["']([^"']+)["']"#).unwrap();
+                re.captures(&html.value)
+                    .map(|captures| captures[1].to_string())
+            }
+            _ => node.children()?.iter().find_map(find),
+        }
+    }
+    let tree = to_mdast(content, &parse_options()).ok()?;
+    find(&tree)
+}
+
+#[test]
+fn test_first_image() {
+    let content = "# Title\n\nText ![cat](/files/abc) and ![dog](/files/def)";
+    assert_eq!(first_image(content).as_deref(), Some("/files/abc"));
+    let content = "<p><img alt='x' src=\"https://example.com/a.png\"></p>";
+    assert_eq!(
+        first_image(content).as_deref(),
+        Some("https://example.com/a.png")
+    );
+    assert_eq!(first_image("```\n![no](/files/code)\n```"), None);
+}
+
 /// Prepare post to be shown as preview.
 pub fn preview(post: &mut Post, max_length: usize) {
     let options = parse_options();
-    let tree = to_mdast(&post.content, &options).unwrap();
+    let tree = to_mdast(post.body(), &options).unwrap();
     let mut preview = String::new();
     let slug = crate::md::extract_slug(post);
     for node in tree.children().unwrap() {
@@ -218,6 +247,7 @@ fn test_keep_link() {
         content: content.to_string(),
         created: chrono::Utc::now(),
         updated: chrono::Utc::now(),
+        slug: None,
     };
     preview(&mut post, 600);
     let expected = indoc::indoc! {"
@@ -258,6 +288,7 @@ fn test_sanitize_preview() {
         content: content.to_string(),
         created: Utc::now(),
         updated: Utc::now(),
+        slug: None,
     };
     preview(&mut post, 600);
     println!("post:\n{}", post.content);
@@ -285,7 +316,10 @@ fn truncate(text: &str, max_length: usize) -> String {
 }
 
 pub fn extract_html_title(post: &Post) -> String {
-    let title = &post.content;
+    if let Some(title) = post.front_matter().title {
+        return title;
+    }
+    let title = post.body();
     // This also would make a post with a single word on the first line have
     // that as the title which I guess makes sense.
     let title = title.split("\n").next().unwrap();
@@ -308,17 +342,21 @@ pub fn extract_html_title(post: &Post) -> String {
 
 /// Description for the meta description and Open Graph description.
 pub fn extract_html_description(post: &Post) -> String {
-    let content = &post.content;
-    // This also would make a post with a single word on the first line have
-    // that as the title which I guess makes sense.
-    let lines = content.lines().collect::<Vec<&str>>();
-    let has_title = lines.first().unwrap().starts_with("# ");
-    let description = if has_title {
-        lines[1..].join("\n")
-    } else {
-        lines.join("\n")
+    let description = match post.front_matter().description {
+        Some(description) => description,
+        None => {
+            // This also would make a post with a single word on the first
+            // line have that as the title which I guess makes sense.
+            let lines = post.body().lines().collect::<Vec<&str>>();
+            let has_title = lines.first().is_some_and(|line| line.starts_with("# "));
+            let description = if has_title {
+                lines[1..].join("\n")
+            } else {
+                lines.join("\n")
+            };
+            remove_urls(&description)
+        }
     };
-    let description = remove_urls(&description);
     let mut description = description
         .replace("&", "&amp;")
         .replace("<", "&lt;")
@@ -345,6 +383,7 @@ fn test_extract_html_description() {
         content: "# Title\nipsum".to_string(),
         created: chrono::Utc::now(),
         updated: chrono::Utc::now(),
+        slug: None,
     };
     let description = extract_html_description(&post);
     assert_eq!(description, "ipsum");
@@ -354,6 +393,7 @@ fn test_extract_html_description() {
         content: "lorem & ipsum".to_string(),
         created: chrono::Utc::now(),
         updated: chrono::Utc::now(),
+        slug: None,
     };
     let description = extract_html_description(&post);
     assert_eq!(description, "lorem &amp; ipsum");
@@ -367,9 +407,17 @@ fn test_extract_html_description() {
 /// This function should be called before `crate::md::preview` because otherwise
 /// the content is converted to Markdown and texts such as `<p><a href` will end
 /// up in the slug
+///
+/// The slug that the author set, in the front matter or the editor, takes
+/// precedence so that editing the title does not change the URL. Non-ASCII
+/// titles are transliterated, so `Über` becomes `uber`.
 pub fn extract_slug(post: &Post) -> String {
+    if let Some(slug) = post.front_matter().slug.or(post.slug.clone()) {
+        return slug;
+    }
     let title = extract_html_title(post);
-    let slug = title.replace(" ", "-");
+    let title = deunicode::deunicode_with_tofu(&title, "");
+    let slug = title.trim().replace(" ", "-");
     let slug = slug
         .replace(",", "-")
         .replace("\"", "-")
@@ -389,6 +437,32 @@ pub fn extract_slug(post: &Post) -> String {
     }
 }
 
+/// Turn the slug that the author typed into one that is safe to use in URLs.
+///
+/// Returns `None` when nothing is left, so that the slug from the title is
+/// used instead.
+pub fn normalize_slug(text: &str) -> Option<String> {
+    let text = deunicode::deunicode_with_tofu(text, "").to_lowercase();
+    let slug = text
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<&str>>()
+        .join("-");
+    let slug = truncate(&slug, 50).trim_end_matches('-').to_string();
+    Some(slug).filter(|slug| !slug.is_empty())
+}
+
+#[test]
+fn test_normalize_slug() {
+    assert_eq!(
+        normalize_slug("Hello World!"),
+        Some("hello-world".to_string())
+    );
+    assert_eq!(normalize_slug(" über--uns "), Some("uber-uns".to_string()));
+    assert_eq!(normalize_slug("../.."), None);
+    assert_eq!(normalize_slug(""), None);
+}
+
 #[test]
 fn test_extract_slug() {
     let mut post = Post {
@@ -396,10 +470,17 @@ fn test_extract_slug() {
         content: "Foo Bar".to_string(),
         created: chrono::Utc::now(),
         updated: chrono::Utc::now(),
+        slug: None,
     };
     assert_eq!(extract_slug(&post), "foo-bar");
     post.content = "Lorem, ipsum".to_string();
     assert_eq!(extract_slug(&post), "lorem--ipsum");
+    post.content = "Ærøskøbing café".to_string();
+    assert_eq!(extract_slug(&post), "aeroskobing-cafe");
+    post.content = "日本語".to_string();
+    assert!(!extract_slug(&post).is_empty());
+    post.slug = Some("custom".to_string());
+    assert_eq!(extract_slug(&post), "custom");
 }
 
 /// Used for RSS feed description field.
@@ -410,8 +491,7 @@ pub fn extract_rss_description(post: &Post) -> String {
     let mut post = post.clone();
     // Should not truncate the post, but instead implement feed pages.
     preview(&mut post, 600);
-    let html = content_to_html(&post.content);
-    html
+    content_to_html(&post.content)
 }
 
 #[cfg(test)]
@@ -426,6 +506,7 @@ mod test {
             content: "[lorem](https://example.com/lorem) ipsum".to_string(),
             created: Utc::now(),
             updated: Utc::now(),
+            slug: None,
         };
         let title = extract_html_title(&post);
         assert_eq!(title, "lorem ipsum");
@@ -435,6 +516,7 @@ mod test {
             content: "# Title\nipsum".to_string(),
             created: Utc::now(),
             updated: Utc::now(),
+            slug: None,
         };
         let title = extract_html_title(&post);
         assert_eq!(title, "Title");
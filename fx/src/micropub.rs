@@ -0,0 +1,510 @@
+//! Micropub endpoint at `/micropub`, see <https://www.w3.org/TR/micropub/>.
+//!
+//! This allows posting from IndieWeb apps. Posts are stored as Markdown with
+//! the `name` property as the first heading.
+use crate::data::Post;
+use crate::files::File;
+use crate::serve::ServerContext;
+use crate::serve::response;
+use crate::serve::response_json;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Multipart;
+use axum::extract::Query;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use chrono::DateTime;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::HashMap;
+
+fn error(
+    ctx: &ServerContext,
+    status: StatusCode,
+    error: &str,
+    description: &str,
+) -> Response<Body> {
+    let body = json!({
+        "error": error,
+        "error_description": description,
+    })
+    .to_string();
+    response_json(status, body, ctx)
+}
+
+fn invalid_request(ctx: &ServerContext, description: &str) -> Response<Body> {
+    error(ctx, StatusCode::BAD_REQUEST, "invalid_request", description)
+}
+
+fn unauthorized(ctx: &ServerContext) -> Response<Body> {
+    let description = "missing or invalid access token";
+    error(ctx, StatusCode::UNAUTHORIZED, "unauthorized", description)
+}
+
+fn insufficient_scope(ctx: &ServerContext, scope: &str) -> Response<Body> {
+    let description = format!("the access token does not have the {scope} scope");
+    error(
+        ctx,
+        StatusCode::FORBIDDEN,
+        "insufficient_scope",
+        &description,
+    )
+}
+
+fn server_error(ctx: &ServerContext, description: &str) -> Response<Body> {
+    error(
+        ctx,
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "server_error",
+        description,
+    )
+}
+
+fn created(ctx: &ServerContext, location: &str) -> Response<Body> {
+    let mut headers = HeaderMap::new();
+    let location = format!("{}{location}", ctx.base_url());
+    headers.insert("Location", HeaderValue::from_str(&location).unwrap());
+    response(StatusCode::CREATED, headers, "", ctx)
+}
+
+/// A Micropub request in the JSON syntax.
+///
+/// Form-encoded requests are converted to this so that both are handled the
+/// same way.
+#[derive(Debug, Default, Deserialize)]
+struct Request {
+    #[serde(default, rename = "type")]
+    kind: Vec<String>,
+    #[serde(default)]
+    properties: HashMap<String, Vec<Value>>,
+    action: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    replace: HashMap<String, Vec<Value>>,
+    #[serde(default)]
+    add: HashMap<String, Vec<Value>>,
+    /// Either a list of property names or an object with values to remove.
+    delete: Option<Value>,
+    #[serde(skip)]
+    access_token: Option<String>,
+}
+
+fn from_form(body: &str) -> Result<Request, String> {
+    let pairs = serde_urlencoded::from_str::<Vec<(String, String)>>(body)
+        .map_err(|e| format!("invalid form: {e}"))?;
+    let mut request = Request::default();
+    for (key, value) in pairs {
+        match key.as_str() {
+            "h" => request.kind.push(format!("h-{value}")),
+            "action" => request.action = Some(value),
+            "url" => request.url = Some(value),
+            "access_token" => request.access_token = Some(value),
+            _ => {
+                let key = key.trim_end_matches("[]").to_string();
+                request
+                    .properties
+                    .entry(key)
+                    .or_default()
+                    .push(Value::String(value));
+            }
+        }
+    }
+    Ok(request)
+}
+
+fn first_str(values: Option<&Vec<Value>>) -> Option<String> {
+    as_str(values?.first()?)
+}
+
+fn as_str(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        // Content can be `{"html": "..."}` which is fine since Markdown
+        // allows HTML.
+        Value::Object(o) => o
+            .get("html")
+            .or(o.get("value"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string()),
+        _ => None,
+    }
+}
+
+/// Split the Markdown into the front matter, the `name` (first heading) and
+/// the `content`.
+///
+/// Micropub has no property for the front matter, so it is kept aside and
+/// joined back when a client updates the post.
+fn split_name(markdown: &str) -> (&str, Option<String>, String) {
+    let (_, body) = crate::front_matter::split(markdown);
+    let front_matter = markdown[..markdown.len() - body.len()].trim_end();
+    match body.strip_prefix("# ") {
+        Some(rest) => {
+            let (name, content) = rest.split_once('\n').unwrap_or((rest, ""));
+            let name = Some(name.trim().to_string());
+            (front_matter, name, content.trim().to_string())
+        }
+        None => (front_matter, None, body.trim().to_string()),
+    }
+}
+
+fn join_name(front_matter: &str, name: Option<&str>, content: &str) -> String {
+    let body = match name {
+        Some(name) if !name.is_empty() => format!("# {name}\n\n{content}"),
+        _ => content.to_string(),
+    };
+    if front_matter.is_empty() {
+        body
+    } else {
+        format!("{front_matter}\n\n{body}")
+    }
+}
+
+#[test]
+fn test_split_name() {
+    let markdown = "# Title\n\nSome text.";
+    assert_eq!(
+        split_name(markdown),
+        ("", Some("Title".to_string()), "Some text.".to_string())
+    );
+    assert_eq!(split_name("Note"), ("", None, "Note".to_string()));
+    assert_eq!(join_name("", Some("Title"), "Some text."), markdown);
+    assert_eq!(join_name("", None, "Note"), "Note");
+
+    let markdown = "---\nlang: nl\n---\n\n# Titel\n\nWat tekst.";
+    let front_matter = "---\nlang: nl\n---";
+    assert_eq!(
+        split_name(markdown),
+        (
+            front_matter,
+            Some("Titel".to_string()),
+            "Wat tekst.".to_string()
+        )
+    );
+    assert_eq!(
+        join_name(front_matter, Some("Titel"), "Wat tekst."),
+        markdown
+    );
+    let markdown = "---\nlang: nl\n---\nNotitie";
+    assert_eq!(
+        split_name(markdown),
+        (front_matter, None, "Notitie".to_string())
+    );
+}
+
+/// Return an image for each `photo` value, which is a URL or an object with
+/// `value` and `alt`.
+fn photos_markdown(photos: &[Value]) -> Vec<String> {
+    photos
+        .iter()
+        .filter_map(|photo| match photo {
+            Value::String(url) => Some(format!("![]({url})")),
+            Value::Object(o) => {
+                let url = o.get("value")?.as_str()?;
+                let alt = o.get("alt").and_then(|a| a.as_str()).unwrap_or_default();
+                Some(format!("![{alt}]({url})"))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+fn markdown_from_properties(properties: &HashMap<String, Vec<Value>>) -> Option<String> {
+    let content = first_str(properties.get("content")).unwrap_or_default();
+    let name = first_str(properties.get("name"));
+    let photos = properties
+        .get("photo")
+        .map(|photos| photos_markdown(photos))
+        .unwrap_or_default();
+    let content = [vec![content], photos]
+        .concat()
+        .into_iter()
+        .filter(|s| !s.trim().is_empty())
+        .collect::<Vec<String>>()
+        .join("\n\n");
+    if content.is_empty() && name.is_none() {
+        return None;
+    }
+    Some(join_name("", name.as_deref(), &content))
+}
+
+#[derive(Debug, Deserialize)]
+struct MicropubQuery {
+    q: Option<String>,
+    url: Option<String>,
+}
+
+fn is_authenticated(ctx: &ServerContext, headers: &HeaderMap, token: Option<&str>) -> bool {
+    match crate::api::bearer_token(headers).or(token) {
+        Some(token) => {
+            crate::indieauth::is_valid_token(ctx, token) || crate::api::is_valid_token(ctx, token)
+        }
+        None => false,
+    }
+}
+
+/// Return an error response unless the token is the admin password or an
+/// IndieAuth token with the scope.
+fn authorize(
+    ctx: &ServerContext,
+    headers: &HeaderMap,
+    token: Option<&str>,
+    scope: &str,
+) -> Option<Response<Body>> {
+    let token = match crate::api::bearer_token(headers).or(token) {
+        Some(token) => token,
+        None => return Some(unauthorized(ctx)),
+    };
+    if crate::indieauth::is_valid_token(ctx, token) {
+        if crate::indieauth::has_scope(ctx, token, scope) {
+            return None;
+        }
+        return Some(insufficient_scope(ctx, scope));
+    }
+    if crate::api::is_valid_token(ctx, token) {
+        None
+    } else {
+        Some(unauthorized(ctx))
+    }
+}
+
+fn post_from_url(ctx: &ServerContext, url: Option<&str>) -> Option<Post> {
+    let id = crate::replies::post_id_from_url(ctx, url?)?;
+    let post = Post::get(&ctx.conn(), id).ok()?;
+    if post.content == "<DELETED>" {
+        None
+    } else {
+        Some(post)
+    }
+}
+
+async fn get_micropub(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Query(query): Query<MicropubQuery>,
+) -> Response<Body> {
+    if !is_authenticated(&ctx, &headers, None) {
+        return unauthorized(&ctx);
+    }
+    let body = match query.q.as_deref() {
+        Some("config") => json!({
+            "media-endpoint": format!("{}/micropub/media", ctx.base_url()),
+            "syndicate-to": [],
+            "post-types": [
+                { "type": "note", "name": "Note" },
+                { "type": "article", "name": "Article" },
+                { "type": "photo", "name": "Photo" },
+            ],
+        }),
+        Some("syndicate-to") => json!({ "syndicate-to": [] }),
+        Some("source") => {
+            let post = match post_from_url(&ctx, query.url.as_deref()) {
+                Some(post) => post,
+                None => return invalid_request(&ctx, "url is not a post on this site"),
+            };
+            let (_, name, content) = split_name(&post.content);
+            let name = name.or(post.front_matter().title);
+            let mut properties = json!({
+                "content": [content],
+                "published": [crate::serve::iso8601(&post.created)],
+            });
+            if let Some(name) = name {
+                properties["name"] = json!([name]);
+            }
+            json!({ "type": ["h-entry"], "properties": properties })
+        }
+        _ => return invalid_request(&ctx, "unsupported query"),
+    };
+    response_json(StatusCode::OK, body.to_string(), &ctx)
+}
+
+async fn create(ctx: &ServerContext, request: &Request) -> Response<Body> {
+    if !request.kind.is_empty() && !request.kind.iter().any(|kind| kind == "h-entry") {
+        return invalid_request(ctx, "only h-entry is supported");
+    }
+    let content = match markdown_from_properties(&request.properties) {
+        Some(content) => content,
+        None => return invalid_request(ctx, "missing content"),
+    };
+    let created = first_str(request.properties.get("published"))
+        .and_then(|published| DateTime::parse_from_rfc3339(&published).ok())
+        .map(|published| published.with_timezone(&Utc))
+        .unwrap_or(Utc::now());
+    // The Micropub spec lets clients suggest a slug with `mp-slug`.
+    let slug = first_str(request.properties.get("mp-slug"))
+        .and_then(|slug| crate::md::normalize_slug(&slug));
+    let now = Utc::now();
+    let id = match Post::insert(&ctx.conn(), created, now, &content, slug.as_deref()) {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("Failed to insert post: {e}");
+            return server_error(ctx, "failed to insert post");
+        }
+    };
+    tracing::info!("Created post {id} via Micropub");
+    crate::trigger::trigger_github_backup(ctx).await;
+    crate::ap::send_create(ctx, id).await;
+    crate::webmention::send_mentions(ctx, id, None).await;
+    created_post(ctx, id)
+}
+
+fn created_post(ctx: &ServerContext, id: i64) -> Response<Body> {
+    let post = match Post::get(&ctx.conn(), id) {
+        Ok(post) => post,
+        Err(e) => {
+            tracing::error!("Failed to get created post {id}: {e}");
+            return server_error(ctx, "failed to get created post");
+        }
+    };
+    let slug = crate::md::extract_slug(&post);
+    created(ctx, &crate::html::post_link(&post, &slug))
+}
+
+async fn update(ctx: &ServerContext, request: &Request) -> Response<Body> {
+    let mut post = match post_from_url(ctx, request.url.as_deref()) {
+        Some(post) => post,
+        None => return invalid_request(ctx, "url is not a post on this site"),
+    };
+    let previous = post.content.clone();
+    let (front_matter, mut name, mut content) = split_name(&previous);
+    for (key, values) in &request.replace {
+        match key.as_str() {
+            "content" => content = first_str(Some(values)).unwrap_or_default(),
+            "name" => name = first_str(Some(values)),
+            _ => return invalid_request(ctx, &format!("cannot replace {key}")),
+        }
+    }
+    // Added content and photos are appended since a post has one text.
+    let mut add = request.add.iter().collect::<Vec<_>>();
+    add.sort_by_key(|(key, _)| *key);
+    for (key, values) in add {
+        let added = match key.as_str() {
+            "content" => values.iter().filter_map(as_str).collect(),
+            "photo" => photos_markdown(values),
+            _ => return invalid_request(ctx, &format!("cannot add {key}")),
+        };
+        let parts = [vec![content], added].concat();
+        let parts = parts.into_iter().filter(|s| !s.trim().is_empty());
+        content = parts.collect::<Vec<String>>().join("\n\n");
+    }
+    if let Some(delete) = &request.delete {
+        let keys = match delete {
+            Value::Array(keys) => keys.iter().filter_map(|k| k.as_str()).collect(),
+            Value::Object(o) => o.keys().map(|k| k.as_str()).collect(),
+            _ => vec![],
+        };
+        for key in keys {
+            match key {
+                "name" => name = None,
+                _ => return invalid_request(ctx, &format!("cannot delete {key}")),
+            }
+        }
+    }
+    post.content = join_name(front_matter, name.as_deref(), &content);
+    post.updated = Utc::now();
+    if let Err(e) = post.update(&ctx.conn()) {
+        tracing::error!("Failed to update post: {e}");
+        return server_error(ctx, "failed to update post");
+    }
+    tracing::info!("Updated post {} via Micropub", post.id);
+    crate::trigger::trigger_github_backup(ctx).await;
+    crate::ap::send_update(ctx, post.id).await;
+    crate::webmention::send_mentions(ctx, post.id, Some(&previous)).await;
+    response(StatusCode::NO_CONTENT, HeaderMap::new(), "", ctx)
+}
+
+async fn delete(ctx: &ServerContext, request: &Request) -> Response<Body> {
+    let post = match post_from_url(ctx, request.url.as_deref()) {
+        Some(post) => post,
+        None => return invalid_request(ctx, "url is not a post on this site"),
+    };
+    if let Err(e) = Post::delete(&ctx.conn(), post.id) {
+        tracing::error!("Failed to delete post: {e}");
+        return server_error(ctx, "failed to delete post");
+    }
+    tracing::info!("Deleted post {} via Micropub", post.id);
+    crate::trigger::trigger_github_backup(ctx).await;
+    crate::ap::send_delete(ctx, post.id).await;
+    response(StatusCode::NO_CONTENT, HeaderMap::new(), "", ctx)
+}
+
+async fn post_micropub(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    body: String,
+) -> Response<Body> {
+    let is_json = headers
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/json"));
+    let request = if is_json {
+        serde_json::from_str::<Request>(&body).map_err(|e| format!("invalid JSON: {e}"))
+    } else {
+        from_form(&body)
+    };
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return invalid_request(&ctx, &e),
+    };
+    let scope = match request.action.as_deref() {
+        None | Some("create") => "create",
+        Some("update") => "update",
+        Some("delete") => "delete",
+        Some(action) => return invalid_request(&ctx, &format!("unsupported action {action}")),
+    };
+    if let Some(response) = authorize(&ctx, &headers, request.access_token.as_deref(), scope) {
+        return response;
+    }
+    match scope {
+        "create" => create(&ctx, &request).await,
+        "update" => update(&ctx, &request).await,
+        _ => delete(&ctx, &request).await,
+    }
+}
+
+async fn post_media(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Response<Body> {
+    if let Some(response) = authorize(&ctx, &headers, None, "media") {
+        return response;
+    }
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() != Some("file") {
+            continue;
+        }
+        let filename = field.file_name().unwrap_or("upload").to_string();
+        let mime_type = field
+            .content_type()
+            .unwrap_or("application/octet-stream")
+            .to_string();
+        let data = match field.bytes().await {
+            Ok(data) => data,
+            Err(e) => return invalid_request(&ctx, &format!("failed to read file: {e}")),
+        };
+        let file = File::new(&mime_type, &filename, data);
+        if let Err(e) = File::insert(&ctx.conn(), &file) {
+            tracing::error!("Failed to insert file: {e}");
+            return server_error(&ctx, "failed to store file");
+        }
+        crate::trigger::trigger_github_backup(&ctx).await;
+        return created(&ctx, &crate::files::path(&file));
+    }
+    invalid_request(&ctx, "missing file")
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/micropub", get(get_micropub))
+        .route("/micropub", post(post_micropub))
+        .route("/micropub/media", post(post_media))
+}
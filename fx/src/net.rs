@@ -0,0 +1,174 @@
+//! Requests to URLs that are chosen by other servers.
+//!
+//! ActivityPub key ids and inboxes as well as Webmention sources and endpoints
+//! come from whoever sends the request. Without these checks, they could be used
+//! to reach services on the local network of the server (server-side request
+//! forgery).
+use crate::serve::ServerContext;
+use reqwest::Url;
+use reqwest::dns::Addrs;
+use reqwest::dns::Name;
+use reqwest::dns::Resolve;
+use reqwest::dns::Resolving;
+use reqwest::redirect::Policy;
+use std::net::IpAddr;
+
+/// Whether the address is reachable on the public internet.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            let is_shared = a == 100 && (64..128).contains(&b);
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || is_shared
+                || a == 0)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(IpAddr::V4(ip));
+            }
+            let first = ip.segments()[0];
+            let is_unique_local = (first & 0xfe00) == 0xfc00;
+            let is_link_local = (first & 0xffc0) == 0xfe80;
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_multicast()
+                || is_unique_local
+                || is_link_local)
+        }
+    }
+}
+
+#[test]
+fn test_is_public() {
+    let public = |ip: &str| is_public(ip.parse().unwrap());
+    assert!(public("93.184.216.34"));
+    assert!(public("2606:2800:220:1::"));
+    assert!(!public("127.0.0.1"));
+    assert!(!public("10.1.2.3"));
+    assert!(!public("172.16.0.1"));
+    assert!(!public("192.168.1.1"));
+    assert!(!public("169.254.169.254"));
+    assert!(!public("100.64.0.1"));
+    assert!(!public("0.0.0.0"));
+    assert!(!public("::1"));
+    assert!(!public("fd00::1"));
+    assert!(!public("fe80::1"));
+    assert!(!public("::ffff:127.0.0.1"));
+}
+
+/// Resolves hosts to their public addresses only.
+///
+/// Since this runs on connect, it also covers redirects and hosts that resolve
+/// to another address by the time the request is made.
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host.as_str(), 0)).await?;
+            let addrs = addrs
+                .filter(|addr| is_public(addr.ip()))
+                .collect::<Vec<_>>();
+            if addrs.is_empty() {
+                return Err(format!("{host} does not resolve to a public address").into());
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Check the scheme and, since the resolver is skipped for them, IP addresses.
+fn check_url(url: &Url) -> Result<(), String> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("{url} is not an http(s) URL"));
+    }
+    let host = url.host_str().ok_or(format!("{url} has no host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let Ok(ip) = host.parse::<IpAddr>() else {
+        return Ok(());
+    };
+    if is_public(ip) {
+        Ok(())
+    } else {
+        Err(format!("{url} is not a public address"))
+    }
+}
+
+#[test]
+fn test_check_url() {
+    let check = |url: &str| check_url(&Url::parse(url).unwrap());
+    assert_eq!(check("https://example.com/users/alice"), Ok(()));
+    assert_eq!(check("http://93.184.216.34/"), Ok(()));
+    assert!(check("http://127.0.0.1:3000/").is_err());
+    assert!(check("http://[::1]/").is_err());
+    assert!(check("file:///etc/passwd").is_err());
+}
+
+/// Parse the URL and refuse it when it points to the local network.
+pub fn public_url(ctx: &ServerContext, url: &str) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|e| format!("invalid URL {url}: {e}"))?;
+    if !ctx.args.allow_private_addresses {
+        check_url(&url)?;
+    }
+    Ok(url)
+}
+
+/// Only follow redirects to URLs that pass `check_url`.
+fn public_redirects() -> Policy {
+    Policy::custom(|attempt| {
+        if 10 <= attempt.previous().len() {
+            return attempt.error("too many redirects");
+        }
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(e) => attempt.error(e),
+        }
+    })
+}
+
+/// Return a client for requests to URLs from other servers.
+///
+/// Use together with `public_url`.
+pub fn client(ctx: &ServerContext) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(format!("fx/{}", env!("CARGO_PKG_VERSION")))
+        .timeout(std::time::Duration::from_secs(10));
+    if !ctx.args.allow_private_addresses {
+        builder = builder
+            .dns_resolver(PublicResolver)
+            .redirect(public_redirects());
+    }
+    builder.build().unwrap()
+}
+
+/// The largest response body that is read from another server.
+pub const MAX_BODY: usize = 1024 * 1024;
+
+/// Read the body as text, but stop at `limit` bytes.
+///
+/// Other servers choose the URL, so `Response::text` would let them make this
+/// server buffer a response of any size.
+pub async fn text(mut response: reqwest::Response, limit: usize) -> Result<String, String> {
+    if response
+        .content_length()
+        .is_some_and(|length| (limit as u64) < length)
+    {
+        return Err(format!("response is larger than {limit} bytes"));
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if limit < body.len() + chunk.len() {
+            return Err(format!("response is larger than {limit} bytes"));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
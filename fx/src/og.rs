@@ -0,0 +1,370 @@
+//! Open Graph images for link previews.
+//!
+//! Social sites show the `og:image` of a page when a link is shared. For
+//! posts without an image, a card with the site name, post title and date is
+//! rendered at `/posts/{id}/og.png`. Rendering is done in Rust with a bundled
+//! font, so no browser or system fonts are needed.
+use crate::data::Kv;
+use crate::data::Post;
+use crate::serve::ServerContext;
+use crate::serve::response;
+use ab_glyph::Font;
+use ab_glyph::FontRef;
+use ab_glyph::PxScale;
+use ab_glyph::ScaleFont;
+use ab_glyph::point;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use bytes::Bytes;
+use std::collections::HashMap;
+
+/// The size that Facebook, LinkedIn and X recommend.
+pub const WIDTH: u32 = 1200;
+pub const HEIGHT: u32 = 630;
+const MARGIN: f32 = 80.0;
+const FONT: &[u8] = include_bytes!("static/DejaVuSans-Bold.ttf");
+
+type Rgb = [u8; 3];
+
+/// The colors of the site from `style.css`.
+pub struct Theme {
+    background: Rgb,
+    text: Rgb,
+    muted: Rgb,
+    gradient: (Rgb, Rgb),
+}
+
+impl Theme {
+    pub const LIGHT: Theme = Theme {
+        background: [255, 255, 255],
+        text: [2, 17, 34],
+        muted: [102, 102, 102],
+        gradient: ([7, 94, 207], [68, 146, 248]),
+    };
+    pub const DARK: Theme = Theme {
+        background: [50, 58, 62],
+        text: [245, 245, 245],
+        muted: [180, 180, 180],
+        gradient: ([7, 94, 207], [68, 146, 248]),
+    };
+}
+
+/// The rendered cards by post id.
+///
+/// The key holds everything that is drawn, so a card is rendered again when
+/// the post is updated or the site name or theme changes.
+#[derive(Default)]
+pub struct OgCache {
+    cards: HashMap<i64, (String, Bytes)>,
+}
+
+struct Canvas {
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(color: Rgb) -> Self {
+        let pixels = color.repeat((WIDTH * HEIGHT) as usize);
+        Self { pixels }
+    }
+    /// Mix `color` into the pixel with `alpha` between 0 and 1.
+    fn blend(&mut self, x: i32, y: i32, color: Rgb, alpha: f32) {
+        if x < 0 || y < 0 || WIDTH as i32 <= x || HEIGHT as i32 <= y {
+            return;
+        }
+        let i = 3 * (y as usize * WIDTH as usize + x as usize);
+        let alpha = alpha.clamp(0.0, 1.0);
+        for (pixel, color) in self.pixels[i..i + 3].iter_mut().zip(color) {
+            *pixel = (*pixel as f32 * (1.0 - alpha) + color as f32 * alpha).round() as u8;
+        }
+    }
+    /// Fill a horizontal band with a gradient from left to right.
+    fn gradient(&mut self, y: u32, height: u32, (from, to): (Rgb, Rgb)) {
+        for x in 0..WIDTH {
+            let t = x as f32 / (WIDTH - 1) as f32;
+            let mut color = [0; 3];
+            for c in 0..3 {
+                color[c] = (from[c] as f32 * (1.0 - t) + to[c] as f32 * t).round() as u8;
+            }
+            for y in y..y + height {
+                self.blend(x as i32, y as i32, color, 1.0);
+            }
+        }
+    }
+    /// Draw `text` with the baseline starting at `(x, y)`.
+    fn text(&mut self, font: &FontRef, size: f32, (x, y): (f32, f32), text: &str, color: Rgb) {
+        for (id, offset) in layout(font, size, text) {
+            let glyph = id.with_scale_and_position(size, point(x + offset, y));
+            if let Some(outlined) = font.outline_glyph(glyph) {
+                let bounds = outlined.px_bounds();
+                outlined.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x as i32 + gx as i32;
+                    let py = bounds.min.y as i32 + gy as i32;
+                    self.blend(px, py, color, coverage);
+                });
+            }
+        }
+    }
+    fn encode_png(&self) -> Result<Vec<u8>, png::EncodingError> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, WIDTH, HEIGHT);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()?;
+        Ok(data)
+    }
+}
+
+/// Replace characters that the font does not have, such as Chinese, by
+/// their transliteration.
+fn supported(font: &FontRef, text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            if font.glyph_id(c).0 != 0 || c.is_whitespace() {
+                c.to_string()
+            } else {
+                deunicode::deunicode_char(c).unwrap_or("").to_string()
+            }
+        })
+        .collect()
+}
+
+/// Return the glyphs of `text` with their horizontal offset from the start.
+fn layout(font: &FontRef, size: f32, text: &str) -> Vec<(ab_glyph::GlyphId, f32)> {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut glyphs = Vec::new();
+    let mut caret = 0.0;
+    let mut previous = None;
+    for c in text.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        glyphs.push((id, caret));
+        caret += scaled.h_advance(id);
+        previous = Some(id);
+    }
+    glyphs
+}
+
+fn text_width(font: &FontRef, size: f32, text: &str) -> f32 {
+    let scaled = font.as_scaled(PxScale::from(size));
+    match layout(font, size, text).last() {
+        Some((id, offset)) => offset + scaled.h_advance(*id),
+        None => 0.0,
+    }
+}
+
+/// Break `text` into lines that fit in `width`.
+///
+/// Returns `None` when more than `max_lines` are needed.
+fn wrap(
+    font: &FontRef,
+    size: f32,
+    text: &str,
+    width: f32,
+    max_lines: usize,
+) -> Option<Vec<String>> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let candidate = if line.is_empty() {
+            word.to_string()
+        } else {
+            format!("{line} {word}")
+        };
+        if text_width(font, size, &candidate) <= width || line.is_empty() {
+            line = candidate;
+        } else {
+            lines.push(std::mem::replace(&mut line, word.to_string()));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    let fits = lines
+        .iter()
+        .all(|line| text_width(font, size, line) <= width);
+    if fits && lines.len() <= max_lines {
+        Some(lines)
+    } else {
+        None
+    }
+}
+
+/// Shorten `text` until it fits in `width`, ending with an ellipsis.
+fn ellipsize(font: &FontRef, size: f32, text: &str, width: f32) -> String {
+    let mut text = text.to_string();
+    while !text.is_empty() && width < text_width(font, size, &format!("{text}…")) {
+        text.pop();
+    }
+    format!("{}…", text.trim_end())
+}
+
+/// Return `text` or, when it is too wide, the start of it with an ellipsis.
+fn fit(font: &FontRef, size: f32, text: &str, width: f32) -> String {
+    if text_width(font, size, text) <= width {
+        text.to_string()
+    } else {
+        ellipsize(font, size, text, width)
+    }
+}
+
+/// Lay out the title in at most three lines, shrinking the font if needed.
+fn title_lines(font: &FontRef, title: &str, width: f32) -> (f32, Vec<String>) {
+    for size in [72.0, 64.0, 56.0] {
+        if let Some(lines) = wrap(font, size, title, width, 3) {
+            return (size, lines);
+        }
+    }
+    // Fill two lines and cut off the rest on the third.
+    let size = 56.0;
+    let words = title.split_whitespace().collect::<Vec<&str>>();
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while lines.len() < 2 && start < words.len() {
+        let mut end = start + 1;
+        while end < words.len() && text_width(font, size, &words[start..=end].join(" ")) <= width {
+            end += 1;
+        }
+        lines.push(fit(font, size, &words[start..end].join(" "), width));
+        start = end;
+    }
+    if start < words.len() {
+        lines.push(ellipsize(font, size, &words[start..].join(" "), width));
+    }
+    (size, lines)
+}
+
+/// Render a card as PNG.
+pub fn render(site_name: &str, title: &str, date: &str, theme: &Theme) -> Vec<u8> {
+    let font = FontRef::try_from_slice(FONT).expect("bundled font is valid");
+    let width = WIDTH as f32 - 2.0 * MARGIN;
+    let mut canvas = Canvas::new(theme.background);
+    canvas.gradient(0, 16, theme.gradient);
+
+    let site_name = fit(&font, 36.0, &supported(&font, site_name), width);
+    canvas.text(&font, 36.0, (MARGIN, 130.0), &site_name, theme.muted);
+
+    let (size, lines) = title_lines(&font, &supported(&font, title), width);
+    let line_height = size * 1.25;
+    let mut y = 190.0 + size;
+    for line in lines {
+        canvas.text(&font, size, (MARGIN, y), &line, theme.text);
+        y += line_height;
+    }
+
+    canvas.text(
+        &font,
+        32.0,
+        (MARGIN, HEIGHT as f32 - MARGIN),
+        date,
+        theme.muted,
+    );
+    canvas
+        .encode_png()
+        .expect("encoding to memory does not fail")
+}
+
+/// Return the card for the post from the cache or render it.
+///
+/// Rendering takes a while, so it runs on a blocking thread and the cache is
+/// only locked to look up and store the card.
+pub async fn card(ctx: &ServerContext, post: &Post) -> Bytes {
+    let site_name = Kv::get_or_empty_string(&ctx.conn(), "site_name");
+    let dark_mode = Kv::get_or_empty_string(&ctx.conn(), "dark_mode");
+    let title = crate::md::extract_html_title(post);
+    let key = format!("{}\n{site_name}\n{dark_mode}\n{title}", post.updated);
+    if let Some((cached_key, png)) = ctx.og_cache.lock().await.cards.get(&post.id)
+        && *cached_key == key
+    {
+        return png.clone();
+    }
+    let date = post.created.format("%B %-d, %Y").to_string();
+    let png = tokio::task::spawn_blocking(move || {
+        let theme = if dark_mode == "on" {
+            &Theme::DARK
+        } else {
+            &Theme::LIGHT
+        };
+        Bytes::from(render(&site_name, &title, &date, theme))
+    })
+    .await
+    .unwrap();
+    let mut cache = ctx.og_cache.lock().await;
+    cache.cards.insert(post.id, (key, png.clone()));
+    png
+}
+
+/// Return the absolute URL of the `og:image` of the post.
+///
+/// The image in the front matter or otherwise the first image in the post
+/// is preferred over the generated card.
+pub fn image_url(ctx: &ServerContext, post: &Post) -> String {
+    let image = post
+        .front_matter()
+        .image
+        .or_else(|| crate::md::first_image(post.body()));
+    let base = ctx.base_url();
+    match image {
+        Some(image) if image.starts_with('/') => format!("{base}{image}"),
+        Some(image) => image,
+        None => format!("{base}/posts/{}/og.png", post.id),
+    }
+}
+
+async fn get_og_image(State(ctx): State<ServerContext>, Path(id): Path<i64>) -> Response<Body> {
+    let post = match Post::get(&ctx.conn(), id) {
+        Ok(post) if post.content != "<DELETED>" => post,
+        _ => return crate::serve::not_found(State(ctx)).await,
+    };
+    let png = card(&ctx, &post).await;
+    let mut headers = HeaderMap::new();
+    headers.insert("Content-Type", HeaderValue::from_static("image/png"));
+    headers.insert(
+        "Cache-Control",
+        HeaderValue::from_static("public, max-age=3600"),
+    );
+    response(StatusCode::OK, headers, png, &ctx)
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/posts/{id}/og.png", get(get_og_image))
+}
+
+#[test]
+fn test_render() {
+    let png = render(
+        "Site",
+        "A title that is long enough to wrap",
+        "May 1, 2025",
+        &Theme::LIGHT,
+    );
+    assert!(png.starts_with(b"\x89PNG"));
+    let decoder = png::Decoder::new(std::io::Cursor::new(png));
+    let reader = decoder.read_info().unwrap();
+    assert_eq!((reader.info().width, reader.info().height), (WIDTH, HEIGHT));
+}
+
+#[test]
+fn test_title_lines() {
+    let font = FontRef::try_from_slice(FONT).unwrap();
+    let width = WIDTH as f32 - 2.0 * MARGIN;
+    let (size, lines) = title_lines(&font, "Short", width);
+    assert_eq!((size, lines), (72.0, vec!["Short".to_string()]));
+    let title = "word ".repeat(100);
+    let (_, lines) = title_lines(&font, &title, width);
+    assert_eq!(lines.len(), 3);
+    assert!(lines[2].ends_with('…'));
+}
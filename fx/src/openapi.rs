@@ -0,0 +1,221 @@
+//! OpenAPI 3.1 description of the API at `/api/openapi.json`.
+//!
+//! The description is generated from the same list of endpoints that is used
+//! to register the routes, so the two cannot drift apart.
+use crate::serve::ServerContext;
+use axum::handler::Handler;
+use axum::http::Method;
+use axum::http::StatusCode;
+use axum::routing::MethodFilter;
+use axum::routing::MethodRouter;
+use axum::routing::on;
+use schemars::JsonSchema;
+use schemars::Schema;
+use schemars::SchemaGenerator;
+use schemars::generate::SchemaSettings;
+use serde_json::Map;
+use serde_json::Value;
+use serde_json::json;
+
+type SchemaFn = fn(&mut SchemaGenerator) -> Schema;
+
+fn subschema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.subschema_for::<T>()
+}
+
+fn root_schema<T: JsonSchema>(generator: &mut SchemaGenerator) -> Schema {
+    generator.root_schema_for::<T>()
+}
+
+/// The body of a request or response.
+pub enum Content {
+    Empty,
+    Json(SchemaFn),
+    /// A body that is not JSON such as an archive, a file or TOML.
+    Other(&'static str),
+}
+
+impl Content {
+    pub fn json<T: JsonSchema>() -> Self {
+        Content::Json(subschema::<T>)
+    }
+}
+
+/// An API endpoint together with the handler that serves it.
+pub struct Endpoint {
+    pub method: Method,
+    pub path: &'static str,
+    pub summary: &'static str,
+    /// The name of the handler function.
+    pub operation_id: &'static str,
+    pub authenticated: bool,
+    query: Option<SchemaFn>,
+    request: Vec<Content>,
+    responses: Vec<(StatusCode, Vec<Content>)>,
+    pub handler: MethodRouter<ServerContext>,
+}
+
+impl Endpoint {
+    pub fn new<H, T>(method: Method, path: &'static str, summary: &'static str, handler: H) -> Self
+    where
+        H: Handler<T, ServerContext>,
+        T: 'static,
+    {
+        let filter = MethodFilter::try_from(method.clone()).unwrap();
+        let operation_id = std::any::type_name::<H>().rsplit("::").next().unwrap();
+        Self {
+            method,
+            path,
+            summary,
+            operation_id,
+            authenticated: false,
+            query: None,
+            request: Vec::new(),
+            responses: Vec::new(),
+            handler: on(filter, handler),
+        }
+    }
+    /// Require an `Authorization: Bearer <token>` header.
+    pub fn authenticated(mut self) -> Self {
+        self.authenticated = true;
+        self
+    }
+    /// Describe the query parameters via the fields of `T`.
+    pub fn query<T: JsonSchema>(mut self) -> Self {
+        self.query = Some(root_schema::<T>);
+        self
+    }
+    pub fn request(mut self, content: Content) -> Self {
+        self.request.push(content);
+        self
+    }
+    pub fn response(mut self, status: StatusCode, content: Content) -> Self {
+        match self.responses.iter_mut().find(|(s, _)| *s == status) {
+            Some((_, contents)) => contents.push(content),
+            None => self.responses.push((status, vec![content])),
+        }
+        self
+    }
+}
+
+fn content_map(generator: &mut SchemaGenerator, contents: &[Content]) -> Map<String, Value> {
+    let mut map = Map::new();
+    for content in contents {
+        match content {
+            Content::Empty => (),
+            Content::Json(schema) => {
+                let schema = schema(generator);
+                map.insert("application/json".to_string(), json!({ "schema": schema }));
+            }
+            Content::Other(media_type) => {
+                let schema = json!({ "type": "string", "format": "binary" });
+                map.insert(media_type.to_string(), json!({ "schema": schema }));
+            }
+        }
+    }
+    map
+}
+
+fn parameters(generator: &mut SchemaGenerator, endpoint: &Endpoint) -> Vec<Value> {
+    let mut parameters = Vec::new();
+    for segment in endpoint.path.split('/') {
+        if let Some(name) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            // Post ids are the only numeric path parameters.
+            let kind = if name == "id" { "integer" } else { "string" };
+            parameters.push(json!({
+                "name": name,
+                "in": "path",
+                "required": true,
+                "schema": { "type": kind },
+            }));
+        }
+    }
+    if let Some(query) = endpoint.query {
+        let schema = query(generator);
+        let required = schema
+            .get("required")
+            .and_then(|r| r.as_array())
+            .cloned()
+            .unwrap_or_default();
+        let properties = schema.get("properties").and_then(|p| p.as_object());
+        for (name, schema) in properties.into_iter().flatten() {
+            let mut parameter = json!({
+                "name": name,
+                "in": "query",
+                "required": required.contains(&json!(name)),
+                "schema": schema,
+            });
+            if let Some(description) = schema.get("description") {
+                parameter["description"] = description.clone();
+            }
+            parameters.push(parameter);
+        }
+    }
+    parameters
+}
+
+fn operation(generator: &mut SchemaGenerator, endpoint: &Endpoint) -> Value {
+    let mut operation = json!({
+        "operationId": endpoint.operation_id,
+        "summary": endpoint.summary,
+    });
+    let parameters = parameters(generator, endpoint);
+    if !parameters.is_empty() {
+        operation["parameters"] = json!(parameters);
+    }
+    let request = content_map(generator, &endpoint.request);
+    if !request.is_empty() {
+        operation["requestBody"] = json!({ "required": true, "content": request });
+    }
+    let mut responses = Map::new();
+    for (status, contents) in &endpoint.responses {
+        let description = status.canonical_reason().unwrap_or("");
+        let mut response = json!({ "description": description });
+        let content = content_map(generator, contents);
+        if !content.is_empty() {
+            response["content"] = json!(content);
+        }
+        responses.insert(status.as_str().to_string(), response);
+    }
+    operation["responses"] = json!(responses);
+    if endpoint.authenticated {
+        operation["security"] = json!([{ "bearer": [] }]);
+    }
+    operation
+}
+
+/// Return the OpenAPI document for the given endpoints.
+pub fn document(ctx: &ServerContext, endpoints: &[Endpoint]) -> Value {
+    let settings = SchemaSettings::draft2020_12().with(|settings| {
+        settings.definitions_path = "/components/schemas".into();
+        settings.meta_schema = None;
+    });
+    let mut generator = SchemaGenerator::new(settings);
+    let mut paths = Map::new();
+    for endpoint in endpoints {
+        let operation = operation(&mut generator, endpoint);
+        let path = paths.entry(endpoint.path).or_insert_with(|| json!({}));
+        path[endpoint.method.as_str().to_lowercase()] = operation;
+    }
+    let version = include_str!("version.txt").trim();
+    let mut document = json!({
+        "openapi": "3.1.0",
+        "info": { "title": "fx", "version": version },
+        "paths": paths,
+        "components": {
+            "schemas": generator.take_definitions(true),
+            "securitySchemes": {
+                "bearer": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "The admin password or an IndieAuth access token.",
+                },
+            },
+        },
+    });
+    let base_url = ctx.base_url();
+    if !base_url.is_empty() {
+        document["servers"] = json!([{ "url": base_url }]);
+    }
+    document
+}
@@ -0,0 +1,358 @@
+//! Redirects from old paths, such as those of imported posts, to new targets.
+//!
+//! Redirects are only checked when no route matches, so they cannot hide
+//! existing pages.
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::response;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Form;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::http::Uri;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Result;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// The status codes that can be used for redirects.
+pub const STATUS_CODES: [u16; 4] = [301, 302, 307, 308];
+
+fn default_status() -> u16 {
+    301
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct Redirect {
+    /// The path on this site, such as `/2020/01/hello/`, optionally with a
+    /// query.
+    pub from: String,
+    /// The path or URL to redirect to.
+    pub to: String,
+    /// One of 301 (the default), 302, 307 or 308.
+    #[serde(default = "default_status")]
+    pub status: u16,
+}
+
+/// Return the path and query of a URL or path.
+///
+/// Imported posts have full URLs, but only the path can be redirected.
+pub fn normalize_path(url: &str) -> String {
+    let url = url.trim();
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map(|start| &rest[start..]).unwrap_or("/"),
+        None => url,
+    };
+    let path = path.split('#').next().unwrap_or("");
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{path}")
+    }
+}
+
+impl Redirect {
+    /// Create a redirect with a normalized `from` or return why it is invalid.
+    pub fn new(from: &str, to: &str, status: u16) -> Result<Self, String> {
+        let redirect = Self {
+            from: normalize_path(from),
+            to: to.trim().to_string(),
+            status,
+        };
+        redirect.validate()?;
+        Ok(redirect)
+    }
+    pub fn validate(&self) -> Result<(), String> {
+        if self.from == "/" {
+            return Err("from cannot be the home page".to_string());
+        }
+        if !self.from.starts_with('/') {
+            return Err("from must be a path starting with /".to_string());
+        }
+        if self.to.is_empty() {
+            return Err("to is empty".to_string());
+        }
+        if self.to == self.from {
+            return Err("from and to are the same".to_string());
+        }
+        if !STATUS_CODES.contains(&self.status) {
+            return Err(format!("status must be one of {STATUS_CODES:?}"));
+        }
+        Ok(())
+    }
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS redirects (
+                source TEXT PRIMARY KEY,
+                target TEXT NOT NULL,
+                status INTEGER NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    fn from_row(row: &rusqlite::Row) -> Result<Self> {
+        Ok(Self {
+            from: row.get("source")?,
+            to: row.get("target")?,
+            status: row.get("status")?,
+        })
+    }
+    /// Insert the redirect or replace the one with the same `from`.
+    pub fn insert(conn: &Connection, redirect: &Self) -> Result<usize> {
+        let stmt = "
+            INSERT OR REPLACE INTO redirects (source, target, status)
+            VALUES (?, ?, ?);
+        ";
+        let params = rusqlite::params![redirect.from, redirect.to, redirect.status];
+        conn.execute(stmt, params)
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        let stmt = "SELECT source, target, status FROM redirects ORDER BY source ASC";
+        let mut stmt = conn.prepare(stmt)?;
+        let redirects = stmt.query_map([], Self::from_row)?;
+        redirects.collect()
+    }
+    pub fn get(conn: &Connection, from: &str) -> Result<Option<Self>> {
+        let stmt = "SELECT source, target, status FROM redirects WHERE source = ?";
+        conn.query_row(stmt, [from], Self::from_row).optional()
+    }
+    pub fn delete(conn: &Connection, from: &str) -> Result<usize> {
+        let stmt = "DELETE FROM redirects WHERE source = ?";
+        conn.execute(stmt, [from])
+    }
+    /// Find the redirect for a requested path and query.
+    ///
+    /// Without a match for the query, the path alone is tried, and a trailing
+    /// slash is optional.
+    pub fn find(conn: &Connection, path_and_query: &str) -> Result<Option<Self>> {
+        let path = path_and_query.split('?').next().unwrap_or("");
+        let alternative = match path.strip_suffix('/') {
+            Some(path) => path.to_string(),
+            None => format!("{path}/"),
+        };
+        for from in [path_and_query, path, &alternative] {
+            if let Some(redirect) = Self::get(conn, from)? {
+                return Ok(Some(redirect));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Redirect when there is a redirect for the path and show 404 otherwise.
+pub async fn fallback(State(ctx): State<ServerContext>, uri: Uri) -> Response<Body> {
+    let path_and_query = uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or(uri.path());
+    let redirect = match Redirect::find(&ctx.conn(), path_and_query) {
+        Ok(redirect) => redirect,
+        Err(e) => {
+            tracing::error!("Failed to find redirect for {path_and_query}: {e}");
+            None
+        }
+    };
+    let Some(redirect) = redirect else {
+        return crate::serve::not_found(State(ctx)).await;
+    };
+    let (Ok(status), Ok(location)) = (
+        StatusCode::from_u16(redirect.status),
+        HeaderValue::from_str(&redirect.to),
+    ) else {
+        tracing::error!("Invalid redirect from {}", redirect.from);
+        return crate::serve::not_found(State(ctx)).await;
+    };
+    let mut headers = HeaderMap::new();
+    headers.insert("Location", location);
+    tracing::info!("\"GET {path_and_query} HTTP/1.1\" {}", status.as_u16());
+    response(status, headers, "", &ctx)
+}
+
+fn show_redirect(redirect: &Redirect) -> String {
+    let from = escape_html(&redirect.from);
+    let to = escape_html(&redirect.to);
+    let status = redirect.status;
+    format!(
+        "
+        <div style='padding: 6px; padding-bottom: 6px; padding-top: 12px; \
+          border-bottom: 1px solid var(--border); font-size: 0.8rem;'>
+            <form method='post' action='/redirects/delete' style='margin: 0;'>
+                <code>{from}</code> → <a href='{to}'>{to}</a> ({status})
+                <input type='hidden' name='from' value='{from}'/>
+                <input type='submit' value='🗑️ Delete' \
+                  style='font-size: 0.8rem; padding: 0.1rem;'/>
+            </form>
+        </div>
+        "
+    )
+}
+
+async fn get_redirects(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let redirects = match Redirect::list(&ctx.conn()) {
+        Ok(redirects) => redirects,
+        Err(e) => {
+            let msg = "Could not get redirects from database";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    let redirects = redirects
+        .iter()
+        .map(show_redirect)
+        .collect::<Vec<String>>()
+        .join("");
+    let options = STATUS_CODES
+        .iter()
+        .map(|status| format!("<option value='{status}'>{status}</option>"))
+        .collect::<String>();
+    let body = format!(
+        "
+        <div style='border-bottom: 2px solid var(--border);'>
+            <form method='post' action='/redirects/add' \
+              class='margin-auto' \
+              style='margin-top: 5vh; width: 80%;'>
+                <div>
+                    <label for='from'>From</label><br>
+                    <input type='text' id='from' name='from' \
+                      placeholder='/2020/01/hello/' required/>
+                </div>
+                <br>
+                <div>
+                    <label for='to'>To</label><br>
+                    <input type='text' id='to' name='to' placeholder='/posts/1' required/>
+                </div>
+                <br>
+                <div>
+                    <label for='status'>Status</label><br>
+                    <select id='status' name='status'>{options}</select>
+                    <br>
+                    <span style='font-size: 0.8rem; line-height: 1.2; \
+                      display: inline-block;'>
+                        Redirects are used for paths that do not exist, such as the URLs of
+                        posts on the site that they were imported from. Use 301 for pages
+                        that moved permanently.
+                    </span>
+                </div>
+                <br>
+                <div>
+                    <input style='margin-left: 0;' type='submit' value='Add'/>
+                </div>
+                <br>
+            </form>
+        </div>
+        <div>
+            {redirects}
+        </div>
+        "
+    );
+    let page_settings = PageSettings::new(
+        "Redirects",
+        Some(is_logged_in),
+        None,
+        false,
+        Top::GoHome,
+        "",
+    );
+    let body = page(&ctx, &page_settings, &body).await;
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+#[derive(Debug, Deserialize)]
+struct RedirectForm {
+    from: String,
+    to: String,
+    status: u16,
+}
+
+async fn post_add(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    Form(form): Form<RedirectForm>,
+) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let redirect = match Redirect::new(&form.from, &form.to, form.status) {
+        Ok(redirect) => redirect,
+        Err(e) => {
+            let msg = format!("Invalid redirect: {e}");
+            return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", &msg).await;
+        }
+    };
+    Redirect::insert(&ctx.conn(), &redirect).unwrap();
+    tracing::info!("\"POST /redirects/add HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/redirects")
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteForm {
+    from: String,
+}
+
+async fn post_delete(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    Form(form): Form<DeleteForm>,
+) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    Redirect::delete(&ctx.conn(), &form.from).unwrap();
+    tracing::info!("\"POST /redirects/delete HTTP/1.1\" 303");
+    crate::serve::see_other(&ctx, "/redirects")
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/redirects", get(get_redirects))
+        .route("/redirects/add", post(post_add))
+        .route("/redirects/delete", post(post_delete))
+}
+
+#[test]
+fn test_normalize_path() {
+    assert_eq!(
+        normalize_path("https://blog.example/2020/hello/"),
+        "/2020/hello/"
+    );
+    assert_eq!(normalize_path("https://blog.example"), "/");
+    assert_eq!(normalize_path("old/page?id=1#top"), "/old/page?id=1");
+}
+
+#[test]
+fn test_find() {
+    let conn = Connection::open_in_memory().unwrap();
+    Redirect::create_table(&conn).unwrap();
+    let redirect = Redirect::new("https://old.example/a/", "/posts/1", 301).unwrap();
+    Redirect::insert(&conn, &redirect).unwrap();
+    let query = Redirect::new("/index.php?p=2", "/posts/2", 308).unwrap();
+    Redirect::insert(&conn, &query).unwrap();
+    let find = |path| Redirect::find(&conn, path).unwrap();
+    assert_eq!(find("/a/"), Some(redirect.clone()));
+    assert_eq!(find("/a"), Some(redirect.clone()));
+    assert_eq!(find("/a/?utm=x"), Some(redirect));
+    assert_eq!(find("/index.php?p=2"), Some(query));
+    assert_eq!(find("/index.php?p=3"), None);
+    assert!(Redirect::new("/", "/posts/1", 301).is_err());
+    assert!(Redirect::new("/a", "/b", 200).is_err());
+}
@@ -0,0 +1,743 @@
+//! Replies and reactions from the fediverse at `/replies`.
+//!
+//! Replies, and Webmentions from other sites, are only shown below the post
+//! after the site owner approved them.
+//! Likes and boosts (announces) are only counted.
+use crate::data::Post;
+use crate::data::SqliteDateTime;
+use crate::html::PageSettings;
+use crate::html::Top;
+use crate::html::escape_html;
+use crate::html::page;
+use crate::html::show_date;
+use crate::serve::ServerContext;
+use crate::serve::is_logged_in;
+use crate::serve::not_found;
+use crate::serve::response;
+use axum::Form;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Path;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use axum_extra::extract::CookieJar;
+use chrono::DateTime;
+use chrono::Utc;
+use rusqlite::Connection;
+use rusqlite::Result;
+use rusqlite::params;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Status {
+    /// Waiting for moderation.
+    Pending,
+    Approved,
+    Hidden,
+}
+
+impl Status {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Pending => "pending",
+            Status::Approved => "approved",
+            Status::Hidden => "hidden",
+        }
+    }
+    fn from_str(s: &str) -> Self {
+        match s {
+            "approved" => Status::Approved,
+            "hidden" => Status::Hidden,
+            _ => Status::Pending,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Reply {
+    pub id: i64,
+    /// The URL that identifies the reply on the other server.
+    pub object_id: String,
+    /// The id of the post that is replied to.
+    pub post_id: i64,
+    pub author_name: String,
+    pub author_url: String,
+    /// Sanitized HTML content.
+    pub content: String,
+    pub published: DateTime<Utc>,
+    pub status: Status,
+}
+
+impl Reply {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS replies (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                object_id TEXT UNIQUE NOT NULL,
+                post_id INTEGER NOT NULL,
+                author_name TEXT NOT NULL,
+                author_url TEXT NOT NULL,
+                content TEXT NOT NULL,
+                published DATETIME NOT NULL,
+                status TEXT NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Insert the reply or update the content if it already exists.
+    ///
+    /// Only the author can update a reply. Edited replies have to be approved
+    /// again, except for hidden replies which stay hidden.
+    pub fn upsert(&self, conn: &Connection) -> Result<usize> {
+        let stmt = "
+            INSERT INTO replies
+              (object_id, post_id, author_name, author_url, content, published, status)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT(object_id) DO UPDATE SET
+              author_name = excluded.author_name,
+              content = excluded.content,
+              status = CASE
+                WHEN replies.content = excluded.content OR replies.status = 'hidden'
+                THEN replies.status
+                ELSE excluded.status
+              END
+            WHERE replies.author_url = excluded.author_url;
+        ";
+        let params = params![
+            self.object_id,
+            self.post_id,
+            self.author_name,
+            self.author_url,
+            self.content,
+            self.published.to_sqlite(),
+            self.status.as_str(),
+        ];
+        conn.execute(stmt, params)
+    }
+    fn query(conn: &Connection, stmt: &str, params: impl rusqlite::Params) -> Result<Vec<Self>> {
+        conn.prepare(stmt)?
+            .query_map(params, |row| {
+                let published: String = row.get("published")?;
+                let status: String = row.get("status")?;
+                Ok(Reply {
+                    id: row.get("id")?,
+                    object_id: row.get("object_id")?,
+                    post_id: row.get("post_id")?,
+                    author_name: row.get("author_name")?,
+                    author_url: row.get("author_url")?,
+                    content: row.get("content")?,
+                    published: DateTime::from_sqlite(&published),
+                    status: Status::from_str(&status),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT * FROM replies ORDER BY published DESC;
+        ";
+        Self::query(conn, stmt, [])
+    }
+    pub fn list_approved(conn: &Connection, post_id: i64) -> Result<Vec<Self>> {
+        let stmt = "
+            SELECT * FROM replies
+            WHERE post_id = ? AND status = 'approved'
+            ORDER BY published;
+        ";
+        Self::query(conn, stmt, [post_id])
+    }
+    pub fn get(conn: &Connection, id: i64) -> Result<Self> {
+        let stmt = "SELECT * FROM replies WHERE id = ?";
+        Self::query(conn, stmt, [id])?
+            .pop()
+            .ok_or(rusqlite::Error::QueryReturnedNoRows)
+    }
+    pub fn set_status(conn: &Connection, id: i64, status: Status) -> Result<usize> {
+        let stmt = "UPDATE replies SET status = ? WHERE id = ?";
+        conn.execute(stmt, params![status.as_str(), id])
+    }
+    /// The id of a Webmention of the post by the source.
+    ///
+    /// The prefix keeps Webmentions apart from ActivityPub replies, whose id is
+    /// the URL of the reply, so that they cannot overwrite or delete each other.
+    pub fn webmention_id(post_id: i64, source: &str) -> String {
+        format!("webmention:{post_id}:{source}")
+    }
+    /// Return the domain that sent the reply.
+    ///
+    /// The `author_url` is chosen by the sender, so this uses the id instead.
+    /// `handle_create` checked that it has the domain of the actor, and the
+    /// source of a Webmention was fetched to verify it.
+    pub fn domain(&self) -> Option<String> {
+        match self.object_id.strip_prefix("webmention:") {
+            Some(rest) => domain_of(rest.split_once(':')?.1),
+            None => domain_of(&self.object_id),
+        }
+    }
+    /// Delete the Webmention when the source no longer links to the post.
+    pub fn delete_webmention(conn: &Connection, post_id: i64, source: &str) -> Result<usize> {
+        let stmt = "DELETE FROM replies WHERE object_id = ? AND post_id = ?";
+        conn.execute(stmt, params![Self::webmention_id(post_id, source), post_id])
+    }
+    /// Delete the reply when the author deleted it on their server.
+    ///
+    /// Only actors from the same domain as the reply can delete it.
+    pub fn delete_by_object_id(conn: &Connection, object_id: &str, actor: &str) -> Result<usize> {
+        if domain_of(object_id) != domain_of(actor) {
+            return Ok(0);
+        }
+        let stmt = "DELETE FROM replies WHERE object_id = ?";
+        conn.execute(stmt, [object_id])
+    }
+}
+
+#[test]
+fn test_upsert() {
+    let conn = Connection::open_in_memory().unwrap();
+    Reply::create_table(&conn).unwrap();
+    let reply = Reply {
+        id: 0,
+        object_id: "https://example.com/notes/1".to_string(),
+        post_id: 1,
+        author_name: "Alice".to_string(),
+        author_url: "https://example.com/users/alice".to_string(),
+        content: "<p>Hi</p>".to_string(),
+        published: Utc::now(),
+        status: Status::Pending,
+    };
+    reply.upsert(&conn).unwrap();
+    Reply::set_status(&conn, 1, Status::Approved).unwrap();
+    // Resending the same reply keeps it approved.
+    reply.upsert(&conn).unwrap();
+    assert_eq!(Reply::get(&conn, 1).unwrap().status, Status::Approved);
+
+    let mut other = reply.clone();
+    other.author_url = "https://example.com/users/mallory".to_string();
+    other.content = "<p>Spam</p>".to_string();
+    assert_eq!(other.upsert(&conn).unwrap(), 0);
+    assert_eq!(Reply::get(&conn, 1).unwrap().content, "<p>Hi</p>");
+
+    // The sender chooses the author URL, so blocking uses the id.
+    assert_eq!(reply.domain().as_deref(), Some("example.com"));
+    let mut webmention = reply.clone();
+    webmention.object_id = Reply::webmention_id(1, "https://example.org/a:b");
+    webmention.author_url = "https://example.net".to_string();
+    assert_eq!(webmention.domain().as_deref(), Some("example.org"));
+
+    let mut edited = reply.clone();
+    edited.content = "<p>Hello</p>".to_string();
+    edited.upsert(&conn).unwrap();
+    let stored = Reply::get(&conn, 1).unwrap();
+    assert_eq!(stored.content, "<p>Hello</p>");
+    assert_eq!(stored.status, Status::Pending);
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReactionKind {
+    Like,
+    Announce,
+}
+
+impl ReactionKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReactionKind::Like => "Like",
+            ReactionKind::Announce => "Announce",
+        }
+    }
+}
+
+/// A like or a boost of a post.
+pub struct Reaction;
+
+impl Reaction {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS reactions (
+                activity_id TEXT PRIMARY KEY,
+                post_id INTEGER NOT NULL,
+                actor TEXT NOT NULL,
+                kind TEXT NOT NULL
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    pub fn insert(
+        conn: &Connection,
+        activity_id: &str,
+        post_id: i64,
+        actor: &str,
+        kind: ReactionKind,
+    ) -> Result<usize> {
+        // One reaction of each kind per actor, so resending is harmless.
+        let stmt = "
+            INSERT OR REPLACE INTO reactions (activity_id, post_id, actor, kind)
+            SELECT ?1, ?2, ?3, ?4
+            WHERE NOT EXISTS (
+                SELECT 1 FROM reactions
+                WHERE post_id = ?2 AND actor = ?3 AND kind = ?4 AND activity_id != ?1
+            );
+        ";
+        conn.execute(stmt, params![activity_id, post_id, actor, kind.as_str()])
+    }
+    pub fn delete(conn: &Connection, activity_id: &str, actor: &str) -> Result<usize> {
+        let stmt = "DELETE FROM reactions WHERE activity_id = ? AND actor = ?";
+        conn.execute(stmt, [activity_id, actor])
+    }
+    pub fn count(conn: &Connection, post_id: i64, kind: ReactionKind) -> Result<i64> {
+        let stmt = "SELECT COUNT(*) FROM reactions WHERE post_id = ? AND kind = ?";
+        conn.query_row(stmt, params![post_id, kind.as_str()], |row| row.get(0))
+    }
+}
+
+/// Domains from which all activities are ignored.
+pub struct BlockedDomain;
+
+impl BlockedDomain {
+    pub fn create_table(conn: &Connection) -> Result<usize> {
+        let stmt = "
+            CREATE TABLE IF NOT EXISTS blocked_domains (
+                domain TEXT PRIMARY KEY
+            );
+        ";
+        conn.execute(stmt, [])
+    }
+    /// Block the domain and hide everything that was received from it.
+    pub fn insert(conn: &Connection, domain: &str) -> Result<()> {
+        let stmt = "INSERT OR IGNORE INTO blocked_domains (domain) VALUES (?)";
+        conn.execute(stmt, [domain])?;
+        for reply in Reply::list(conn)? {
+            if reply.domain().as_deref() == Some(domain) {
+                Reply::set_status(conn, reply.id, Status::Hidden)?;
+            }
+        }
+        let stmt = "SELECT activity_id, actor FROM reactions";
+        let reactions = conn
+            .prepare(stmt)?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<Vec<(String, String)>, _>>()?;
+        for (activity_id, actor) in reactions {
+            if domain_of(&actor).as_deref() == Some(domain) {
+                Reaction::delete(conn, &activity_id, &actor)?;
+            }
+        }
+        Ok(())
+    }
+    pub fn delete(conn: &Connection, domain: &str) -> Result<usize> {
+        conn.execute("DELETE FROM blocked_domains WHERE domain = ?", [domain])
+    }
+    pub fn list(conn: &Connection) -> Result<Vec<String>> {
+        let stmt = "SELECT domain FROM blocked_domains ORDER BY domain";
+        conn.prepare(stmt)?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()
+    }
+    pub fn is_blocked(conn: &Connection, url: &str) -> bool {
+        let domain = match domain_of(url) {
+            Some(domain) => domain,
+            None => return false,
+        };
+        let stmt = "SELECT COUNT(*) FROM blocked_domains WHERE domain = ?";
+        let count: i64 = conn
+            .query_row(stmt, [domain], |row| row.get(0))
+            .unwrap_or(0);
+        0 < count
+    }
+}
+
+/// Whether the URL is safe to use as link, unlike for example `javascript:`.
+fn is_http(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| url.scheme() == "http" || url.scheme() == "https")
+}
+
+pub fn domain_of(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+    url.host_str().map(|host| host.to_string())
+}
+
+/// Convert remote HTML to safe HTML by only keeping the text and line breaks.
+///
+/// Links and formatting are lost, but this avoids having to trust a HTML
+/// sanitizer with content from any server on the internet.
+pub fn sanitize(html: &str) -> String {
+    let breaks = regex::Regex::new(r"(?i)<br\s*/?>|</p>").unwrap();
+    let html = breaks.replace_all(html, "\n");
+    let tags = regex::Regex::new(r"<[^>]*>").unwrap();
+    let text = tags.replace_all(&html, "");
+    // Any remaining angle brackets are not part of a tag.
+    let text = text.replace('<', "&lt;").replace('>', "&gt;");
+    text.split('\n')
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| format!("<p>{line}</p>"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[test]
+fn test_sanitize() {
+    let html = r#"<p><span class="h-card"><a href="https://example.com/@a">@<span>a</span></a></span> Nice post!</p><p>Line<br>break</p>"#;
+    assert_eq!(
+        sanitize(html),
+        "<p>@a Nice post!</p>\n<p>Line</p>\n<p>break</p>"
+    );
+    let html = "<script>alert(1)</script><img src=x onerror=alert(1)>1 < 2";
+    assert_eq!(sanitize(html), "<p>alert(1)1 &lt; 2</p>");
+    assert_eq!(sanitize("a &amp; b"), "<p>a &amp; b</p>");
+}
+
+/// Return the id of the post that the URL points to, if it is one of ours.
+pub fn post_id_from_url(ctx: &ServerContext, url: &str) -> Option<i64> {
+    let base = ctx.base_url();
+    let path = url.strip_prefix(&base)?;
+    let rest = path
+        .strip_prefix("/ap/notes/")
+        .or(path.strip_prefix("/posts/"))?;
+    let id = rest.split(['/', '#', '?']).next()?;
+    id.parse::<i64>().ok()
+}
+
+fn actor_name(actor: &Value) -> String {
+    let name = actor["name"].as_str().unwrap_or_default();
+    if name.is_empty() {
+        actor["preferredUsername"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Store a reply to one of our posts.
+///
+/// Creates that do not reply to one of our posts are ignored.
+pub fn handle_create(ctx: &ServerContext, actor: &Value, object: &Value) -> Result<(), String> {
+    let in_reply_to = match object["inReplyTo"].as_str() {
+        Some(in_reply_to) => in_reply_to,
+        None => return Ok(()),
+    };
+    let post_id = match post_id_from_url(ctx, in_reply_to) {
+        Some(post_id) => post_id,
+        None => return Ok(()),
+    };
+    if Post::get(&ctx.conn(), post_id).is_err() {
+        return Ok(());
+    }
+    let actor_id = actor["id"].as_str().unwrap_or_default();
+    if object["attributedTo"].as_str() != Some(actor_id) {
+        return Err("reply is not attributed to the actor".to_string());
+    }
+    let object_id = object["id"].as_str().ok_or("reply has no id")?;
+    if domain_of(object_id) != domain_of(actor_id) {
+        return Err("reply is not on the server of the actor".to_string());
+    }
+    let published = object["published"]
+        .as_str()
+        .and_then(|published| DateTime::parse_from_rfc3339(published).ok())
+        .map(|published| published.with_timezone(&Utc))
+        .unwrap_or(Utc::now());
+    let reply = Reply {
+        id: 0,
+        object_id: object_id.to_string(),
+        post_id,
+        author_name: actor_name(actor),
+        author_url: actor["url"]
+            .as_str()
+            .filter(|url| is_http(url))
+            .unwrap_or(actor_id)
+            .to_string(),
+        content: sanitize(object["content"].as_str().unwrap_or_default()),
+        published,
+        status: Status::Pending,
+    };
+    reply.upsert(&ctx.conn()).map_err(|e| e.to_string())?;
+    tracing::info!("New reply to post {post_id} from {actor_id}");
+    Ok(())
+}
+
+/// Count a like or announce of one of our posts.
+pub fn handle_reaction(
+    ctx: &ServerContext,
+    actor_id: &str,
+    activity: &Value,
+    kind: ReactionKind,
+) -> Result<(), String> {
+    let object = match &activity["object"] {
+        Value::String(object) => object.as_str(),
+        object => object["id"].as_str().unwrap_or_default(),
+    };
+    let post_id = match post_id_from_url(ctx, object) {
+        Some(post_id) => post_id,
+        None => return Ok(()),
+    };
+    if Post::get(&ctx.conn(), post_id).is_err() {
+        return Ok(());
+    }
+    let activity_id = activity["id"].as_str().ok_or("activity has no id")?;
+    Reaction::insert(&ctx.conn(), activity_id, post_id, actor_id, kind)
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+fn reply_html(reply: &Reply) -> String {
+    let name = escape_html(&reply.author_name);
+    let author = if is_http(&reply.author_url) {
+        let url = escape_html(&reply.author_url);
+        format!("<a href='{url}' rel='nofollow ugc'>{name}</a>")
+    } else {
+        name
+    };
+    let date = show_date(&reply.published);
+    format!(
+        "
+        <div class='reply'>
+            <div class='reply-header'>
+                {author} · {date}
+            </div>
+            <div class='reply-content'>
+                {}
+            </div>
+        </div>
+        ",
+        reply.content
+    )
+}
+
+#[test]
+fn test_reply_html() {
+    let mut reply = Reply {
+        id: 1,
+        object_id: "https://example.com/notes/1".to_string(),
+        post_id: 1,
+        author_name: "Alice".to_string(),
+        author_url: "https://example.com/@alice".to_string(),
+        content: "<p>Hi</p>".to_string(),
+        published: Utc::now(),
+        status: Status::Approved,
+    };
+    assert!(reply_html(&reply).contains("<a href='https://example.com/@alice'"));
+    reply.author_url = "javascript:alert(1)".to_string();
+    let html = reply_html(&reply);
+    assert!(!html.contains("javascript:"));
+    assert!(html.contains("Alice"));
+}
+
+/// HTML that is shown below the post with the reactions and approved replies.
+pub fn section(ctx: &ServerContext, post_id: i64) -> String {
+    let conn = ctx.conn();
+    let likes = Reaction::count(&conn, post_id, ReactionKind::Like).unwrap_or(0);
+    let announces = Reaction::count(&conn, post_id, ReactionKind::Announce).unwrap_or(0);
+    let replies = Reply::list_approved(&conn, post_id).unwrap_or_default();
+    if likes == 0 && announces == 0 && replies.is_empty() {
+        return "".to_string();
+    }
+    let replies = replies
+        .iter()
+        .map(reply_html)
+        .collect::<Vec<String>>()
+        .join("\n");
+    format!(
+        "
+        <div class='replies'>
+            <div class='reactions'>
+                <span title='likes'>❤️ {likes}</span>&nbsp;
+                <span title='boosts'>🔁 {announces}</span>
+            </div>
+            {replies}
+        </div>
+        "
+    )
+}
+
+fn moderation_buttons(reply: &Reply) -> String {
+    let id = reply.id;
+    let approve = if reply.status == Status::Approved {
+        ""
+    } else {
+        &format!(
+            "
+            <form action='/replies/approve/{id}' method='post' style='display: inline;'>
+                <button type='submit'>approve</button>
+            </form>
+            "
+        )
+    };
+    let hide = if reply.status == Status::Hidden {
+        ""
+    } else {
+        &format!(
+            "
+            <form action='/replies/hide/{id}' method='post' style='display: inline;'>
+                <button type='submit'>hide</button>
+            </form>
+            "
+        )
+    };
+    let domain = reply.domain().unwrap_or_default();
+    let domain = escape_html(&domain);
+    format!(
+        "
+        {approve}
+        {hide}
+        <form action='/replies/block/{id}' method='post' style='display: inline;'>
+            <button type='submit'>block {domain}</button>
+        </form>
+        "
+    )
+}
+
+async fn get_replies(State(ctx): State<ServerContext>, jar: CookieJar) -> Response<Body> {
+    let is_logged_in = is_logged_in(&ctx, &jar);
+    if !is_logged_in {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let replies = match Reply::list(&ctx.conn()) {
+        Ok(replies) => replies,
+        Err(e) => {
+            let msg = "Could not get replies from database";
+            tracing::error!("{msg}: {e}");
+            return crate::serve::internal_server_error(&ctx, msg).await;
+        }
+    };
+    let replies = replies
+        .iter()
+        .map(|reply| {
+            let post_id = reply.post_id;
+            let status = reply.status.as_str();
+            format!(
+                "
+                <div style='padding-top: 12px; border-bottom: 1px solid var(--border);'>
+                    <div style='font-size: 0.8rem;'>
+                        Reply to <a href='/posts/{post_id}'>post {post_id}</a> ({status})
+                    </div>
+                    {}
+                    <div style='padding-bottom: 12px;'>
+                        {}
+                    </div>
+                </div>
+                ",
+                reply_html(reply),
+                moderation_buttons(reply)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let blocked = BlockedDomain::list(&ctx.conn()).unwrap_or_default();
+    let blocked = blocked
+        .iter()
+        .map(|domain| {
+            let domain = escape_html(domain);
+            format!(
+                "
+                <form action='/replies/unblock' method='post'>
+                    <code>{domain}</code>
+                    <input type='hidden' name='domain' value='{domain}'/>
+                    <button type='submit'>unblock</button>
+                </form>
+                "
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let body = format!(
+        "
+        <div style='margin-top: 5vh;'>
+            <p>
+                Replies from the fediverse are only shown below the post after
+                they have been approved.
+            </p>
+            {replies}
+            <h3>Blocked domains</h3>
+            {blocked}
+        </div>
+        "
+    );
+    let settings = PageSettings::new("Replies", Some(is_logged_in), None, false, Top::GoHome, "");
+    let body = page(&ctx, &settings, &body).await;
+    response(StatusCode::OK, HeaderMap::new(), body, &ctx)
+}
+
+async fn moderate(ctx: &ServerContext, jar: &CookieJar, id: i64, status: Status) -> Response<Body> {
+    if !is_logged_in(ctx, jar) {
+        return crate::serve::unauthorized(ctx).await;
+    }
+    match Reply::set_status(&ctx.conn(), id, status) {
+        Ok(1) => crate::serve::see_other(ctx, "/replies"),
+        Ok(_) => not_found(State(ctx.clone())).await,
+        Err(e) => crate::serve::internal_server_error(ctx, &e.to_string()).await,
+    }
+}
+
+async fn post_approve(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+) -> Response<Body> {
+    moderate(&ctx, &jar, id, Status::Approved).await
+}
+
+async fn post_hide(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+) -> Response<Body> {
+    moderate(&ctx, &jar, id, Status::Hidden).await
+}
+
+async fn post_block(
+    State(ctx): State<ServerContext>,
+    Path(id): Path<i64>,
+    jar: CookieJar,
+) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    let reply = match Reply::get(&ctx.conn(), id) {
+        Ok(reply) => reply,
+        Err(_) => return not_found(State(ctx)).await,
+    };
+    let domain = match reply.domain() {
+        Some(domain) => domain,
+        None => return not_found(State(ctx)).await,
+    };
+    if let Err(e) = BlockedDomain::insert(&ctx.conn(), &domain) {
+        return crate::serve::internal_server_error(&ctx, &e.to_string()).await;
+    }
+    tracing::info!("Blocked domain {domain}");
+    crate::serve::see_other(&ctx, "/replies")
+}
+
+#[derive(Debug, Deserialize)]
+struct UnblockForm {
+    domain: String,
+}
+
+async fn post_unblock(
+    State(ctx): State<ServerContext>,
+    jar: CookieJar,
+    Form(form): Form<UnblockForm>,
+) -> Response<Body> {
+    if !is_logged_in(&ctx, &jar) {
+        return crate::serve::unauthorized(&ctx).await;
+    }
+    if let Err(e) = BlockedDomain::delete(&ctx.conn(), &form.domain) {
+        return crate::serve::internal_server_error(&ctx, &e.to_string()).await;
+    }
+    crate::serve::see_other(&ctx, "/replies")
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router
+        .clone()
+        .route("/replies", get(get_replies))
+        .route("/replies/approve/{id}", post(post_approve))
+        .route("/replies/hide/{id}", post(post_hide))
+        .route("/replies/block/{id}", post(post_block))
+        .route("/replies/unblock", post(post_unblock))
+}
@@ -0,0 +1,338 @@
+//! Restore a site from an archive created by `/api/download/all.tar.xz`.
+use crate::data::Post;
+use crate::files::File;
+use crate::settings::Settings;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use clap::Parser;
+use rusqlite::Connection;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde::Serialize;
+use std::io::BufReader;
+use std::io::Read;
+use tar::Archive;
+use xz2::read::XzDecoder;
+
+#[derive(Clone, Debug, Parser)]
+pub struct RestoreArgs {
+    /// Path to the `all.tar.xz` archive.
+    pub archive: String,
+    /// Only report what would change.
+    #[arg(long)]
+    pub dry_run: bool,
+    #[arg(long, env = "FX_DATABASE_PATH", default_value = "/data/db.sqlite")]
+    pub database_path: String,
+}
+
+/// What was (or, in a dry run, would be) changed by a restore.
+#[derive(Debug, Default, JsonSchema, Serialize)]
+pub struct RestoreReport {
+    pub dry_run: bool,
+    /// Ids of posts that did not exist or were deleted.
+    pub posts_created: Vec<i64>,
+    /// Ids of posts whose content or timestamps differ.
+    pub posts_updated: Vec<i64>,
+    pub posts_unchanged: usize,
+    pub settings_changed: bool,
+    /// Shas of files that did not exist.
+    pub files_added: Vec<String>,
+    pub files_unchanged: usize,
+}
+
+impl std::fmt::Display for RestoreReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let prefix = if self.dry_run {
+            "Would restore"
+        } else {
+            "Restored"
+        };
+        writeln!(f, "{prefix}:")?;
+        writeln!(f, "  posts created: {:?}", self.posts_created)?;
+        writeln!(f, "  posts updated: {:?}", self.posts_updated)?;
+        writeln!(f, "  posts unchanged: {}", self.posts_unchanged)?;
+        writeln!(f, "  settings changed: {}", self.settings_changed)?;
+        writeln!(f, "  files added: {:?}", self.files_added)?;
+        write!(f, "  files unchanged: {}", self.files_unchanged)
+    }
+}
+
+/// Parse the timestamps written by `create_archive` (or RFC 3339).
+///
+/// The formats that Jekyll and Hugo front matter commonly use are accepted
+/// too, where times without a timezone are taken to be UTC.
+pub(crate) fn parse_datetime(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim().trim_matches('\'').trim_matches('"');
+    if let Ok(dt) = DateTime::parse_from_rfc3339(text) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = DateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S %z") {
+        return Some(dt.with_timezone(&Utc));
+    }
+    let text = text.strip_suffix(" UTC").unwrap_or(text);
+    let naive = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+            date.and_hms_opt(0, 0, 0)
+        });
+    naive.map(|naive| naive.and_utc())
+}
+
+/// Parse a `posts/{id}.md` file with `created`, `updated` and optionally
+/// `slug` front matter.
+fn parse_post(id: i64, text: &str) -> Result<Post, String> {
+    let (front_matter, content) = crate::front_matter::split(text);
+    let created = front_matter.created;
+    let updated = front_matter.updated;
+    let created = created.ok_or_else(|| format!("post {id}: invalid created"))?;
+    let updated = updated.ok_or_else(|| format!("post {id}: invalid updated"))?;
+    Ok(Post {
+        id,
+        created,
+        updated,
+        content: crate::data::cleanup_content(content),
+        slug: front_matter.slug,
+    })
+}
+
+/// Guess the mime type from the extension since the archive does not store it.
+pub(crate) fn guess_mime_type(filename: &str) -> &'static str {
+    let ext = filename.rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "txt" => "text/plain",
+        "md" => "text/markdown",
+        "html" => "text/html",
+        "css" => "text/css",
+        "js" => "text/javascript",
+        "json" => "application/json",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// An entry in `files/index.toml`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct FileIndexEntry {
+    pub sha: String,
+    pub mime_type: String,
+    pub filename: String,
+}
+
+/// The `files/index.toml` in an archive which maps each sha to the metadata
+/// that is needed to serve the file at `/files/{sha}` again.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FileIndex {
+    pub files: Vec<FileIndexEntry>,
+}
+
+impl FileIndex {
+    /// Return the entry for `files/{name}` if `name` is `{sha}/{filename}`
+    /// with a sha that is listed in the index.
+    pub fn entry(&self, name: &str) -> Option<&FileIndexEntry> {
+        let (sha, _) = name.split_once('/')?;
+        self.files.iter().find(|entry| entry.sha == sha)
+    }
+}
+
+/// Create the file for `files/{name}` in an archive.
+///
+/// Files that are listed in `files/index.toml` are stored as
+/// `files/{sha}/{filename}`. Other files, such as those in archives from
+/// before the index was added, are stored as `files/{filename}`, where the
+/// filename may contain slashes, and the sha is computed from the data.
+pub(crate) fn archived_file(index: &FileIndex, name: &str, data: Bytes) -> File {
+    match index.entry(name) {
+        Some(entry) => File {
+            sha: entry.sha.clone(),
+            mime_type: entry.mime_type.clone(),
+            filename: entry.filename.clone(),
+            data,
+        },
+        None => File::new(guess_mime_type(name), name, data),
+    }
+}
+
+#[test]
+fn test_archived_file() {
+    let index = FileIndex {
+        files: vec![FileIndexEntry {
+            sha: "69b83ddf8f65695f".to_string(),
+            mime_type: "text/plain".to_string(),
+            filename: "example.txt".to_string(),
+        }],
+    };
+    let data = || Bytes::from_static(b"a");
+    let file = archived_file(&index, "69b83ddf8f65695f/example.txt", data());
+    assert_eq!(file.sha, "69b83ddf8f65695f");
+    assert_eq!(file.filename, "example.txt");
+    // A legacy file in a directory is not mistaken for `{sha}/{filename}`.
+    let file = archived_file(&index, "example/a.txt", data());
+    assert_eq!(
+        file.sha,
+        File::new("text/plain", "example/a.txt", data()).sha
+    );
+    assert_eq!(file.filename, "example/a.txt");
+    assert_eq!(file.mime_type, "text/plain");
+    let file = archived_file(&FileIndex::default(), "a.txt", data());
+    assert_eq!(file.filename, "a.txt");
+}
+
+fn restore_post(
+    conn: &Connection,
+    post: &Post,
+    report: &mut RestoreReport,
+) -> rusqlite::Result<()> {
+    match Post::get(conn, post.id) {
+        Ok(current) if current.content != "<DELETED>" => {
+            let unchanged = current.content == post.content
+                && current.slug == post.slug
+                && current.created == post.created
+                && current.updated == post.updated;
+            if unchanged {
+                report.posts_unchanged += 1;
+                return Ok(());
+            }
+            report.posts_updated.push(post.id);
+        }
+        _ => report.posts_created.push(post.id),
+    }
+    if !report.dry_run {
+        Post::upsert(conn, post)?;
+    }
+    Ok(())
+}
+
+fn restore_settings(
+    conn: &Connection,
+    settings: Settings,
+    report: &mut RestoreReport,
+) -> rusqlite::Result<()> {
+    let settings = settings.normalize();
+    let current = Settings::from_db(conn).ok().map(Settings::normalize);
+    if current.as_ref() != Some(&settings) {
+        report.settings_changed = true;
+        if !report.dry_run {
+            settings.save(conn)?;
+        }
+    }
+    Ok(())
+}
+
+fn restore_file(
+    conn: &Connection,
+    file: &File,
+    report: &mut RestoreReport,
+) -> rusqlite::Result<()> {
+    if File::get(conn, &file.sha).is_ok() {
+        report.files_unchanged += 1;
+        return Ok(());
+    }
+    report.files_added.push(file.sha.clone());
+    if !report.dry_run {
+        File::insert(conn, file)?;
+    }
+    Ok(())
+}
+
+/// Restore the `all.tar.xz` archive that is read from `reader`.
+///
+/// Entries are restored while the archive is read, so only one file is held
+/// in memory at a time. Everything happens in one transaction so that an
+/// invalid archive changes nothing.
+///
+/// Posts keep their ids and timestamps. Posts and files that are not in the
+/// archive are left untouched. `files/index.toml` comes before the files in
+/// archives that are created by fx, see `archived_file`.
+pub fn restore<R: Read>(
+    conn: &mut Connection,
+    reader: R,
+    dry_run: bool,
+) -> Result<RestoreReport, String> {
+    let mut report = RestoreReport {
+        dry_run,
+        ..Default::default()
+    };
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let mut ar = Archive::new(XzDecoder::new(reader));
+    let mut index = FileIndex::default();
+    let entries = ar.entries().map_err(|e| format!("invalid archive: {e}"))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("invalid archive: {e}"))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map_err(|e| e.to_string())?;
+        let path = path.to_string_lossy().to_string();
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .map_err(|e| format!("failed to read {path}: {e}"))?;
+        let result = if let Some(name) = path.strip_prefix("posts/") {
+            let id = name
+                .strip_suffix(".md")
+                .and_then(|id| id.parse::<i64>().ok())
+                .ok_or_else(|| format!("unexpected post path: {path}"))?;
+            let text = String::from_utf8(data).map_err(|e| format!("{path}: {e}"))?;
+            restore_post(&tx, &parse_post(id, &text)?, &mut report)
+        } else if path == "settings/settings.toml" {
+            let text = String::from_utf8(data).map_err(|e| format!("{path}: {e}"))?;
+            let settings = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+            restore_settings(&tx, settings, &mut report)
+        } else if path == "files/index.toml" {
+            let text = String::from_utf8(data).map_err(|e| format!("{path}: {e}"))?;
+            index = toml::from_str(&text).map_err(|e| format!("{path}: {e}"))?;
+            Ok(())
+        } else if let Some(name) = path.strip_prefix("files/") {
+            let file = archived_file(&index, name, Bytes::from(data));
+            restore_file(&tx, &file, &mut report)
+        } else {
+            tracing::warn!("ignoring unknown archive entry: {path}");
+            Ok(())
+        };
+        result.map_err(|e| format!("failed to restore {path}: {e}"))?;
+    }
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(report)
+}
+
+/// Restore from the command line while the server is not running.
+pub fn run(args: &RestoreArgs) {
+    let archive = std::fs::File::open(&args.archive).expect("Failed to open archive");
+    let mut conn = Connection::open(&args.database_path).expect("Failed to open database");
+    crate::data::init_tables(&conn);
+    match restore(&mut conn, BufReader::new(archive), args.dry_run) {
+        Ok(report) => println!("{report}"),
+        Err(e) => {
+            eprintln!("Failed to restore: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[test]
+fn test_parse_post() {
+    let text = "---\ncreated: '2024-01-02 03:04:05 UTC'\nupdated: '2024-01-03 03:04:05.123 UTC'\n---\n\n# Title\n\nText\n\n";
+    let post = parse_post(7, text).unwrap();
+    assert_eq!(post.id, 7);
+    assert_eq!(post.created.to_rfc3339(), "2024-01-02T03:04:05+00:00");
+    assert_eq!(post.updated.timestamp(), 1704251045);
+    assert_eq!(post.content, "# Title\n\nText\n");
+    assert_eq!(post.slug, None);
+    let text = "---\ncreated: '2024-01-02 03:04:05 UTC'\nupdated: '2024-01-02 03:04:05 UTC'\nslug: 'hello'\n---\n\nText\n";
+    assert_eq!(parse_post(7, text).unwrap().slug.as_deref(), Some("hello"));
+    assert!(parse_post(1, "# No front matter").is_err());
+}
@@ -58,6 +58,7 @@ async fn search(ctx: &ServerContext, q: &str) -> Vec<Post> {
             id,
             created,
             content,
+            slug UNINDEXED,
             content=posts,
             tokenize=trigram
         );
@@ -65,8 +66,8 @@ async fn search(ctx: &ServerContext, q: &str) -> Vec<Post> {
     conn.execute(stmt, []).unwrap();
 
     let stmt = "
-        INSERT INTO posts_fts (id, created, content)
-        SELECT id, created, content FROM posts;
+        INSERT INTO posts_fts (id, created, content, slug)
+        SELECT id, created, content, slug FROM posts;
     ";
     conn.execute(stmt, []).unwrap();
 
@@ -83,6 +84,7 @@ async fn search(ctx: &ServerContext, q: &str) -> Vec<Post> {
                 created: SqliteDateTime::from_sqlite(&created),
                 updated: SqliteDateTime::from_sqlite(&created),
                 content,
+                slug: row.get("slug")?,
             };
             Ok(post)
         })
@@ -6,8 +6,10 @@ use crate::data::Kv;
 use crate::data::Post;
 use crate::html::PageSettings;
 use crate::html::Top;
+use crate::html::escape_html;
 use crate::html::page;
 use crate::html::wrap_post_content;
+use crate::og::OgCache;
 use axum::Form;
 use axum::Router;
 use axum::body::Body;
@@ -48,6 +50,10 @@ pub struct ServerContext {
     pub pool: DbPool,
     pub salt: Salt,
     pub blog_cache: Arc<Mutex<BlogCache>>,
+    /// Held while deliveries are being sent to avoid sending them twice.
+    pub delivery_lock: Arc<Mutex<()>>,
+    /// Rendered Open Graph images.
+    pub og_cache: Arc<Mutex<OgCache>>,
 }
 
 impl ServerContext {
@@ -62,6 +68,8 @@ impl ServerContext {
             pool,
             salt,
             blog_cache,
+            delivery_lock: Arc::new(Mutex::new(())),
+            og_cache: Arc::new(Mutex::new(OgCache::default())),
         }
     }
     pub fn conn(&self) -> PooledConnection<SqliteConnectionManager> {
@@ -360,7 +368,7 @@ async fn get_edit(
     };
     let title = crate::md::extract_html_title(&post);
     let title = format!("Edit '{title}'");
-    let body = crate::html::edit_post_form(&post);
+    let body = crate::html::edit_post_form(&post, &post.version());
     let extra_head = Kv::get_or_empty_string(&ctx.conn(), "extra_head");
     let settings = PageSettings::new(
         &title,
@@ -374,13 +382,37 @@ async fn get_edit(
     response::<String>(StatusCode::OK, HeaderMap::new(), body, &ctx)
 }
 
-fn iso8601(dt: &chrono::DateTime<chrono::Utc>) -> String {
+pub fn iso8601(dt: &chrono::DateTime<chrono::Utc>) -> String {
     dt.format("%Y-%m-%dT%H:%M:%SZ").to_string()
 }
 
+/// `BlogPosting` structured data for search engines, see
+/// <https://developers.google.com/search/docs/appearance/structured-data/article>.
+fn blog_posting(ctx: &ServerContext, post: &Post, title: &str, author: &str, url: &str) -> String {
+    let base = ctx.base_url();
+    let json = serde_json::json!({
+        "@context": "https://schema.org",
+        "@type": "BlogPosting",
+        "headline": title,
+        "url": url,
+        "mainEntityOfPage": url,
+        "datePublished": iso8601(&post.created),
+        "dateModified": iso8601(&post.updated),
+        "image": crate::og::image_url(ctx, post),
+        "author": {
+            "@type": "Person",
+            "name": author,
+            "url": format!("{base}/"),
+        },
+    });
+    // Prevent the content from closing the script element.
+    let json = json.to_string().replace("</", "<\\/");
+    format!("<script type='application/ld+json'>{json}</script>")
+}
+
 async fn get_post_with_slug(
     State(ctx): State<ServerContext>,
-    Path((id, _slug)): Path<(i64, String)>,
+    Path((id, requested_slug)): Path<(i64, String)>,
     jar: CookieJar,
 ) -> Response<Body> {
     let is_logged_in = is_logged_in(&ctx, &jar);
@@ -392,14 +424,25 @@ async fn get_post_with_slug(
     if post.content == "<DELETED>" {
         return not_found(State(ctx)).await;
     }
+    let slug = crate::md::extract_slug(&post);
+    // Old slugs, for example from before the title was edited, keep working.
+    if requested_slug != slug {
+        return redirect_to_post(&ctx, &post, &slug);
+    }
     let title = crate::md::extract_html_title(&post);
     let author = Kv::get(&ctx.conn(), "author_name").unwrap();
     let author = String::from_utf8(author).unwrap();
     // Open Graph uses ISO 8601 according to <https://ogp.me/>.
     let created = iso8601(&post.created);
     let updated = iso8601(&post.updated);
-    let slug = crate::md::extract_slug(&post);
-    let canonical = format!("{}/posts/{}/{slug}", &ctx.base_url(), &post.id);
+    let front_matter = post.front_matter();
+    let canonical = match &front_matter.canonical {
+        Some(canonical) => escape_html(canonical),
+        None => format!("{}/posts/{}/{slug}", &ctx.base_url(), &post.id),
+    };
+    let image = escape_html(&crate::og::image_url(&ctx, &post));
+    let url = format!("{}{}", ctx.base_url(), crate::html::post_link(&post, &slug));
+    let blog_posting = blog_posting(&ctx, &post, &title, &author, &url);
     let extra_head = Kv::get_or_empty_string(&ctx.conn(), "extra_head");
     let extra_head = indoc::formatdoc! {r#"
         <meta property='article:author' content='{author}'/>
@@ -407,11 +450,14 @@ async fn get_post_with_slug(
         <meta property='article:modified_time' content='{updated}'/>
         <meta property='og:url' content='{canonical}'/>
         <meta property='og:type' content='article'/>
+        <meta property='og:image' content='{image}'/>
+        <meta name='twitter:card' content='summary_large_image'/>
         <link rel='canonical' href='{canonical}'/>
+        {blog_posting}
         {}
     "#, &extra_head};
     let description = crate::md::extract_html_description(&post);
-    let settings = PageSettings::new(
+    let mut settings = PageSettings::new(
         &title,
         Some(is_logged_in),
         Some(&description),
@@ -419,7 +465,11 @@ async fn get_post_with_slug(
         Top::GoHome,
         &extra_head,
     );
+    if let Some(lang) = &front_matter.lang {
+        settings.set_lang(lang);
+    }
     let mut body = wrap_post_content(&post, &slug, false);
+    body = format!("{body}\n{}", crate::replies::section(&ctx, post.id));
     if is_logged_in {
         body = format!("{}\n{body}", crate::html::edit_post_buttons(&ctx, &post));
     }
@@ -451,7 +501,12 @@ async fn get_post(
     if slug.is_empty() {
         return get_post_with_slug(State(ctx), Path((id, slug)), jar).await;
     }
-    let url = crate::html::post_link(&post, &slug);
+    redirect_to_post(&ctx, &post, &slug)
+}
+
+/// Redirect to the URL of the post with the current slug.
+fn redirect_to_post(ctx: &ServerContext, post: &Post, slug: &str) -> Response<Body> {
+    let url = crate::html::post_link(post, slug);
     // Same behavior as Reddit. Any slug is accepted and then redirected to the
     // right page. I couldn't figure out the Reddit status code, but permanent
     // redirect seems suitable.
@@ -464,12 +519,12 @@ async fn get_post(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 HeaderMap::new(),
                 format!("Failed to set redirect URL to '{url}'"),
-                &ctx,
+                ctx,
             );
         }
     };
     headers.insert("Location", loc);
-    response(StatusCode::PERMANENT_REDIRECT, headers, "", &ctx)
+    response(StatusCode::PERMANENT_REDIRECT, headers, "", ctx)
 }
 
 pub async fn not_found(State(ctx): State<ServerContext>) -> Response<Body> {
@@ -501,7 +556,32 @@ fn is_https(headers: &HeaderMap) -> bool {
     false
 }
 
-async fn get_login(State(ctx): State<ServerContext>, headers: HeaderMap) -> Response<Body> {
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    /// The local path to return to after logging in.
+    next: Option<String>,
+}
+
+/// Return the path if it is on this site, so that the login cannot be used
+/// to redirect to other sites.
+fn local_path(next: Option<&str>) -> Option<&str> {
+    next.filter(|next| next.starts_with('/') && !next.starts_with("//") && !next.starts_with("/\\"))
+}
+
+#[test]
+fn test_local_path() {
+    assert_eq!(local_path(Some("/auth?a=b")), Some("/auth?a=b"));
+    assert_eq!(local_path(Some("//evil.example.com")), None);
+    assert_eq!(local_path(Some("/\\evil.example.com")), None);
+    assert_eq!(local_path(Some("https://evil.example.com")), None);
+    assert_eq!(local_path(None), None);
+}
+
+async fn get_login(
+    State(ctx): State<ServerContext>,
+    headers: HeaderMap,
+    Query(query): Query<LoginQuery>,
+) -> Response<Body> {
     let error = if ctx.args.production && !is_https(&headers) {
         Some(
             "Did not find X-Forwarded-Proto header which suggests the request is not over HTTPS. Login will probably fail. Use a reverse proxy like Caddy with HTTPS to fix this.",
@@ -509,7 +589,8 @@ async fn get_login(State(ctx): State<ServerContext>, headers: HeaderMap) -> Resp
     } else {
         None
     };
-    let body = crate::html::login(&ctx, error).await;
+    let next = local_path(query.next.as_deref());
+    let body = crate::html::login(&ctx, error, next).await;
     tracing::info!("\"GET /login HTTP/1.1\" 200");
     response::<String>(StatusCode::OK, HeaderMap::new(), body, &ctx)
 }
@@ -523,8 +604,10 @@ pub struct LoginForm {
 async fn post_login(
     State(ctx): State<ServerContext>,
     jar: CookieJar,
+    Query(query): Query<LoginQuery>,
     Form(form): Form<LoginForm>,
 ) -> Result<(CookieJar, Redirect), Response<Body>> {
+    let next = local_path(query.next.as_deref());
     let password = match &ctx.args.password {
         Some(password) => password,
         None => {
@@ -549,10 +632,10 @@ async fn post_login(
     match new_jar {
         Some(jar) => {
             tracing::info!("\"POST /login HTTP/1.1\" 200");
-            Ok((jar, Redirect::to("/")))
+            Ok((jar, Redirect::to(next.unwrap_or("/"))))
         }
         None => {
-            let body = crate::html::login(&ctx, Some("Invalid username or password"));
+            let body = crate::html::login(&ctx, Some("Invalid username or password"), next);
             tracing::info!("\"POST /login HTTP/1.1\" 401");
             Err(response::<String>(
                 StatusCode::UNAUTHORIZED,
@@ -586,12 +669,17 @@ async fn post_delete(
     }
     Post::delete(&ctx.conn(), id).unwrap();
     crate::trigger::trigger_github_backup(&ctx).await;
+    crate::ap::send_delete(&ctx, id).await;
     Ok(Redirect::to("/"))
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct EditPostForm {
     pub content: String,
+    /// The `Post::version` on which the edit is based.
+    pub version: Option<String>,
+    /// Empty to use the slug from the title.
+    pub slug: Option<String>,
 }
 
 /// Return a 303 redirect to the given url.
@@ -640,28 +728,62 @@ async fn post_edit(
     let input = String::from_utf8(bytes).unwrap();
     let publish = input.contains("publish=Publish");
     let form = serde_urlencoded::from_str::<EditPostForm>(&input).unwrap();
-    let created = match Post::get(&ctx.conn(), id) {
-        Ok(post) => post.created,
-        Err(_) => Utc::now(),
+    let saved = Post::get(&ctx.conn(), id).ok();
+    let created = match &saved {
+        Some(post) => post.created,
+        None => Utc::now(),
     };
     let post = Post {
         id,
         created,
         updated: Utc::now(),
         content: trim_newline_suffix(&form.content),
+        slug: form.slug.as_deref().and_then(crate::md::normalize_slug),
     };
     if publish {
-        let post = post.update(&ctx.conn());
-        if post.is_err() {
-            return response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                HeaderMap::new(),
-                format!("Failed to update post: {}", post.err().unwrap()),
-                &ctx,
-            );
+        // The saved version, if it changed since the edit form was opened.
+        let conflict = {
+            let conn = ctx.conn();
+            // A form without a version is not based on the saved version.
+            let result = match &saved {
+                Some(saved) if form.version.as_deref() == Some(saved.version().as_str()) => {
+                    post.update_if_unchanged(&conn, saved)
+                }
+                Some(_) => Ok(0),
+                None => post.update(&conn),
+            };
+            match result {
+                Ok(0) if saved.is_some() => Post::get(&conn, id).ok(),
+                Ok(_) => None,
+                Err(e) => {
+                    return response(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        HeaderMap::new(),
+                        format!("Failed to update post: {e}"),
+                        &ctx,
+                    );
+                }
+            }
         };
+        if let Some(saved) = conflict {
+            tracing::info!("\"POST /posts/edit/{id} HTTP/1.1\" 409");
+            let settings = PageSettings::new(
+                "Edit conflict",
+                Some(is_logged_in),
+                None,
+                false,
+                Top::GoBack,
+                extra_head,
+            );
+            let body = crate::html::edit_conflict(&post, &saved);
+            let body = page(&ctx, &settings, &body).await;
+            return response(StatusCode::CONFLICT, HeaderMap::new(), body, &ctx);
+        }
         let url = format!("/posts/{}", id);
         crate::trigger::trigger_github_backup(&ctx).await;
+        crate::ap::send_update(&ctx, id).await;
+        let previous = saved.as_ref().map(|saved| saved.content.as_str());
+        crate::webmention::send_mentions(&ctx, id, previous).await;
         see_other(&ctx, &url)
     } else {
         let preview = crate::html::wrap_post_content(&post, "", false);
@@ -706,17 +828,21 @@ async fn post_add(
     if publish {
         let now = Utc::now();
         let content = trim_newline_suffix(&form.content);
-        let post_id = Post::insert(&ctx.conn(), now, now, &content);
-        if let Err(_e) = post_id {
-            return response(
-                StatusCode::INTERNAL_SERVER_ERROR,
-                HeaderMap::new(),
-                "Failed to insert post",
-                &ctx,
-            );
+        let post_id = match Post::insert(&ctx.conn(), now, now, &content, None) {
+            Ok(post_id) => post_id,
+            Err(_e) => {
+                return response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    HeaderMap::new(),
+                    "Failed to insert post",
+                    &ctx,
+                );
+            }
         };
         let url = "/?reset_forms=true";
         crate::trigger::trigger_github_backup(&ctx).await;
+        crate::ap::send_create(&ctx, post_id).await;
+        crate::webmention::send_mentions(&ctx, post_id, None).await;
         see_other(&ctx, url)
     } else {
         let post = Post {
@@ -724,6 +850,7 @@ async fn post_add(
             created: Utc::now(),
             updated: Utc::now(),
             content: form.content,
+            slug: None,
         };
         let is_front_page_preview = false;
         let preview = crate::html::wrap_post_content(&post, "", is_front_page_preview);
@@ -732,8 +859,23 @@ async fn post_add(
     }
 }
 
-async fn get_webfinger(State(ctx): State<ServerContext>) -> Response<Body> {
-    let body = crate::ap::webfinger(&ctx).await;
+#[derive(Deserialize)]
+struct WebfingerQuery {
+    resource: Option<String>,
+}
+
+async fn get_webfinger(
+    State(ctx): State<ServerContext>,
+    Query(query): Query<WebfingerQuery>,
+) -> Response<Body> {
+    let resource = match query.resource {
+        Some(resource) => resource,
+        None => {
+            let msg = "Missing resource parameter";
+            return error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", msg).await;
+        }
+    };
+    let body = crate::ap::webfinger(&ctx, &resource).await;
     let body = match body {
         Some(body) => body,
         None => return not_found(State(ctx)).await,
@@ -744,6 +886,7 @@ async fn get_webfinger(State(ctx): State<ServerContext>) -> Response<Body> {
         "Content-Type",
         HeaderValue::from_static("application/jrd+json; charset=utf-8"),
     );
+    headers.insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
     response::<String>(StatusCode::OK, headers, body, &ctx)
 }
 
@@ -767,13 +910,22 @@ pub fn app(ctx: ServerContext) -> Router {
         .route("/static/katex.js", get(get_katex))
         .route("/static/nodefer.js", get(get_nodefer))
         .route("/.well-known/webfinger", get(get_webfinger));
+    let router = crate::ap::routes(&router);
     let router = crate::api::routes(&router);
     let router = crate::blogroll::routes(&router);
     let router = crate::discovery::routes(&router);
+    let router = crate::feed::routes(&router);
     let router = crate::files::routes(&router);
+    let router = crate::import::routes(&router);
+    let router = crate::indieauth::routes(&router);
+    let router = crate::micropub::routes(&router);
+    let router = crate::og::routes(&router);
+    let router = crate::redirects::routes(&router);
+    let router = crate::replies::routes(&router);
     let router = crate::search::routes(&router);
     let router = crate::settings::routes(&router);
-    let router = router.fallback(not_found);
+    let router = crate::webmention::routes(&router);
+    let router = router.fallback(crate::redirects::fallback);
     // Files larger than this will be rejected during upload.
     let limit = 15 * 1024 * 1024;
     router.with_state(ctx).layer(DefaultBodyLimit::max(limit))
@@ -819,6 +971,7 @@ async fn schedule_jobs(blog_cache: Arc<Mutex<BlogCache>>, ctx: ServerContext) {
             return;
         }
     };
+    let delivery_ctx = ctx.clone();
     let ctx = Arc::new(Mutex::new(ctx));
     let task = move |_uuid, _l| {
         let blog_cache = blog_cache.clone();
@@ -846,10 +999,26 @@ async fn schedule_jobs(blog_cache: Arc<Mutex<BlogCache>>, ctx: ServerContext) {
             tracing::error!("Failed to add job to scheduler: {}", e);
         }
     }
+    // Retry failed ActivityPub deliveries every minute.
+    let task = move |_uuid, _l| {
+        let ctx = delivery_ctx.clone();
+        async move {
+            crate::delivery::process(&ctx).await;
+        }
+        .boxed()
+    };
+    let job = Job::new_async("00 * * * * *", task).unwrap();
+    match scheduler.add(job).await {
+        Ok(_) => (),
+        Err(e) => {
+            tracing::error!("Failed to add job to scheduler: {}", e);
+        }
+    }
     scheduler.start().await.unwrap();
 }
 
-pub async fn run(args: &ServeArgs) {
+/// Connect to the database and create the context without starting any jobs.
+pub async fn init_context(args: &ServeArgs) -> ServerContext {
     let pool = data::connect(args).unwrap();
     let conn = pool.get().unwrap();
     data::init(args, &conn);
@@ -857,8 +1026,12 @@ pub async fn run(args: &ServeArgs) {
     let blog_cache = init_blog_cache(&conn).await;
     drop(conn);
     let blog_cache = Arc::new(Mutex::new(blog_cache));
-    let ctx = ServerContext::new(args.clone(), pool, salt, blog_cache.clone()).await;
-    schedule_jobs(blog_cache.clone(), ctx.clone()).await;
+    ServerContext::new(args.clone(), pool, salt, blog_cache).await
+}
+
+pub async fn run(args: &ServeArgs) {
+    let ctx = init_context(args).await;
+    schedule_jobs(ctx.blog_cache.clone(), ctx.clone()).await;
     let app = app(ctx);
     // Listen on both IPv4 and IPv6. This seems to not be necessary behind the
     // Caddy reverse proxy for IPv6 to work, but could probably be useful for
@@ -1,3 +1,5 @@
+use crate::changes::Change;
+use crate::changes::Kind;
 use crate::data::Kv;
 use crate::data::cleanup_content;
 use crate::html::PageSettings;
@@ -17,10 +19,11 @@ use axum::routing::get;
 use axum::routing::post;
 use axum_extra::extract::CookieJar;
 use rusqlite::Connection;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
 pub struct Settings {
     pub site_name: String,
     pub site_description: String,
@@ -58,6 +61,60 @@ impl Settings {
     }
     pub fn set_about(conn: &Connection, about: &str) -> rusqlite::Result<()> {
         Kv::insert(conn, "about", about.as_bytes())?;
+        Change::record(conn, Kind::Settings, "settings", false)?;
+        Ok(())
+    }
+    /// Clean up the fields in the same way as they are stored.
+    pub fn normalize(self) -> Self {
+        // Browsers send "on" for a checked checkbox, but allow "off" for
+        // formats that have no null.
+        let dark_mode = self.dark_mode.filter(|value| value.trim() == "on");
+        let mut feeds = self
+            .blogroll_feeds
+            .split("\n")
+            .map(|line| line.trim())
+            .collect::<Vec<_>>();
+        feeds.sort();
+        Self {
+            site_name: self.site_name.trim().to_string(),
+            site_description: self.site_description.trim().to_string(),
+            author_name: self.author_name.trim().to_string(),
+            about: cleanup_content(&self.about),
+            dark_mode,
+            extra_head: cleanup_content(&self.extra_head),
+            blogroll_feeds: feeds.join("\n").trim().to_string(),
+        }
+    }
+    pub fn validate(&self) -> Result<(), String> {
+        if self.site_name.trim().is_empty() {
+            return Err("site name is required".to_string());
+        }
+        if self.author_name.trim().is_empty() {
+            return Err("author name is required".to_string());
+        }
+        for feed in self.blogroll_feeds.lines().filter(|l| !l.trim().is_empty()) {
+            if reqwest::Url::parse(feed.trim()).is_err() {
+                return Err(format!("invalid blogroll feed URL: {feed}"));
+            }
+        }
+        Ok(())
+    }
+    /// Store normalized settings.
+    pub fn save(&self, conn: &Connection) -> rusqlite::Result<()> {
+        Kv::insert(conn, "site_name", self.site_name.as_bytes())?;
+        Kv::insert(conn, "site_description", self.site_description.as_bytes())?;
+        Kv::insert(conn, "author_name", self.author_name.as_bytes())?;
+        let dark_mode = if self.dark_mode.is_some() {
+            "on"
+        } else {
+            "off"
+        };
+        Kv::insert(conn, "dark_mode", dark_mode.as_bytes())?;
+        Kv::insert(conn, "about", self.about.as_bytes())?;
+        Kv::insert(conn, "extra_head", self.extra_head.as_bytes())?;
+        let key = crate::data::BLOGROLL_SETTINGS_KEY;
+        Kv::insert(conn, key, self.blogroll_feeds.as_bytes())?;
+        Change::record(conn, Kind::Settings, "settings", false)?;
         Ok(())
     }
 }
@@ -159,6 +216,10 @@ async fn get_settings(State(ctx): State<ServerContext>, jar: CookieJar) -> Respo
             {}
             <input style='margin-left: 0;' type='submit' value='Save'/>
         </form>
+        <p style='font-size: 0.8rem;'>
+            To import posts from Mastodon or WordPress, go to <a href='/import'>import</a>.
+            To redirect old URLs to new pages, go to <a href='/redirects'>redirects</a>.
+        </p>
         ",
         text_input(
             InputType::Text,
@@ -230,7 +291,7 @@ async fn get_settings(State(ctx): State<ServerContext>, jar: CookieJar) -> Respo
     response(StatusCode::OK, HeaderMap::new(), body, &ctx)
 }
 
-async fn update_feeds(ctx: &ServerContext) {
+pub async fn update_feeds(ctx: &ServerContext) {
     let blog_cache = ctx.blog_cache.clone();
     let mut blog_cache = blog_cache.lock().await;
     blog_cache.update(ctx).await;
@@ -245,36 +306,14 @@ async fn post_settings(
     if !is_logged_in {
         return crate::serve::unauthorized(&ctx).await;
     }
-    {
-        let conn = ctx.conn();
-        Kv::insert(&conn, "site_name", form.site_name.trim().as_bytes()).unwrap();
-        Kv::insert(
-            &conn,
-            "site_description",
-            form.site_description.trim().as_bytes(),
-        )
-        .unwrap();
-        Kv::insert(&conn, "author_name", form.author_name.trim().as_bytes()).unwrap();
-        let dark_mode = if form.dark_mode.is_some() {
-            "on"
-        } else {
-            "off"
-        };
-        Kv::insert(&conn, "dark_mode", dark_mode.as_bytes()).unwrap();
-        let about = cleanup_content(&form.about);
-        Kv::insert(&conn, "about", about.as_bytes()).unwrap();
-        let extra_head = cleanup_content(&form.extra_head);
-        Kv::insert(&conn, "extra_head", extra_head.as_bytes()).unwrap();
-
-        let key = crate::data::BLOGROLL_SETTINGS_KEY;
-        let mut feeds = form
-            .blogroll_feeds
-            .split("\n")
-            .map(|line| line.trim())
-            .collect::<Vec<_>>();
-        feeds.sort();
-        let feeds = feeds.join("\n");
-        Kv::insert(&conn, key, feeds.trim().as_bytes()).unwrap();
+    let settings = form.normalize();
+    if let Err(e) = settings.validate() {
+        return crate::serve::error(&ctx, StatusCode::BAD_REQUEST, "Bad Request", &e).await;
+    }
+    if let Err(e) = settings.save(&ctx.conn()) {
+        let msg = "Could not save settings";
+        tracing::error!("{msg}: {e}");
+        return crate::serve::internal_server_error(&ctx, msg).await;
     }
     let ctx_clone = ctx.clone();
     tokio::task::spawn_blocking(async move || {
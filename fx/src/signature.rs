@@ -0,0 +1,323 @@
+//! HTTP Signatures for ActivityPub.
+//!
+//! Implements the `rsa-sha256` variant of
+//! <https://datatracker.ietf.org/doc/html/draft-cavage-http-signatures-12>
+//! since that is what Mastodon and most other fediverse servers expect.
+use crate::data::Kv;
+use axum::http::HeaderMap;
+use axum::http::HeaderValue;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::Url;
+use rsa::RsaPrivateKey;
+use rsa::RsaPublicKey;
+use rsa::pkcs1v15::Signature;
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs1v15::VerifyingKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::pkcs8::EncodePrivateKey;
+use rsa::pkcs8::EncodePublicKey;
+use rsa::pkcs8::LineEnding;
+use rsa::signature::SignatureEncoding;
+use rsa::signature::Signer;
+use rsa::signature::Verifier;
+use rusqlite::Connection;
+use sha2::Digest;
+use sha2::Sha256;
+
+const PRIVATE_KEY_KEY: &str = "ap_private_key";
+const PUBLIC_KEY_KEY: &str = "ap_public_key";
+
+/// The keypair that is used to sign outgoing ActivityPub requests.
+#[derive(Clone, Debug)]
+pub struct Keypair {
+    pub private_key: RsaPrivateKey,
+    /// The public key in SPKI PEM format as expected by `publicKeyPem`.
+    pub public_key_pem: String,
+}
+
+impl Keypair {
+    pub fn generate(bits: usize) -> Result<Self, String> {
+        let mut rng = rsa::rand_core::UnwrapErr(getrandom::SysRng);
+        let private_key = RsaPrivateKey::new(&mut rng, bits).map_err(|e| e.to_string())?;
+        let public_key = RsaPublicKey::from(&private_key);
+        let public_key_pem = public_key
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            private_key,
+            public_key_pem,
+        })
+    }
+    fn get(conn: &Connection) -> Option<Result<Self, String>> {
+        let private_key = Kv::get(conn, PRIVATE_KEY_KEY).ok()?;
+        let public_key = Kv::get(conn, PUBLIC_KEY_KEY).ok()?;
+        let parse = || {
+            let private_key = String::from_utf8(private_key).map_err(|e| e.to_string())?;
+            let private_key =
+                RsaPrivateKey::from_pkcs8_pem(&private_key).map_err(|e| e.to_string())?;
+            let public_key_pem = String::from_utf8(public_key).map_err(|e| e.to_string())?;
+            Ok(Self {
+                private_key,
+                public_key_pem,
+            })
+        };
+        Some(parse())
+    }
+    /// Return the keypair from the database or generate and store a new one.
+    ///
+    /// The key is generated lazily because most sites will never receive an
+    /// ActivityPub request and the generation takes a moment.
+    pub fn obtain(conn: &Connection) -> Result<Self, String> {
+        if let Some(keypair) = Self::get(conn) {
+            return keypair;
+        }
+        let keypair = Self::generate(2048)?;
+        let private_key = keypair
+            .private_key
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(|e| e.to_string())?;
+        // Both keys in one statement, so that when two requests generate a
+        // keypair at the same time, one of them is stored as a whole.
+        let stmt = "INSERT OR IGNORE INTO kv (key, value) VALUES (?1, ?2), (?3, ?4)";
+        let params = rusqlite::params![
+            PRIVATE_KEY_KEY,
+            private_key.as_bytes(),
+            PUBLIC_KEY_KEY,
+            keypair.public_key_pem.as_bytes(),
+        ];
+        conn.execute(stmt, params).map_err(|e| e.to_string())?;
+        Self::get(conn).ok_or("keypair was not stored")?
+    }
+}
+
+#[test]
+fn test_obtain() {
+    let conn = Connection::open_in_memory().unwrap();
+    Kv::create_table(&conn).unwrap();
+    let keypair = Keypair::obtain(&conn).unwrap();
+    let again = Keypair::obtain(&conn).unwrap();
+    assert_eq!(keypair.public_key_pem, again.public_key_pem);
+    assert_eq!(keypair.private_key, again.private_key);
+}
+
+/// Return the value for the `Digest` header.
+pub fn digest(body: &[u8]) -> String {
+    let hash = Sha256::digest(body);
+    format!("SHA-256={}", STANDARD.encode(hash))
+}
+
+/// Return the date in the format that is required by the `Date` header.
+pub fn http_date(dt: &chrono::DateTime<chrono::Utc>) -> String {
+    dt.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+fn signing_string(headers: &[(&str, String)]) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| format!("{name}: {value}"))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Return the headers that are required to sign a request to `url`.
+///
+/// The body is only signed (via the `Digest` header) when it is given. Mastodon
+/// requires this for `POST` requests.
+pub fn sign(
+    private_key: &RsaPrivateKey,
+    key_id: &str,
+    method: &str,
+    url: &str,
+    body: Option<&[u8]>,
+) -> Result<HeaderMap, String> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{host}:{port}"),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(format!("no host in {url}")),
+    };
+    let target = match url.query() {
+        Some(query) => format!("{} {}?{query}", method.to_lowercase(), url.path()),
+        None => format!("{} {}", method.to_lowercase(), url.path()),
+    };
+    let date = http_date(&chrono::Utc::now());
+    let mut signed = vec![
+        ("(request-target)", target),
+        ("host", host.clone()),
+        ("date", date.clone()),
+    ];
+    if let Some(body) = body {
+        signed.push(("digest", digest(body)));
+    }
+    let signing_key = SigningKey::<Sha256>::new(private_key.clone());
+    let signature = signing_key.sign(signing_string(&signed).as_bytes());
+    let signature = STANDARD.encode(signature.to_bytes());
+    let names = signed
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let header = format!(
+        r#"keyId="{key_id}",algorithm="rsa-sha256",headers="{names}",signature="{signature}""#
+    );
+    let mut headers = HeaderMap::new();
+    for (name, value) in signed.iter().skip(1) {
+        let value = HeaderValue::from_str(value).map_err(|e| e.to_string())?;
+        headers.insert(*name, value);
+    }
+    let header = HeaderValue::from_str(&header).map_err(|e| e.to_string())?;
+    headers.insert("Signature", header);
+    Ok(headers)
+}
+
+/// The parsed content of a `Signature` header.
+#[derive(Debug, PartialEq)]
+pub struct SignatureHeader {
+    pub key_id: String,
+    pub headers: Vec<String>,
+    pub signature: String,
+}
+
+impl SignatureHeader {
+    pub fn parse(headers: &HeaderMap) -> Option<Self> {
+        let header = headers.get("Signature")?.to_str().ok()?;
+        let mut key_id = None;
+        // Defaults to only `date` according to the specification.
+        let mut signed = vec!["date".to_string()];
+        let mut signature = None;
+        for part in header.split(',') {
+            let (name, value) = part.trim().split_once('=')?;
+            let value = value.trim_matches('"').to_string();
+            match name {
+                "keyId" => key_id = Some(value),
+                "headers" => signed = value.split(' ').map(|s| s.to_string()).collect(),
+                "signature" => signature = Some(value),
+                _ => (),
+            }
+        }
+        Some(Self {
+            key_id: key_id?,
+            headers: signed,
+            signature: signature?,
+        })
+    }
+}
+
+/// How far the signed `Date` may be from now, to allow for clock skew.
+const MAX_DATE_SKEW_HOURS: i64 = 12;
+
+/// Reject old signatures so that captured requests cannot be replayed.
+fn check_date(date: &str, now: chrono::DateTime<chrono::Utc>) -> Result<(), String> {
+    let date = chrono::DateTime::parse_from_rfc2822(date)
+        .map_err(|e| format!("invalid date header: {e}"))?;
+    let skew = now.signed_duration_since(date).num_hours().abs();
+    if MAX_DATE_SKEW_HOURS <= skew {
+        return Err(format!("date is {skew} hours from now"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_check_date() {
+    let now = chrono::Utc::now();
+    let date = |hours: i64| http_date(&(now - chrono::Duration::hours(hours)));
+    assert_eq!(check_date(&date(0), now), Ok(()));
+    assert_eq!(check_date(&date(11), now), Ok(()));
+    assert_eq!(check_date(&date(-11), now), Ok(()));
+    assert!(check_date(&date(13), now).is_err());
+    assert!(check_date(&date(-13), now).is_err());
+    assert!(check_date("yesterday", now).is_err());
+}
+
+/// Verify the signature of an incoming request.
+///
+/// `path` is the path including the query as received by the server.
+pub fn verify(
+    headers: &HeaderMap,
+    method: &str,
+    path: &str,
+    body: &[u8],
+    public_key_pem: &str,
+) -> Result<(), String> {
+    let header = SignatureHeader::parse(headers).ok_or("missing or invalid signature header")?;
+    // Without these, a signature for another endpoint or host could be replayed.
+    for required in ["(request-target)", "host"] {
+        if !header.headers.iter().any(|name| name == required) {
+            return Err(format!("{required} is not signed"));
+        }
+    }
+    let mut signed = Vec::new();
+    for name in &header.headers {
+        if name == "(request-target)" {
+            signed.push((name.as_str(), format!("{} {path}", method.to_lowercase())));
+            continue;
+        }
+        let value = headers
+            .get(name.as_str())
+            .ok_or(format!("missing signed header {name}"))?;
+        let value = value.to_str().map_err(|e| e.to_string())?;
+        signed.push((name.as_str(), value.to_string()));
+    }
+    match signed.iter().find(|(name, _)| *name == "date") {
+        Some((_, date)) => check_date(date, chrono::Utc::now())?,
+        None => return Err("date is not signed".to_string()),
+    }
+    if method.eq_ignore_ascii_case("post") {
+        let expected = digest(body);
+        match signed.iter().find(|(name, _)| *name == "digest") {
+            Some((_, value)) if *value == expected => (),
+            Some(_) => return Err("digest does not match body".to_string()),
+            None => return Err("digest is not signed".to_string()),
+        }
+    }
+    let public_key = RsaPublicKey::from_public_key_pem(public_key_pem.trim())
+        .map_err(|e| format!("invalid public key: {e}"))?;
+    let verifying_key = VerifyingKey::<Sha256>::new(public_key);
+    let signature = STANDARD
+        .decode(&header.signature)
+        .map_err(|e| e.to_string())?;
+    let signature = Signature::try_from(signature.as_slice()).map_err(|e| e.to_string())?;
+    verifying_key
+        .verify(signing_string(&signed).as_bytes(), &signature)
+        .map_err(|_| "signature does not match".to_string())
+}
+
+#[test]
+fn test_sign_and_verify() {
+    let keypair = Keypair::generate(1024).unwrap();
+    let key_id = "https://example.com/ap/actor#main-key";
+    let url = "https://example.com/ap/inbox";
+    let body = br#"{"type":"Follow"}"#;
+    let mut headers = sign(&keypair.private_key, key_id, "POST", url, Some(body)).unwrap();
+    let header = SignatureHeader::parse(&headers).unwrap();
+    assert_eq!(header.key_id, key_id);
+    assert_eq!(
+        header.headers,
+        vec!["(request-target)", "host", "date", "digest"]
+    );
+    let pem = &keypair.public_key_pem;
+    assert_eq!(verify(&headers, "POST", "/ap/inbox", body, pem), Ok(()));
+    assert!(verify(&headers, "POST", "/ap/outbox", body, pem).is_err());
+    assert!(verify(&headers, "POST", "/ap/inbox", b"{}", pem).is_err());
+
+    let other = Keypair::generate(1024).unwrap();
+    let other_pem = &other.public_key_pem;
+    assert!(verify(&headers, "POST", "/ap/inbox", body, other_pem).is_err());
+
+    headers.insert("host", HeaderValue::from_static("example.org"));
+    assert!(verify(&headers, "POST", "/ap/inbox", body, pem).is_err());
+
+    for (name, without) in [
+        ("(request-target)", "host date digest"),
+        ("host", "(request-target) date digest"),
+    ] {
+        let mut headers = sign(&keypair.private_key, key_id, "POST", url, Some(body)).unwrap();
+        let value = headers["Signature"].to_str().unwrap();
+        let value = value.replace("(request-target) host date digest", without);
+        headers.insert("Signature", HeaderValue::from_str(&value).unwrap());
+        let expected = Err(format!("{name} is not signed"));
+        assert_eq!(verify(&headers, "POST", "/ap/inbox", body, pem), expected);
+    }
+}
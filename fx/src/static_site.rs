@@ -0,0 +1,255 @@
+//! Export the public pages as a static site.
+//!
+//! The pages are rendered by sending requests to the same router that the
+//! server uses, so the output is identical to what visitors see. The result
+//! can be hosted on any static host as a read-only mirror.
+//!
+//! Static hosts take the Content-Type from the extension, so files are written
+//! to `files/{sha}/{filename}` as in the backup archive and links to
+//! `/files/{sha}` are rewritten to that path.
+use crate::ServeArgs;
+use crate::data::Post;
+use crate::files::FileInfo;
+use crate::serve::ServerContext;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use bytes::Bytes;
+use clap::Parser;
+use http_body_util::BodyExt;
+use regex::Captures;
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+use std::path::PathBuf;
+use tower::util::ServiceExt;
+
+#[derive(Clone, Debug, Parser)]
+pub struct ExportStaticArgs {
+    /// The directory to write the site to.
+    pub out_dir: String,
+    /// The same settings as for `serve`, so set `FX_PRODUCTION` and
+    /// `FX_DATABASE_PATH` to export an existing site.
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    /// Number of HTML pages, including the redirects from `/posts/{id}`.
+    pub pages: usize,
+    pub files: usize,
+    /// Feeds, `robots.txt` and the static assets.
+    pub other: usize,
+}
+
+impl std::fmt::Display for ExportReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Exported:")?;
+        writeln!(f, "  pages: {}", self.pages)?;
+        writeln!(f, "  files: {}", self.files)?;
+        write!(f, "  other: {}", self.other)
+    }
+}
+
+/// Make the links in a page work without a server.
+///
+/// Static hosts ignore the query, so `/?page=2` becomes `/page/2/`. The login
+/// link is removed since the mirror is read-only.
+fn rewrite_links(html: &str) -> String {
+    let re = Regex::new(r"/\?page=(\d+)").unwrap();
+    let html = re.replace_all(html, "/page/$1/");
+    let re = Regex::new(r#"<a [^>]*href=["']?/login["']?[^>]*>Login</a>"#).unwrap();
+    re.replace_all(&html, "").to_string()
+}
+
+/// Point links to `/files/{sha}` to the path where the file was written.
+fn rewrite_file_links(text: &str, paths: &HashMap<String, String>) -> String {
+    let re = Regex::new(r"/files/([0-9a-f]+)(/?)").unwrap();
+    re.replace_all(text, |captures: &Captures| match paths.get(&captures[1]) {
+        Some(path) if captures[2].is_empty() => format!("/{path}"),
+        _ => captures[0].to_string(),
+    })
+    .to_string()
+}
+
+#[test]
+fn test_rewrite_file_links() {
+    let paths = HashMap::from([("abc".to_string(), "files/abc/a.png".to_string())]);
+    let html =
+        "<img src='/files/abc'> [x](https://example.com/files/abc) /files/abc/a.png /files/def";
+    let expected = "<img src='/files/abc/a.png'> [x](https://example.com/files/abc/a.png) \
+        /files/abc/a.png /files/def";
+    assert_eq!(rewrite_file_links(html, &paths), expected);
+}
+
+/// A page that sends visitors of `/posts/{id}` to the URL with the slug.
+fn redirect_page(url: &str) -> String {
+    indoc::formatdoc! {"
+        <!DOCTYPE html>
+        <html>
+        <head>
+            <meta charset='utf-8'>
+            <link rel='canonical' href='{url}'>
+            <meta http-equiv='refresh' content='0; url={url}'>
+        </head>
+        <body><a href='{url}'>{url}</a></body>
+        </html>
+    "}
+}
+
+struct Exporter {
+    app: Router,
+    out_dir: PathBuf,
+    report: ExportReport,
+    /// The path of each file by sha.
+    files: HashMap<String, String>,
+}
+
+impl Exporter {
+    async fn get(&self, uri: &str) -> Result<(StatusCode, Bytes), String> {
+        let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = self.app.clone().oneshot(req).await.unwrap();
+        let status = response.status();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| format!("{uri}: {e}"))?
+            .to_bytes();
+        Ok((status, body))
+    }
+    fn write(&self, path: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.out_dir.join(path);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| format!("{}: {e}", dir.display()))?;
+        }
+        std::fs::write(&path, data).map_err(|e| format!("{}: {e}", path.display()))
+    }
+    /// Write the response for `uri` to `path` in the output directory.
+    async fn export(&mut self, uri: &str, path: &str) -> Result<Bytes, String> {
+        let (status, body) = self.get(uri).await?;
+        if status != StatusCode::OK {
+            return Err(format!("{uri}: {status}"));
+        }
+        if path.ends_with(".html") {
+            let html = rewrite_links(&String::from_utf8_lossy(&body));
+            self.write(path, rewrite_file_links(&html, &self.files).as_bytes())?;
+            self.report.pages += 1;
+        } else if path.ends_with(".xml") || path.ends_with(".json") {
+            let text = String::from_utf8_lossy(&body);
+            self.write(path, rewrite_file_links(&text, &self.files).as_bytes())?;
+            self.report.other += 1;
+        } else {
+            self.write(path, &body)?;
+            self.report.other += 1;
+        }
+        Ok(body)
+    }
+    /// Export the home page and the pages after it.
+    async fn export_home(&mut self) -> Result<(), String> {
+        let mut body = self.export("/", "index.html").await?;
+        let mut page = 2;
+        loop {
+            let next = Regex::new(&format!(r"/\?page={page}\b")).unwrap();
+            if !next.is_match(&String::from_utf8_lossy(&body)) {
+                return Ok(());
+            }
+            let uri = format!("/?page={page}");
+            body = self
+                .export(&uri, &format!("page/{page}/index.html"))
+                .await?;
+            page += 1;
+        }
+    }
+    async fn export_posts(&mut self, ctx: &ServerContext) -> Result<(), String> {
+        let posts = Post::list(&ctx.conn()).map_err(|e| e.to_string())?;
+        for post in posts {
+            let slug = crate::md::extract_slug(&post);
+            let url = crate::html::post_link(&post, &slug);
+            let path = format!("{}/index.html", url.trim_start_matches('/'));
+            self.export(&url, &path).await?;
+            let og = format!("posts/{}/og.png", post.id);
+            self.export(&format!("/{og}"), &og).await?;
+            if !slug.is_empty() {
+                let path = format!("posts/{}/index.html", post.id);
+                self.write(&path, redirect_page(&url).as_bytes())?;
+                self.report.pages += 1;
+            }
+        }
+        Ok(())
+    }
+    async fn export_files(&mut self, ctx: &ServerContext) -> Result<(), String> {
+        let files = FileInfo::list(&ctx.conn()).map_err(|e| e.to_string())?;
+        for file in files {
+            let url = format!("/files/{}", file.sha);
+            let (status, body) = self.get(&url).await?;
+            if status != StatusCode::OK {
+                return Err(format!("{url}: {status}"));
+            }
+            let path = crate::files::archive_path(&file.sha, &file.filename);
+            self.write(&path, &body)?;
+            // For links to `/files/{sha}` from elsewhere, which most static
+            // hosts redirect to the directory.
+            let index = format!("files/{}/index.html", file.sha);
+            self.write(&index, redirect_page(&format!("/{path}")).as_bytes())?;
+            self.files.insert(file.sha, path);
+            self.report.files += 1;
+        }
+        Ok(())
+    }
+}
+
+/// Write all public pages, feeds and files of the site to `out_dir`.
+pub async fn export(ctx: &ServerContext, out_dir: &Path) -> Result<ExportReport, String> {
+    let mut exporter = Exporter {
+        app: crate::serve::app(ctx.clone()),
+        out_dir: out_dir.to_path_buf(),
+        report: ExportReport::default(),
+        files: HashMap::new(),
+    };
+    // First, so that the pages can link to the paths of the files.
+    exporter.export_files(ctx).await?;
+    exporter.export_home().await?;
+    exporter.export_posts(ctx).await?;
+    for path in [
+        "feed.xml",
+        "atom.xml",
+        "feed.json",
+        "sitemap.xml",
+        "robots.txt",
+        "static/style.css",
+        "static/script.js",
+        "static/katex.js",
+        "static/nodefer.js",
+    ] {
+        exporter.export(&format!("/{path}"), path).await?;
+    }
+    // Most static hosts show `404.html` for missing pages.
+    let (_, body) = exporter.get("/404.html").await?;
+    let html = rewrite_links(&String::from_utf8_lossy(&body));
+    let html = rewrite_file_links(&html, &exporter.files);
+    exporter.write("404.html", html.as_bytes())?;
+    exporter.report.pages += 1;
+    Ok(exporter.report)
+}
+
+/// Export from the command line.
+pub async fn run(args: &ExportStaticArgs) {
+    let ctx = crate::serve::init_context(&args.serve).await;
+    match export(&ctx, Path::new(&args.out_dir)).await {
+        Ok(report) => println!("{report}"),
+        Err(e) => {
+            eprintln!("Failed to export: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[test]
+fn test_rewrite_links() {
+    let html = r#"<a class="unstyled-link" href="/?page=2">▶ next</a><a class="unstyled-link menu-space" href="/login">Login</a>"#;
+    let expected = r#"<a class="unstyled-link" href="/page/2/">▶ next</a>"#;
+    assert_eq!(rewrite_links(html), expected);
+}
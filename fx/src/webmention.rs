@@ -0,0 +1,302 @@
+//! Sending and receiving Webmentions, see <https://www.w3.org/TR/webmention/>.
+//!
+//! Received mentions are stored as replies so that they go through the same
+//! moderation at `/replies`.
+use crate::data::Post;
+use crate::replies::BlockedDomain;
+use crate::replies::Reply;
+use crate::replies::Status;
+use crate::serve::ServerContext;
+use crate::serve::response;
+use axum::Form;
+use axum::Router;
+use axum::body::Body;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::Response;
+use axum::http::StatusCode;
+use axum::routing::post;
+use chrono::Utc;
+use reqwest::Url;
+use serde::Deserialize;
+
+/// Return the absolute links in the post that point to other sites.
+fn outbound_links(ctx: &ServerContext, content: &str) -> Vec<String> {
+    let html = crate::md::content_to_html(content);
+    let href = regex::Regex::new(r#"href="(https?://[^"]+)""#).unwrap();
+    let base = ctx.base_url();
+    let mut links = href
+        .captures_iter(&html)
+        .map(|captures| captures[1].replace("&amp;", "&"))
+        .filter(|link| base.is_empty() || !link.starts_with(&base))
+        .collect::<Vec<String>>();
+    links.sort();
+    links.dedup();
+    links
+}
+
+fn is_webmention_rel(rel: &str) -> bool {
+    rel.split_whitespace()
+        .any(|rel| rel.eq_ignore_ascii_case("webmention"))
+}
+
+/// Return the endpoint from a `Link` header such as
+/// `<https://example.com/webmention>; rel="webmention"`.
+fn endpoint_from_link_header(header: &str) -> Option<String> {
+    let link = regex::Regex::new(r#"<([^>]*)>\s*;\s*rel="?([^";]*)"?"#).unwrap();
+    link.captures_iter(header)
+        .find(|captures| is_webmention_rel(&captures[2]))
+        .map(|captures| captures[1].to_string())
+}
+
+#[test]
+fn test_endpoint_from_link_header() {
+    let header = r#"<https://a.example/x>; rel="other", </webmention>; rel="webmention""#;
+    assert_eq!(
+        endpoint_from_link_header(header),
+        Some("/webmention".to_string())
+    );
+    let header = "<https://a.example/wm>; rel=webmention";
+    assert_eq!(
+        endpoint_from_link_header(header),
+        Some("https://a.example/wm".to_string())
+    );
+    assert_eq!(endpoint_from_link_header("<https://a.example/>"), None);
+}
+
+/// Return the endpoint from the first `<link>` or `<a>` element with
+/// `rel="webmention"`.
+fn endpoint_from_html(html: &str) -> Option<String> {
+    let element = regex::Regex::new(r"(?i)<(?:link|a)\s[^>]*>").unwrap();
+    let rel = regex::Regex::new(r#"(?i)\srel\s*=\s*["']([^"']*)["']"#).unwrap();
+    let href = regex::Regex::new(r#"(?i)\shref\s*=\s*["']([^"']*)["']"#).unwrap();
+    element.find_iter(html).find_map(|element| {
+        let element = element.as_str();
+        let rel = rel.captures(element)?;
+        if !is_webmention_rel(&rel[1]) {
+            return None;
+        }
+        Some(href.captures(element)?[1].to_string())
+    })
+}
+
+#[test]
+fn test_endpoint_from_html() {
+    let html = r#"
+        <link rel="stylesheet" href="/style.css">
+        <link href="/webmention?x=1" rel="webmention">
+        <a rel="webmention" href="/other">
+    "#;
+    assert_eq!(
+        endpoint_from_html(html),
+        Some("/webmention?x=1".to_string())
+    );
+    // An empty href means that the page itself is the endpoint.
+    let html = "<a href='' rel='nofollow webmention'>";
+    assert_eq!(endpoint_from_html(html), Some("".to_string()));
+    assert_eq!(endpoint_from_html("<p>rel='webmention'</p>"), None);
+}
+
+/// Discover the Webmention endpoint of the target, if it has one.
+async fn discover_endpoint(client: &reqwest::Client, target: &str) -> Option<Url> {
+    let response = client.get(target).send().await.ok()?;
+    // Redirects are followed so relative links are relative to the final URL.
+    let url = response.url().clone();
+    let from_header = response
+        .headers()
+        .get_all("Link")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(endpoint_from_link_header);
+    let endpoint = match from_header {
+        Some(endpoint) => endpoint,
+        None => {
+            let is_html = response
+                .headers()
+                .get("Content-Type")
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.contains("html"));
+            if !is_html {
+                return None;
+            }
+            let html = crate::net::text(response, crate::net::MAX_BODY)
+                .await
+                .ok()?;
+            endpoint_from_html(&html)?
+        }
+    };
+    url.join(&endpoint).ok()
+}
+
+async fn send(
+    ctx: &ServerContext,
+    client: &reqwest::Client,
+    source: &str,
+    target: &str,
+) -> Result<(), String> {
+    let endpoint = match discover_endpoint(client, target).await {
+        Some(endpoint) => endpoint,
+        None => return Ok(()),
+    };
+    // The endpoint is chosen by the target and could point to the local network.
+    let endpoint = crate::net::public_url(ctx, endpoint.as_str())?;
+    let response = client
+        .post(endpoint.clone())
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(serde_urlencoded::to_string([("source", source), ("target", target)]).unwrap())
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        tracing::info!("Sent webmention for {target} to {endpoint}");
+        Ok(())
+    } else {
+        Err(format!("{endpoint} returned {}", response.status()))
+    }
+}
+
+/// Send Webmentions for all outbound links in the post.
+///
+/// After an edit, `previous` is the content before the edit. Links that the
+/// edit removed are notified too, so that their sites can remove the mention.
+pub async fn send_mentions(ctx: &ServerContext, id: i64, previous: Option<&str>) -> Option<()> {
+    let post = Post::get(&ctx.conn(), id).ok()?;
+    let slug = crate::md::extract_slug(&post);
+    let source = format!("{}{}", ctx.base_url(), crate::html::post_link(&post, &slug));
+    let mut targets = outbound_links(ctx, &post.content);
+    if let Some(previous) = previous {
+        targets.extend(outbound_links(ctx, previous));
+        targets.sort();
+        targets.dedup();
+    }
+    if targets.is_empty() {
+        return None;
+    }
+    // Like the backup trigger, the request should not wait for other servers.
+    let ctx = ctx.clone();
+    tokio::spawn(async move {
+        let client = crate::net::client(&ctx);
+        for target in targets {
+            if let Err(e) = send(&ctx, &client, &source, &target).await {
+                tracing::warn!("Failed to send webmention for {target}: {e}");
+            }
+        }
+    });
+    Some(())
+}
+
+fn title(html: &str) -> Option<String> {
+    let title = regex::Regex::new(r"(?is)<title[^>]*>([^<]*)</title>").unwrap();
+    let title = title.captures(html)?[1].trim().to_string();
+    if title.is_empty() {
+        None
+    } else {
+        Some(title.chars().take(200).collect())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct WebmentionForm {
+    source: String,
+    target: String,
+}
+
+fn bad_request(ctx: &ServerContext, msg: &str) -> Response<Body> {
+    response(
+        StatusCode::BAD_REQUEST,
+        HeaderMap::new(),
+        msg.to_string(),
+        ctx,
+    )
+}
+
+async fn post_webmention(
+    State(ctx): State<ServerContext>,
+    Form(form): Form<WebmentionForm>,
+) -> Response<Body> {
+    let source = &form.source;
+    let is_http = |url: &str| {
+        Url::parse(url).is_ok_and(|url| url.scheme() == "http" || url.scheme() == "https")
+    };
+    if !is_http(source) || !is_http(&form.target) {
+        return bad_request(&ctx, "source and target should be http(s) URLs");
+    }
+    if source == &form.target {
+        return bad_request(&ctx, "source and target should be different");
+    }
+    let post = crate::replies::post_id_from_url(&ctx, &form.target)
+        .and_then(|id| Post::get(&ctx.conn(), id).ok())
+        .filter(|post| post.content != "<DELETED>");
+    let post = match post {
+        Some(post) => post,
+        None => return bad_request(&ctx, "target is not a post on this site"),
+    };
+    if BlockedDomain::is_blocked(&ctx.conn(), source) {
+        return response(StatusCode::ACCEPTED, HeaderMap::new(), "", &ctx);
+    }
+    let url = match crate::net::public_url(&ctx, source) {
+        Ok(url) => url,
+        Err(e) => return bad_request(&ctx, &e),
+    };
+    // Verified in the background as recommended by the specification, since
+    // the source can be slow and the sender does not need to wait for it.
+    let source = source.clone();
+    let background = ctx.clone();
+    tokio::spawn(async move {
+        let ctx = background;
+        if let Err(e) = verify(&ctx, url, &source, &form.target, post.id).await {
+            tracing::warn!("Failed to verify webmention from {source}: {e}");
+        }
+    });
+    response(StatusCode::ACCEPTED, HeaderMap::new(), "", &ctx)
+}
+
+/// Store the mention if the source links to the target and delete it otherwise.
+async fn verify(
+    ctx: &ServerContext,
+    url: Url,
+    source: &str,
+    target: &str,
+    post_id: i64,
+) -> Result<(), String> {
+    let fetched = crate::net::client(ctx)
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("could not fetch source: {e}"))?;
+    let html = match fetched.status() {
+        status if status.is_success() => crate::net::text(fetched, crate::net::MAX_BODY)
+            .await
+            .map_err(|e| format!("could not read source: {e}"))?,
+        StatusCode::GONE => "".to_string(),
+        status => return Err(format!("source returned {status}")),
+    };
+    // Also used to remove mentions when the source no longer links to us.
+    if !html.contains(target) {
+        Reply::delete_webmention(&ctx.conn(), post_id, source).map_err(|e| e.to_string())?;
+        return Err("source does not link to target".to_string());
+    }
+    let url = crate::html::escape_html(source);
+    // The title cannot contain tags since it is matched up to the first `<`.
+    let title = title(&html).unwrap_or(url.clone());
+    let content = format!("<p>Mentioned in <a href='{url}' rel='nofollow ugc'>{title}</a></p>");
+    let reply = Reply {
+        id: 0,
+        object_id: Reply::webmention_id(post_id, source),
+        post_id,
+        author_name: crate::replies::domain_of(source).unwrap_or_default(),
+        author_url: source.to_string(),
+        content,
+        published: Utc::now(),
+        status: Status::Pending,
+    };
+    reply
+        .upsert(&ctx.conn())
+        .map_err(|e| format!("failed to store webmention: {e}"))?;
+    tracing::info!("New webmention for post {post_id} from {source}");
+    Ok(())
+}
+
+pub fn routes(router: &Router<ServerContext>) -> Router<ServerContext> {
+    router.clone().route("/webmention", post(post_webmention))
+}
@@ -0,0 +1,224 @@
+//! Read WordPress exports.
+//!
+//! The export (WXR) is an RSS feed with the posts, pages and attachments as
+//! items and the WordPress fields in the `wp` namespace. It does not contain
+//! the uploaded files, so these are read from a copy of `wp-content/uploads`
+//! if one is given.
+use crate::files::File;
+use crate::html_md::escape_markdown;
+use crate::html_md::html_to_markdown;
+use crate::import::Import;
+use crate::import::ImportedFile;
+use crate::import::ImportedPost;
+use crate::import::Source;
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono::NaiveDateTime;
+use chrono::Utc;
+use roxmltree::Document;
+use roxmltree::Node;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+const CONTENT_NS: &str = "http://purl.org/rss/1.0/modules/content/";
+/// The namespace differs per WXR version (`1.0`, `1.1`, `1.2`).
+const WP_NS: &str = "http://wordpress.org/export/";
+const UPLOADS: &str = "wp-content/uploads/";
+
+/// Return the text of the child element, where `name` is prefixed by `wp:`
+/// or `content:` for elements in those namespaces.
+fn text(item: Node, name: &str) -> String {
+    let (prefix, local) = match name.split_once(':') {
+        Some((prefix, local)) => (Some(prefix), local),
+        None => (None, name),
+    };
+    item.children()
+        .find(|node| {
+            let tag = node.tag_name();
+            tag.name() == local
+                && match (prefix, tag.namespace()) {
+                    (None, None) => true,
+                    (Some("wp"), Some(ns)) => ns.starts_with(WP_NS),
+                    (Some("content"), Some(ns)) => ns == CONTENT_NS,
+                    _ => false,
+                }
+        })
+        .and_then(|node| node.text())
+        .unwrap_or("")
+        .trim()
+        .to_string()
+}
+
+/// Parse the `wp:post_date_gmt` format.
+///
+/// Drafts have `0000-00-00 00:00:00`, which is not a date.
+fn parse_date(text: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+/// Return the path of an upload relative to `wp-content/uploads`.
+fn upload_path(url: &str) -> Option<&str> {
+    let start = url.find(UPLOADS)? + UPLOADS.len();
+    let path = url[start..].split(['?', '#']).next().unwrap_or("");
+    if path.is_empty() || path.split('/').any(|part| part == "..") {
+        return None;
+    }
+    Some(path)
+}
+
+/// Read one post or page or return why it was skipped.
+fn read_post(item: Node, import: &mut Import) -> Result<(), String> {
+    let post_type = text(item, "wp:post_type");
+    let title = text(item, "title");
+    let name = format!("{post_type} {title:?}");
+    let status = text(item, "wp:status");
+    if status != "publish" {
+        return Err(format!("{name}: {status}"));
+    }
+    if !text(item, "wp:post_password").is_empty() {
+        return Err(format!("{name}: password protected"));
+    }
+    let created = parse_date(&text(item, "wp:post_date_gmt"))
+        .or_else(|| {
+            let pub_date = text(item, "pubDate");
+            let dt = DateTime::parse_from_rfc2822(&pub_date).ok()?;
+            Some(dt.with_timezone(&Utc))
+        })
+        .ok_or_else(|| format!("{name}: invalid date"))?;
+    let updated = parse_date(&text(item, "wp:post_modified_gmt"))
+        .filter(|updated| created <= *updated)
+        .unwrap_or(created);
+
+    let mut parts = Vec::new();
+    if !title.is_empty() {
+        parts.push(format!("# {}", escape_markdown(&title)));
+    }
+    let content = html_to_markdown(&text(item, "content:encoded"));
+    if !content.is_empty() {
+        parts.push(content);
+    }
+    if parts.is_empty() {
+        return Err(format!("{name}: empty"));
+    }
+    let comments = item
+        .children()
+        .filter(|node| node.tag_name().name() == "comment")
+        .count();
+    if 0 < comments {
+        import.skipped.push(format!("{name}: {comments} comments"));
+    }
+    let link = text(item, "link");
+    // Slugs with non-ASCII characters are percent-encoded, so those are
+    // derived from the title instead.
+    let slug = Some(text(item, "wp:post_name"))
+        .filter(|slug| !slug.contains('%'))
+        .and_then(|slug| crate::md::normalize_slug(&slug));
+    import.posts.push(ImportedPost {
+        created,
+        updated,
+        content: parts.join("\n\n"),
+        old_url: Some(link).filter(|link| !link.is_empty()),
+        slug,
+    });
+    Ok(())
+}
+
+/// Read the uploads that are referred to by the given URLs.
+fn read_uploads(import: &mut Import, urls: Vec<String>, dir: Option<&Path>) {
+    // The same upload can be referred to by multiple URLs, for example with
+    // and without a query.
+    let mut uploads: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for url in urls {
+        if let Some(path) = upload_path(&url) {
+            let old_urls = uploads.entry(path.to_string()).or_default();
+            if !old_urls.contains(&url) {
+                old_urls.push(url);
+            }
+        }
+    }
+    for (path, old_urls) in uploads {
+        let Some(dir) = dir else {
+            let reason = format!("upload {path}: no uploads directory given");
+            import.skipped.push(reason);
+            continue;
+        };
+        match std::fs::read(dir.join(&path)) {
+            Ok(data) => {
+                let filename = path.rsplit('/').next().unwrap_or(&path);
+                let mime_type = crate::restore::guess_mime_type(filename);
+                let file = File::new(mime_type, filename, Bytes::from(data));
+                import.files.push(ImportedFile { file, old_urls });
+            }
+            Err(e) => import.skipped.push(format!("upload {path}: {e}")),
+        }
+    }
+}
+
+fn read_export(xml: &str, uploads: Option<&Path>) -> Result<Import, String> {
+    let doc = Document::parse(xml).map_err(|e| format!("invalid XML: {e}"))?;
+    let channel = doc
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name("channel"))
+        .ok_or("the export contains no channel")?;
+    let mut import = Import::default();
+    let mut urls = Vec::new();
+    for item in channel.children().filter(|node| node.has_tag_name("item")) {
+        match text(item, "wp:post_type").as_str() {
+            "attachment" => urls.push(text(item, "wp:attachment_url")),
+            "post" | "page" => {
+                if let Err(reason) = read_post(item, &mut import) {
+                    import.skipped.push(reason);
+                }
+            }
+            other => {
+                let title = text(item, "title");
+                import
+                    .skipped
+                    .push(format!("{other} {title:?}: unsupported type"));
+            }
+        }
+    }
+    // Posts often refer to resized versions of the attachments, which are
+    // uploads of their own.
+    for post in &import.posts {
+        crate::import::map_links(&post.content, |target| {
+            urls.push(target.to_string());
+            None
+        });
+    }
+    read_uploads(&mut import, urls, uploads);
+    // Oldest first so that post ids increase with the dates.
+    import.posts.sort_by_key(|post| post.created);
+    Ok(import)
+}
+
+/// A WordPress export with optionally the uploads directory.
+pub struct WordpressExport {
+    xml: Bytes,
+    uploads: Option<PathBuf>,
+}
+
+impl WordpressExport {
+    pub fn new(xml: Bytes, uploads: Option<PathBuf>) -> Self {
+        Self { xml, uploads }
+    }
+}
+
+impl Source for WordpressExport {
+    fn read(&self) -> Result<Import, String> {
+        let xml = std::str::from_utf8(&self.xml).map_err(|e| format!("invalid UTF-8: {e}"))?;
+        read_export(xml, self.uploads.as_deref())
+    }
+}
+
+#[test]
+fn test_upload_path() {
+    let url = "https://example.com/wp-content/uploads/2020/01/cat-300x200.jpg?w=300";
+    assert_eq!(upload_path(url), Some("2020/01/cat-300x200.jpg"));
+    assert_eq!(upload_path("https://example.com/about/"), None);
+    assert_eq!(upload_path("/wp-content/uploads/../../etc/passwd"), None);
+}
@@ -0,0 +1,592 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use axum::routing::get;
+use chrono::Utc;
+use common::*;
+use fx::ap::Follower;
+use fx::data::Post;
+use fx::delivery::Delivery;
+use fx::serve::ServerContext;
+use fx::serve::app;
+use fx::signature::Keypair;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use serde_json::json;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tower::util::ServiceExt;
+
+/// A minimal fediverse server with one actor that records its inbox.
+#[derive(Clone)]
+struct Stub {
+    base: String,
+    keypair: Keypair,
+    inbox: Arc<Mutex<Vec<(HeaderMap, String)>>>,
+}
+
+impl Stub {
+    fn actor(&self) -> String {
+        format!("{}/users/alice", self.base)
+    }
+    fn key_id(&self) -> String {
+        format!("{}#main-key", self.actor())
+    }
+}
+
+async fn stub_actor(State(stub): State<Stub>) -> String {
+    json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": stub.actor(),
+        "type": "Person",
+        "preferredUsername": "alice",
+        "inbox": format!("{}/inbox", stub.actor()),
+        "endpoints": {
+            "sharedInbox": format!("{}/inbox", stub.base),
+        },
+        "publicKey": {
+            "id": stub.key_id(),
+            "owner": stub.actor(),
+            "publicKeyPem": stub.keypair.public_key_pem,
+        },
+    })
+    .to_string()
+}
+
+/// An actor that claims to be an actor on another server.
+async fn stub_impostor(State(stub): State<Stub>) -> String {
+    let mut actor: Value = serde_json::from_str(&stub_actor(State(stub)).await).unwrap();
+    actor["id"] = json!("https://victim.example.com/users/bob");
+    actor.to_string()
+}
+
+async fn stub_inbox(State(stub): State<Stub>, headers: HeaderMap, body: String) -> StatusCode {
+    stub.inbox.lock().unwrap().push((headers, body));
+    StatusCode::ACCEPTED
+}
+
+async fn start_stub() -> Stub {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stub = Stub {
+        base: format!("http://{addr}"),
+        keypair: Keypair::generate(1024).unwrap(),
+        inbox: Arc::new(Mutex::new(Vec::new())),
+    };
+    let router = Router::new()
+        .route("/users/alice", get(stub_actor))
+        .route("/users/mallory", get(stub_impostor))
+        .route("/users/alice/inbox", axum::routing::post(stub_inbox))
+        .route("/inbox", axum::routing::post(stub_inbox))
+        .route("/gone", axum::routing::post(async || StatusCode::GONE))
+        .with_state(stub.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    stub
+}
+
+async fn json_body(ctx: &ServerContext, uri: &str) -> (StatusCode, Value) {
+    let req = Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let content_type = response.headers().get("Content-Type").cloned();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    if status == StatusCode::OK && uri.starts_with("/ap/") {
+        assert_eq!(content_type.unwrap(), fx::ap::ACTIVITY_JSON);
+    }
+    let body = serde_json::from_slice(&body).unwrap_or_default();
+    (status, body)
+}
+
+async fn post_inbox(ctx: &ServerContext, stub: &Stub, activity: &Value, sign: bool) -> StatusCode {
+    let key_id = if sign { Some(stub.key_id()) } else { None };
+    post_inbox_with_key(ctx, stub, activity, key_id).await
+}
+
+async fn post_inbox_with_key(
+    ctx: &ServerContext,
+    stub: &Stub,
+    activity: &Value,
+    key_id: Option<String>,
+) -> StatusCode {
+    let body = activity.to_string();
+    let mut req = Request::builder()
+        .method("POST")
+        .uri("/ap/inbox")
+        .header("Content-Type", fx::ap::ACTIVITY_JSON);
+    if let Some(key_id) = key_id {
+        let url = "https://fx.example.com/ap/inbox";
+        let private_key = &stub.keypair.private_key;
+        let bytes = Some(body.as_bytes());
+        let headers = fx::signature::sign(private_key, &key_id, "POST", url, bytes).unwrap();
+        for (name, value) in headers.iter() {
+            req = req.header(name, value);
+        }
+    }
+    let req = req.body(Body::from(body)).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    response.status()
+}
+
+/// Wait until the stub has received `n` requests in its inboxes.
+async fn received(stub: &Stub, n: usize) -> Vec<(HeaderMap, String)> {
+    for _ in 0..50 {
+        let inbox = stub.inbox.lock().unwrap().clone();
+        if n <= inbox.len() {
+            return inbox;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("stub did not receive {n} requests");
+}
+
+fn follower(inbox: &str, shared_inbox: Option<&str>) -> Follower {
+    Follower {
+        actor: format!("{inbox}/actor"),
+        inbox: inbox.to_string(),
+        shared_inbox: shared_inbox.map(|s| s.to_string()),
+        created: Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn test_actor() {
+    let ctx = server_context().await;
+    let (status, actor) = json_body(&ctx, "/ap/actor").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(actor["type"], "Person");
+    assert_eq!(actor["preferredUsername"], "test-admin");
+    assert_eq!(actor["inbox"], "/ap/inbox");
+    assert_eq!(actor["publicKey"]["id"], "/ap/actor#main-key");
+    let pem = actor["publicKey"]["publicKeyPem"].as_str().unwrap();
+    assert!(pem.starts_with("-----BEGIN PUBLIC KEY-----"));
+
+    // The key is stored and thus stable across requests.
+    let (_status, again) = json_body(&ctx, "/ap/actor").await;
+    assert_eq!(again["publicKey"]["publicKeyPem"], pem);
+}
+
+#[tokio::test]
+async fn test_outbox() {
+    let ctx = server_context().await;
+    let (status, outbox) = json_body(&ctx, "/ap/outbox").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(outbox["type"], "OrderedCollection");
+    assert_eq!(outbox["totalItems"], 2);
+    let item = &outbox["orderedItems"][1];
+    assert_eq!(item["type"], "Create");
+    assert_eq!(item["object"]["type"], "Note");
+    assert_eq!(item["object"]["id"], "/ap/notes/2");
+    assert_eq!(item["object"]["url"], "/posts/2/code");
+    assert!(
+        item["object"]["content"]
+            .as_str()
+            .unwrap()
+            .contains("<h1>Code</h1>")
+    );
+
+    let (status, note) = json_body(&ctx, "/ap/notes/2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(note["id"], "/ap/notes/2");
+    let (status, _note) = json_body(&ctx, "/ap/notes/999").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_follow() {
+    let ctx = server_context().await;
+    let stub = start_stub().await;
+    let follow = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "id": format!("{}#follows/1", stub.actor()),
+        "type": "Follow",
+        "actor": stub.actor(),
+        "object": "/ap/actor",
+    });
+
+    let status = post_inbox(&ctx, &stub, &follow, false).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(Follower::list(&ctx.conn()).unwrap().is_empty());
+
+    let status = post_inbox(&ctx, &stub, &follow, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let followers = Follower::list(&ctx.conn()).unwrap();
+    assert_eq!(followers.len(), 1);
+    assert_eq!(followers[0].actor, stub.actor());
+    assert_eq!(followers[0].inbox, format!("{}/inbox", stub.actor()));
+
+    let (_status, followers) = json_body(&ctx, "/ap/followers").await;
+    assert_eq!(followers["totalItems"], 1);
+
+    // The `Accept` is delivered in the background.
+    let (headers, body) = received(&stub, 1).await.pop().expect("no Accept received");
+    let accept: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(accept["type"], "Accept");
+    assert_eq!(accept["actor"], "/ap/actor");
+    assert_eq!(accept["object"]["id"], follow["id"]);
+    let (_status, actor) = json_body(&ctx, "/ap/actor").await;
+    let pem = actor["publicKey"]["publicKeyPem"].as_str().unwrap();
+    let path = "/users/alice/inbox";
+    let verified = fx::signature::verify(&headers, "POST", path, body.as_bytes(), pem);
+    assert_eq!(verified, Ok(()));
+
+    let undo = json!({
+        "id": format!("{}#follows/1/undo", stub.actor()),
+        "type": "Undo",
+        "actor": stub.actor(),
+        "object": follow,
+    });
+    let status = post_inbox(&ctx, &stub, &undo, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert!(Follower::list(&ctx.conn()).unwrap().is_empty());
+
+    // Another actor cannot send activities on behalf of the stub actor.
+    let spoofed = json!({
+        "id": "https://evil.example.com/follows/1",
+        "type": "Follow",
+        "actor": "https://evil.example.com/users/mallory",
+        "object": "/ap/actor",
+    });
+    let status = post_inbox(&ctx, &stub, &spoofed, true).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_private_addresses() {
+    let mut ctx = server_context().await;
+    ctx.args.allow_private_addresses = false;
+    let stub = start_stub().await;
+    let follow = json!({
+        "id": format!("{}#follows/1", stub.actor()),
+        "type": "Follow",
+        "actor": stub.actor(),
+        "object": "/ap/actor",
+    });
+    // The key id points to 127.0.0.1, which is not fetched.
+    let status = post_inbox(&ctx, &stub, &follow, true).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(Follower::list(&ctx.conn()).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_impostor_actor() {
+    let ctx = server_context().await;
+    let stub = start_stub().await;
+    let victim = "https://victim.example.com/users/bob";
+    let follower = Follower {
+        actor: victim.to_string(),
+        inbox: format!("{victim}/inbox"),
+        shared_inbox: None,
+        created: Utc::now(),
+    };
+    follower.insert(&ctx.conn()).unwrap();
+    let undo = json!({
+        "id": format!("{victim}#follows/1/undo"),
+        "type": "Undo",
+        "actor": victim,
+        "object": {"type": "Follow", "actor": victim, "object": "/ap/actor"},
+    });
+    // The key is on another host than the actor of the activity.
+    let key_id = Some(format!("{}/users/mallory#main-key", stub.base));
+    let status = post_inbox_with_key(&ctx, &stub, &undo, key_id.clone()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The document at the key claims to be the victim.
+    let mut undo = undo;
+    undo["actor"] = json!(format!("{}/users/mallory", stub.base));
+    let status = post_inbox_with_key(&ctx, &stub, &undo, key_id).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(Follower::list(&ctx.conn()).unwrap().len(), 1);
+}
+
+async fn post_form(ctx: &ServerContext, auth: &str, uri: &str, form: &str) -> StatusCode {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Cookie", format!("auth={auth}"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(form.to_string()))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    response.status()
+}
+
+#[tokio::test]
+async fn test_deliver_to_followers() {
+    let (ctx, auth) = request_cookie().await;
+    let stub = start_stub().await;
+    let inbox = format!("{}/inbox", stub.actor());
+    let shared_inbox = format!("{}/inbox", stub.base);
+    let alice = follower(&inbox, Some(&shared_inbox));
+    alice.insert(&ctx.conn()).unwrap();
+    // Another follower on the same server only causes one delivery.
+    let mut bob = follower(&inbox, Some(&shared_inbox));
+    bob.actor = format!("{}/users/bob", stub.base);
+    bob.insert(&ctx.conn()).unwrap();
+
+    let form = "content=Hello+fediverse&publish=Publish";
+    let status = post_form(&ctx, &auth, "/posts/add", form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let inbox = received(&stub, 1).await;
+    let (headers, body) = inbox.last().unwrap();
+    let create: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(create["type"], "Create");
+    assert_eq!(create["object"]["id"], "/ap/notes/3");
+    assert!(
+        create["object"]["content"]
+            .as_str()
+            .unwrap()
+            .contains("Hello fediverse")
+    );
+    assert!(headers.get("Signature").is_some());
+
+    let version = Post::get(&ctx.conn(), 3).unwrap().version();
+    let form = format!("content=Hello+again&version={version}&publish=Publish");
+    let status = post_form(&ctx, &auth, "/posts/edit/3", &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let inbox = received(&stub, 2).await;
+    let update: Value = serde_json::from_str(&inbox[1].1).unwrap();
+    assert_eq!(update["type"], "Update");
+    assert!(
+        update["object"]["content"]
+            .as_str()
+            .unwrap()
+            .contains("Hello again")
+    );
+    assert_ne!(update["id"], create["id"]);
+
+    let status = post_form(&ctx, &auth, "/posts/delete/3", "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let inbox = received(&stub, 3).await;
+    let delete: Value = serde_json::from_str(&inbox[2].1).unwrap();
+    assert_eq!(delete["type"], "Delete");
+    assert_eq!(delete["object"]["id"], "/ap/notes/3");
+
+    assert_eq!(inbox.len(), 3);
+    // The delivery is removed after the inbox responded, which can be a moment
+    // after the stub recorded the request.
+    for _ in 0..100 {
+        if Delivery::list(&ctx.conn()).unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert!(Delivery::list(&ctx.conn()).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_delivery_retry() {
+    let ctx = server_context().await;
+    // Nothing listens on port 1 so the delivery fails.
+    let dead = "http://127.0.0.1:1/inbox";
+    follower(dead, None).insert(&ctx.conn()).unwrap();
+    let id = Delivery::insert(&ctx.conn(), dead, "{}").unwrap();
+
+    fx::delivery::process(&ctx).await;
+    let deliveries = Delivery::list(&ctx.conn()).unwrap();
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].id, id);
+    assert_eq!(deliveries[0].attempts, 1);
+    assert!(Utc::now() < deliveries[0].next_attempt);
+    assert!(deliveries[0].last_error.is_some());
+
+    // Not due yet, so nothing happens.
+    fx::delivery::process(&ctx).await;
+    assert_eq!(Delivery::list(&ctx.conn()).unwrap()[0].attempts, 1);
+
+    // The last attempt prunes the follower.
+    let attempts = fx::delivery::MAX_ATTEMPTS - 1;
+    let stmt = "UPDATE deliveries SET attempts = ?, next_attempt = '2000-01-01 00:00:00'";
+    ctx.conn().execute(stmt, [attempts]).unwrap();
+    fx::delivery::process(&ctx).await;
+    assert!(Delivery::list(&ctx.conn()).unwrap().is_empty());
+    assert!(Follower::list(&ctx.conn()).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_delivery_gone() {
+    let ctx = server_context().await;
+    let stub = start_stub().await;
+    let gone = format!("{}/gone", stub.base);
+    follower(&gone, None).insert(&ctx.conn()).unwrap();
+    Delivery::insert(&ctx.conn(), &gone, "{}").unwrap();
+    fx::delivery::process(&ctx).await;
+    assert!(Delivery::list(&ctx.conn()).unwrap().is_empty());
+    assert!(Follower::list(&ctx.conn()).unwrap().is_empty());
+}
+
+async fn html_body(ctx: &ServerContext, auth: &str, uri: &str) -> String {
+    let req = Request::builder()
+        .method("GET")
+        .uri(uri)
+        .header("Cookie", format!("auth={auth}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap();
+    String::from_utf8(body.to_bytes().to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_replies() {
+    let (ctx, auth) = request_cookie().await;
+    let mut post = fx::data::Post::get(&ctx.conn(), 1).unwrap();
+    post.slug = Some("lorem".to_string());
+    post.update(&ctx.conn()).unwrap();
+    let stub = start_stub().await;
+    let note = json!({
+        "id": format!("{}/notes/1", stub.actor()),
+        "type": "Note",
+        "attributedTo": stub.actor(),
+        "inReplyTo": "/ap/notes/1",
+        "published": "2025-01-01T12:00:00Z",
+        "content": "<p>Great post!<script>alert(1)</script></p>",
+    });
+    let create = json!({
+        "id": format!("{}/notes/1/activity", stub.actor()),
+        "type": "Create",
+        "actor": stub.actor(),
+        "object": note,
+    });
+    let status = post_inbox(&ctx, &stub, &create, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+
+    // Replies are only shown after approval.
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(!body.contains("Great post!"));
+    let body = html_body(&ctx, &auth, "/replies").await;
+    assert!(body.contains("<p>Great post!alert(1)</p>"));
+    assert!(!body.contains("<script>"));
+
+    let status = post_form(&ctx, "", "/replies/approve/1", "").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let status = post_form(&ctx, &auth, "/replies/approve/1", "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(body.contains("Great post!"));
+    assert!(body.contains("alice"));
+
+    // Edited replies have to be approved again.
+    let mut update = create.clone();
+    update["type"] = json!("Update");
+    update["object"]["content"] = json!("<p>Great post! Edited</p>");
+    let status = post_inbox(&ctx, &stub, &update, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(!body.contains("Great post!"));
+    let status = post_form(&ctx, &auth, "/replies/approve/1", "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(body.contains("Great post! Edited"));
+
+    // Replies with an id on another server are rejected.
+    let mut foreign = create.clone();
+    foreign["object"]["id"] = json!("https://victim.example.com/notes/1");
+    let status = post_inbox(&ctx, &stub, &foreign, true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    for (i, kind) in ["Like", "Announce", "Like"].iter().enumerate() {
+        let reaction = json!({
+            "id": format!("{}/reactions/{i}", stub.actor()),
+            "type": kind,
+            "actor": stub.actor(),
+            "object": "/ap/notes/1",
+        });
+        let status = post_inbox(&ctx, &stub, &reaction, true).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+    // The second like from the same actor is not counted.
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(body.contains("❤️ 1"));
+    assert!(body.contains("🔁 1"));
+
+    // Reactions to posts that do not exist are not stored.
+    let reaction = json!({
+        "id": format!("{}/reactions/missing", stub.actor()),
+        "type": "Like",
+        "actor": stub.actor(),
+        "object": "/ap/notes/999",
+    });
+    let status = post_inbox(&ctx, &stub, &reaction, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let count: i64 = ctx
+        .conn()
+        .query_row(
+            "SELECT COUNT(*) FROM reactions WHERE post_id = 999",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(count, 0);
+
+    let undo = json!({
+        "id": format!("{}/reactions/0/undo", stub.actor()),
+        "type": "Undo",
+        "actor": stub.actor(),
+        "object": format!("{}/reactions/0", stub.actor()),
+    });
+    let status = post_inbox(&ctx, &stub, &undo, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(body.contains("❤️ 0"));
+
+    let status = post_form(&ctx, &auth, "/replies/block/1", "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let body = html_body(&ctx, "", "/posts/1/lorem").await;
+    assert!(!body.contains("Great post!"));
+    assert!(!body.contains("🔁"));
+
+    // Activities from blocked domains are ignored.
+    let mut create = create.clone();
+    create["object"]["id"] = json!(format!("{}/notes/2", stub.actor()));
+    let status = post_inbox(&ctx, &stub, &create, true).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let body = html_body(&ctx, &auth, "/replies").await;
+    assert_eq!(body.matches("Great post!").count(), 1);
+    assert!(body.contains("unblock"));
+}
+
+#[tokio::test]
+async fn test_webfinger() {
+    let mut ctx = server_context().await;
+    ctx.args.domain = "fx.example.com".to_string();
+
+    let (status, _body) = json_body(&ctx, "/.well-known/webfinger").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let uri = "/.well-known/webfinger?resource=acct:bob@fx.example.com";
+    let (status, _body) = json_body(&ctx, uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let uri = "/.well-known/webfinger?resource=acct:test-admin@fx.example.com";
+    let (status, body) = json_body(&ctx, uri).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["subject"], "acct:test-admin@fx.example.com");
+    let links = body["links"].as_array().unwrap();
+    let link = links.iter().find(|link| link["rel"] == "self").unwrap();
+    assert_eq!(link["type"], "application/activity+json");
+    assert_eq!(link["href"], "https://fx.example.com/ap/actor");
+    assert!(
+        !links
+            .iter()
+            .any(|link| link["rel"] == "http://webfinger.net/rel/avatar")
+    );
+
+    let head = "<link rel='icon' href='/files/69b83ddf8f65695f'>";
+    fx::data::Kv::insert(&ctx.conn(), "extra_head", head.as_bytes()).unwrap();
+    let uri = "/.well-known/webfinger?resource=https://fx.example.com/ap/actor";
+    let (status, body) = json_body(&ctx, uri).await;
+    assert_eq!(status, StatusCode::OK);
+    let links = body["links"].as_array().unwrap();
+    let avatar = "https://fx.example.com/files/69b83ddf8f65695f";
+    assert!(links.iter().any(|link| link["href"] == avatar));
+    let (_status, actor) = json_body(&ctx, "/ap/actor").await;
+    assert_eq!(actor["icon"]["url"], avatar);
+}
@@ -5,13 +5,19 @@ use axum::extract::Request;
 use axum::http::StatusCode;
 use chrono::Utc;
 use common::*;
+use fx::blogroll::BlogCache;
+use fx::data::Post;
 use fx::serve::ServerContext;
 use fx::serve::app;
 use http_body_util::BodyExt;
+use serde_json::Value;
+use serde_json::json;
 use std::io::Cursor;
 use std::io::Read;
+use std::sync::Arc;
 use tar::Archive;
 use tar::Entry;
+use tokio::sync::Mutex;
 use tower::util::ServiceExt;
 use xz2::read::XzDecoder;
 
@@ -23,7 +29,10 @@ async fn test_api() {
 
 #[tokio::test]
 async fn test_no_access() {
-    let endpoints = ["/api/download/all.tar.xz"];
+    let endpoints = [
+        "/api/download/all.tar.xz",
+        "/api/download/changes.tar.xz?since=2000-01-01T00:00:00Z",
+    ];
     for endpoint in endpoints {
         let (status, _body) = request_body(endpoint).await;
         assert_eq!(status, StatusCode::UNAUTHORIZED);
@@ -118,10 +127,616 @@ async fn test_download_all() {
     assert!(content.contains(r#"author_name = "John""#));
 
     let mut fourth = entries.next().unwrap().unwrap();
-    assert!(path(&fourth).contains("files/example.txt"));
+    assert_eq!(path(&fourth), "files/index.toml");
     let mut content = String::new();
     fourth.read_to_string(&mut content).unwrap();
+    assert!(content.contains(r#"sha = "69b83ddf8f65695f""#));
+    assert!(content.contains(r#"mime_type = "text/plain""#));
+    assert!(content.contains(r#"filename = "example.txt""#));
+
+    let mut fifth = entries.next().unwrap().unwrap();
+    assert_eq!(path(&fifth), "files/69b83ddf8f65695f/example.txt");
+    let mut content = String::new();
+    fifth.read_to_string(&mut content).unwrap();
     assert_eq!(content, "example");
 
     assert!(entries.next().is_none());
 }
+
+#[tokio::test]
+async fn test_download_all_large_file() {
+    let ctx = server_context().await;
+    // Larger than the chunks in which the archive is streamed.
+    let data = (0..100_000).map(|i| i.to_string()).collect::<String>();
+    let uri = "/api/files?filename=large.txt";
+    let (status, _, _) = api_request(&ctx, "POST", uri, &data, true).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let req = Request::builder()
+        .uri("/api/download/all.tar.xz")
+        .header("Authorization", auth_header(&ctx))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // The size is not known up front because the archive is streamed.
+    assert!(response.headers().get("Content-Length").is_none());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+
+    let mut ar = Archive::new(XzDecoder::new(Cursor::new(body)));
+    let mut found = false;
+    for entry in ar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        if entry.path().unwrap().ends_with("large.txt") {
+            let mut content = String::new();
+            entry.read_to_string(&mut content).unwrap();
+            assert_eq!(content, data);
+            found = true;
+        }
+    }
+    assert!(found);
+}
+
+#[tokio::test]
+async fn test_download_all_from_file() {
+    // With a database file, the archive is read via a connection outside the
+    // pool.
+    let path = std::env::temp_dir().join(format!("fx-download-{}.sqlite", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let mut args = fx::ServeArgs::test_default();
+    args.production = true;
+    args.database_path = path.to_string_lossy().to_string();
+    let pool = fx::data::connect(&args).unwrap();
+    fx::data::init(&args, &pool.get().unwrap());
+    let salt = fx_auth::generate_salt();
+    let blog_cache = BlogCache::new(vec![]).await;
+    let blog_cache = Arc::new(Mutex::new(blog_cache));
+    let ctx = ServerContext::new(args, pool, salt, blog_cache).await;
+
+    let now = Utc::now();
+    let id = Post::insert(&ctx.conn(), now, now, "Stored in a file", None).unwrap();
+
+    let entries = download_entries(&ctx, "/api/download/all.tar.xz").await;
+    let post = entries
+        .iter()
+        .find(|(path, _)| *path == format!("posts/{id}.md"))
+        .unwrap();
+    assert!(post.1.contains("Stored in a file"));
+    drop(ctx);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
+
+/// Download an archive and return the path and content of each entry.
+async fn download_entries(ctx: &ServerContext, uri: &str) -> Vec<(String, String)> {
+    let req = Request::builder()
+        .uri(uri)
+        .header("Authorization", auth_header(ctx))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let mut ar = Archive::new(XzDecoder::new(Cursor::new(body)));
+    let mut entries = Vec::new();
+    for entry in ar.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let path = entry.path().unwrap().to_str().unwrap().to_string();
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        entries.push((path, content));
+    }
+    entries
+}
+
+#[tokio::test]
+async fn test_download_changes() {
+    let ctx = server_context().await;
+    let uri = "/api/download/changes.tar.xz?since=2000-01-01T00:00:00Z";
+    let entries = download_entries(&ctx, uri).await;
+    let paths = entries.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            "manifest.toml",
+            "posts/1.md",
+            "posts/2.md",
+            "files/index.toml",
+            "files/69b83ddf8f65695f/example.txt",
+        ]
+    );
+    let manifest: toml::Table = toml::from_str(&entries[0].1).unwrap();
+    assert_eq!(manifest["deleted_posts"].as_array().unwrap().len(), 0);
+    let until = manifest["until"].as_str().unwrap().to_string();
+
+    let body = json!({ "content": "Changed" }).to_string();
+    api_request(&ctx, "PUT", "/api/posts/1", &body, true).await;
+    api_request(&ctx, "DELETE", "/api/posts/2", "", true).await;
+    api_request(&ctx, "DELETE", "/api/files/69b83ddf8f65695f", "", true).await;
+
+    let uri = format!("/api/download/changes.tar.xz?since={until}");
+    let entries = download_entries(&ctx, &uri).await;
+    let paths = entries.iter().map(|(p, _)| p.as_str()).collect::<Vec<_>>();
+    assert_eq!(paths, ["manifest.toml", "posts/1.md", "files/index.toml"]);
+    assert!(entries[1].1.contains("Changed"));
+    assert!(!entries[2].1.contains("69b83ddf8f65695f"));
+    let manifest: toml::Table = toml::from_str(&entries[0].1).unwrap();
+    assert_eq!(manifest["since"].as_str().unwrap(), until);
+    assert_eq!(manifest["deleted_posts"].as_array().unwrap()[0], 2.into());
+    let deleted_files = manifest["deleted_files"].as_array().unwrap();
+    assert_eq!(deleted_files[0].as_str(), Some("69b83ddf8f65695f"));
+
+    let body = json!({ "author_name": "Someone else" }).to_string();
+    api_request(&ctx, "PATCH", "/api/settings", &body, true).await;
+    let entries = download_entries(&ctx, &uri).await;
+    assert!(entries.iter().any(|(p, _)| p == "settings/settings.toml"));
+
+    let uri = "/api/download/changes.tar.xz?since=yesterday";
+    let (status, _, _) = api_request(&ctx, "GET", uri, "", true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn api_request(
+    ctx: &ServerContext,
+    method: &str,
+    uri: &str,
+    body: &str,
+    authenticated: bool,
+) -> (StatusCode, axum::http::HeaderMap, String) {
+    let mut req = Request::builder().method(method).uri(uri);
+    if authenticated {
+        req = req.header("Authorization", auth_header(ctx));
+    }
+    let req = req.body(Body::from(body.to_string())).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, String::from_utf8_lossy(&body).to_string())
+}
+
+#[tokio::test]
+async fn test_posts_api() {
+    let ctx = server_context().await;
+    let (status, _, _) = api_request(&ctx, "GET", "/api/posts", "", false).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, body) = api_request(&ctx, "GET", "/api/posts", "", true).await;
+    assert_eq!(status, StatusCode::OK);
+    let list: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(list["total"], 2);
+    assert_eq!(list["page"], 1);
+    assert_eq!(list["posts"].as_array().unwrap().len(), 2);
+
+    let body = json!({
+        "content": "# New post\n\nWith *emphasis*.",
+        "created": "2024-01-02T03:04:05Z",
+    })
+    .to_string();
+    let (status, headers, body) = api_request(&ctx, "POST", "/api/posts", &body, true).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(headers["Location"], "/posts/3/new-post");
+    let post: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(post["id"], 3);
+    assert_eq!(post["slug"], "new-post");
+    assert_eq!(post["created"], "2024-01-02T03:04:05Z");
+    assert!(post["html"].as_str().unwrap().contains("<em>emphasis</em>"));
+
+    // The new post is the oldest so it is on the last page.
+    let uri = "/api/posts?page=2&per_page=2";
+    let (status, _, body) = api_request(&ctx, "GET", uri, "", true).await;
+    assert_eq!(status, StatusCode::OK);
+    let list: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(list["total"], 3);
+    assert_eq!(list["posts"].as_array().unwrap().len(), 1);
+    assert_eq!(list["posts"][0]["id"], 3);
+
+    let body = json!({ "content": "Edited" }).to_string();
+    let (status, _, body) = api_request(&ctx, "PUT", "/api/posts/3", &body, true).await;
+    assert_eq!(status, StatusCode::OK);
+    let post: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(post["content"].as_str().unwrap().trim(), "Edited");
+    assert_eq!(post["created"], "2024-01-02T03:04:05Z");
+    assert_ne!(post["updated"], post["created"]);
+
+    let body = json!({ "content": "# Renamed", "slug": "New Slug" }).to_string();
+    let (status, _, body) = api_request(&ctx, "PUT", "/api/posts/3", &body, true).await;
+    assert_eq!(status, StatusCode::OK);
+    let post: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(post["slug"], "new-slug");
+    let body = json!({ "content": "# Renamed again" }).to_string();
+    let (_, _, body) = api_request(&ctx, "PUT", "/api/posts/3", &body, true).await;
+    let post: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(post["slug"], "new-slug", "kept when omitted");
+
+    let (status, _, _) = api_request(&ctx, "PUT", "/api/posts/3", "{}", true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _, _) = api_request(&ctx, "DELETE", "/api/posts/3", "", true).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _) = api_request(&ctx, "GET", "/api/posts/3", "", true).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = api_request(&ctx, "DELETE", "/api/posts/3", "", true).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_files_api() {
+    let ctx = server_context().await;
+    let (status, _, _) = api_request(&ctx, "GET", "/api/files", "", false).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, body) = api_request(&ctx, "GET", "/api/files", "", true).await;
+    assert_eq!(status, StatusCode::OK);
+    let list: Value = serde_json::from_str(&body).unwrap();
+    let files = list["files"].as_array().unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0]["sha"], "69b83ddf8f65695f");
+    assert_eq!(files[0]["filename"], "example.txt");
+    assert_eq!(files[0]["size"], 7);
+    assert!(files[0].get("data").is_none());
+
+    let (status, _, _) = api_request(&ctx, "POST", "/api/files", "raw", true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files?filename=screenshot.png")
+        .header("Authorization", auth_header(&ctx))
+        .header("Content-Type", "image/png")
+        .body(Body::from("not really a png"))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let file: Value = serde_json::from_slice(&body).unwrap();
+    let sha = file["sha"].as_str().unwrap().to_string();
+    assert_eq!(file["mime_type"], "image/png");
+    assert_eq!(file["size"], 16);
+    assert_eq!(file["md_link"], format!("![screenshot.png](/files/{sha})"));
+
+    let boundary = "XBOUNDARY";
+    let body = format!(
+        "--{boundary}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"notes.txt\"\r\n\
+         Content-Type: text/plain\r\n\r\n\
+         some notes\r\n\
+         --{boundary}--\r\n"
+    );
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/files")
+        .header("Authorization", auth_header(&ctx))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let file: Value = serde_json::from_slice(&body).unwrap();
+    let notes_sha = file["sha"].as_str().unwrap().to_string();
+    let md_link = format!("[notes.txt](/files/{notes_sha}.txt)");
+    assert_eq!(file["md_link"], md_link);
+
+    let uri = format!("/api/files/{sha}");
+    let body = json!({ "filename": "renamed.png" }).to_string();
+    let (status, _, body) = api_request(&ctx, "PATCH", &uri, &body, true).await;
+    assert_eq!(status, StatusCode::OK);
+    let file: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(file["filename"], "renamed.png");
+
+    let (status, _, _) = api_request(&ctx, "DELETE", &uri, "", true).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _) = api_request(&ctx, "DELETE", &uri, "", true).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, _, body) = api_request(&ctx, "GET", "/api/files", "", true).await;
+    let list: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(list["files"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_settings_api() {
+    let ctx = server_context().await;
+    let (status, _, _) = api_request(&ctx, "GET", "/api/settings", "", false).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, body) = api_request(&ctx, "GET", "/api/settings", "", true).await;
+    assert_eq!(status, StatusCode::OK);
+    let settings: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(settings["author_name"], "John");
+
+    let body = json!({ "site_name": " New name ", "extra_head": "<style></style>" }).to_string();
+    let (status, _, body) = api_request(&ctx, "PATCH", "/api/settings", &body, true).await;
+    assert_eq!(status, StatusCode::OK);
+    let settings: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(settings["site_name"], "New name");
+    assert_eq!(settings["extra_head"], "<style></style>\n");
+    assert_eq!(settings["author_name"], "John");
+
+    let body = json!({ "author_name": "" }).to_string();
+    let (status, _, _) = api_request(&ctx, "PATCH", "/api/settings", &body, true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let body = json!({ "unknown": "value" }).to_string();
+    let (status, _, _) = api_request(&ctx, "PATCH", "/api/settings", &body, true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let req = Request::builder()
+        .method("PATCH")
+        .uri("/api/settings")
+        .header("Authorization", auth_header(&ctx))
+        .header("Content-Type", "application/toml")
+        .header("Accept", "application/toml")
+        .body(Body::from("author_name = \"Jane\"\ndark_mode = \"on\"\n"))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "application/toml");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains(r#"author_name = "Jane""#));
+    assert!(body.contains(r#"dark_mode = "on""#));
+    assert!(body.contains(r#"site_name = "New name""#));
+}
+
+#[tokio::test]
+async fn test_redirects_api() {
+    let ctx = server_context().await;
+    let (status, _, _) = api_request(&ctx, "GET", "/api/redirects", "", false).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let body = json!({ "from": "https://old.example/2020/01/hello/", "to": "/posts/1" });
+    let body = body.to_string();
+    let (status, _, body) = api_request(&ctx, "POST", "/api/redirects", &body, true).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let redirect: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(redirect["from"], "/2020/01/hello/");
+    assert_eq!(redirect["status"], 301);
+    let body = json!({ "from": "/old", "to": "/posts/2", "status": 200 }).to_string();
+    let (status, _, _) = api_request(&ctx, "POST", "/api/redirects", &body, true).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // Existing pages are not redirected.
+    let body = json!({ "from": "/files", "to": "/posts/2" }).to_string();
+    let (status, _, _) = api_request(&ctx, "POST", "/api/redirects", &body, true).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (status, headers, _) = api_request(&ctx, "GET", "/2020/01/hello", "", false).await;
+    assert_eq!(status, StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(headers["Location"], "/posts/1");
+    let (status, _, _) = api_request(&ctx, "GET", "/files", "", false).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _, body) = api_request(&ctx, "GET", "/api/redirects", "", true).await;
+    assert_eq!(status, StatusCode::OK);
+    let list: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(list["redirects"].as_array().unwrap().len(), 2);
+
+    let uri = "/api/redirects?from=%2F2020%2F01%2Fhello%2F";
+    let (status, _, _) = api_request(&ctx, "DELETE", uri, "", true).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _, _) = api_request(&ctx, "DELETE", uri, "", true).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _, _) = api_request(&ctx, "GET", "/2020/01/hello/", "", false).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_posts_api_conflict() {
+    let ctx = server_context().await;
+    let (status, headers, _) = api_request(&ctx, "GET", "/api/posts/1", "", true).await;
+    assert_eq!(status, StatusCode::OK);
+    let etag = headers["ETag"].to_str().unwrap().to_string();
+
+    let put = |body: String, if_match: Option<String>| {
+        let mut req = Request::builder()
+            .method("PUT")
+            .uri("/api/posts/1")
+            .header("Authorization", auth_header(&ctx));
+        if let Some(if_match) = if_match {
+            req = req.header("If-Match", if_match);
+        }
+        req.body(Body::from(body)).unwrap()
+    };
+    let body = json!({ "content": "First" }).to_string();
+    let response = app(ctx.clone())
+        .oneshot(put(body, Some(etag.clone())))
+        .await;
+    let response = response.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()["ETag"], etag.as_str());
+
+    let body = json!({ "content": "Second" }).to_string();
+    let response = app(ctx.clone()).oneshot(put(body, Some(etag))).await;
+    let response = response.unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["current"]["content"].as_str().unwrap().trim(), "First");
+
+    let body = json!({ "content": "Second", "updated": "2000-01-01T00:00:00Z" }).to_string();
+    let response = app(ctx.clone()).oneshot(put(body, None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // An edit that is saved between reading and updating the post wins.
+    let previous = fx::data::Post::get(&ctx.conn(), 1).unwrap();
+    let mut other = previous.clone();
+    other.content = "Other".to_string();
+    other.update(&ctx.conn()).unwrap();
+    let mut post = previous.clone();
+    post.content = "Third".to_string();
+    assert_eq!(post.update_if_unchanged(&ctx.conn(), &previous), Ok(0));
+    let saved = fx::data::Post::get(&ctx.conn(), 1).unwrap();
+    assert_eq!(saved.content.trim(), "Other");
+    assert_eq!(other.update_if_unchanged(&ctx.conn(), &saved), Ok(1));
+}
+
+#[tokio::test]
+async fn test_openapi() {
+    let ctx = server_context().await;
+    let (_, _, body) = api_request(&ctx, "GET", "/api", "", false).await;
+    let api: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(api["openapi_url"], "/api/openapi.json");
+
+    let (status, _, body) = api_request(&ctx, "GET", "/api/openapi.json", "", false).await;
+    assert_eq!(status, StatusCode::OK);
+    let doc: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(doc["openapi"], "3.1.0");
+    let update = &doc["paths"]["/api/posts/{id}"]["put"];
+    assert_eq!(update["operationId"], "update_post");
+    assert_eq!(update["security"][0]["bearer"], json!([]));
+    assert_eq!(update["parameters"][0]["name"], "id");
+    let schema = &update["requestBody"]["content"]["application/json"]["schema"];
+    assert_eq!(schema["$ref"], "#/components/schemas/PostRequest");
+    let list = &doc["paths"]["/api/posts"]["get"];
+    assert!(
+        list["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|p| p["name"] == "per_page" && p["in"] == "query")
+    );
+
+    // Every reference points to an existing schema.
+    let schemas = doc["components"]["schemas"].as_object().unwrap();
+    for reference in body.split("\"$ref\":\"").skip(1) {
+        let reference = reference.split('"').next().unwrap();
+        let name = reference.strip_prefix("#/components/schemas/").unwrap();
+        assert!(schemas.contains_key(name), "missing schema {name}");
+    }
+
+    // Every documented operation is routed.
+    for (path, operations) in doc["paths"].as_object().unwrap() {
+        for method in operations.as_object().unwrap().keys() {
+            let uri = path
+                .replace("{id}", "1")
+                .replace("{sha}", "69b83ddf8f65695f");
+            let method = method.to_uppercase();
+            let (status, _, _) = api_request(&ctx, &method, &uri, "", false).await;
+            assert_ne!(status, StatusCode::NOT_FOUND, "{method} {path}");
+            assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{method} {path}");
+        }
+    }
+}
+
+async fn restore_request(ctx: &ServerContext, uri: &str, archive: Vec<u8>) -> (StatusCode, Value) {
+    let req = Request::builder()
+        .method("POST")
+        .uri(uri)
+        .header("Authorization", auth_header(ctx))
+        .header("Content-Type", "application/octet-stream")
+        .body(Body::from(archive))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, serde_json::from_slice(&body).unwrap())
+}
+
+#[tokio::test]
+async fn test_restore() {
+    let ctx = server_context().await;
+    let req = Request::builder()
+        .uri("/api/download/all.tar.xz")
+        .header("Authorization", auth_header(&ctx))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let archive: Vec<u8> = response
+        .into_body()
+        .collect()
+        .await
+        .unwrap()
+        .to_bytes()
+        .into();
+    let original = Post::get(&ctx.conn(), 1).unwrap();
+
+    let body = json!({ "content": "Changed" }).to_string();
+    api_request(&ctx, "PUT", "/api/posts/1", &body, true).await;
+    api_request(&ctx, "DELETE", "/api/posts/2", "", true).await;
+    let body = json!({ "author_name": "Someone else" }).to_string();
+    api_request(&ctx, "PATCH", "/api/settings", &body, true).await;
+    let uri = "/api/files/69b83ddf8f65695f";
+    api_request(&ctx, "DELETE", uri, "", true).await;
+
+    let (status, report) =
+        restore_request(&ctx, "/api/restore?dry_run=true", archive.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["dry_run"], true);
+    assert_eq!(report["posts_updated"], json!([1]));
+    assert_eq!(report["posts_created"], json!([2]));
+    assert_eq!(report["settings_changed"], true);
+    assert_eq!(report["files_added"].as_array().unwrap().len(), 1);
+    assert_eq!(Post::get(&ctx.conn(), 1).unwrap().content.trim(), "Changed");
+
+    let (status, report) = restore_request(&ctx, "/api/restore", archive.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["dry_run"], false);
+    let restored = Post::get(&ctx.conn(), 1).unwrap();
+    assert_eq!(restored.content, original.content);
+    assert_eq!(restored.created, original.created);
+    assert_eq!(restored.updated, original.updated);
+    assert!(
+        Post::get(&ctx.conn(), 2)
+            .unwrap()
+            .content
+            .contains("# Code")
+    );
+    let (_, _, body) = api_request(&ctx, "GET", "/api/settings", "", true).await;
+    let settings: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(settings["author_name"], "John");
+    // The sha is kept even though it does not match the data.
+    assert_eq!(report["files_added"], json!(["69b83ddf8f65695f"]));
+    let uri = "/files/69b83ddf8f65695f";
+    let (status, headers, body) = api_request(&ctx, "GET", uri, "", false).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["Content-Type"], "text/plain");
+    assert_eq!(body, "example");
+
+    // Restoring again changes nothing, also when the upload arrives in chunks.
+    let chunks = archive
+        .chunks(100)
+        .map(|chunk| Ok::<_, std::io::Error>(chunk.to_vec()))
+        .collect::<Vec<_>>();
+    let req = Request::builder()
+        .method("POST")
+        .uri("/api/restore")
+        .header("Authorization", auth_header(&ctx))
+        .body(Body::from_stream(futures_util::stream::iter(chunks)))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let report: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["posts_unchanged"], 2);
+    assert_eq!(report["settings_changed"], false);
+    assert_eq!(report["files_unchanged"], 1);
+
+    let (status, _) = restore_request(&ctx, "/api/restore", b"garbage".to_vec()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_restore_legacy_files() {
+    let ctx = server_context().await;
+    // Archives from before `files/index.toml` stored files by their name, which
+    // may contain a directory.
+    let mut ar = tar::Builder::new(xz2::write::XzEncoder::new(Vec::new(), 6));
+    for (path, data) in [("files/example/a.txt", "nested"), ("files/b.txt", "flat")] {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        ar.append_data(&mut header, path, data.as_bytes()).unwrap();
+    }
+    let archive = ar.into_inner().unwrap().finish().unwrap();
+
+    let (status, report) = restore_request(&ctx, "/api/restore", archive).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(report["files_added"].as_array().unwrap().len(), 2);
+    let (_, _, body) = api_request(&ctx, "GET", "/api/files", "", true).await;
+    assert!(body.contains(r#""filename":"example/a.txt""#));
+    assert!(body.contains(r#""filename":"b.txt""#));
+}
@@ -31,6 +31,8 @@ impl TestDefault for ServeArgs {
             username: "test-admin".to_string(),
             html_lang: "en".to_string(),
             log_level: "info".to_string(),
+            // The stub servers in the tests listen on 127.0.0.1.
+            allow_private_addresses: true,
             password: Some("test-password".to_string()),
             domain: "".to_string(),
         }
@@ -56,6 +58,7 @@ pub async fn server_context() -> ServerContext {
     ServerContext::new(args, conn, salt, blog_cache).await
 }
 
+#[allow(dead_code)]
 pub async fn request_body(uri: &str) -> (StatusCode, String) {
     let ctx = server_context().await;
     let app = app(ctx);
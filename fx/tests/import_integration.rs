@@ -0,0 +1,332 @@
+mod common;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::StatusCode;
+use common::*;
+use fx::data::Post;
+use fx::import::ImportSource;
+use fx::serve::ServerContext;
+use fx::serve::app;
+use http_body_util::BodyExt;
+use serde_json::json;
+use std::io::Cursor;
+use std::io::Write;
+use tower::util::ServiceExt;
+use zip::ZipWriter;
+use zip::write::SimpleFileOptions;
+
+const ACTOR: &str = "https://mastodon.example/users/alice";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+fn create(id: u32, published: &str, content: &str, in_reply_to: Option<&str>) -> serde_json::Value {
+    json!({
+        "id": format!("{ACTOR}/statuses/{id}/activity"),
+        "type": "Create",
+        "actor": ACTOR,
+        "published": published,
+        "to": [PUBLIC],
+        "cc": [format!("{ACTOR}/followers")],
+        "object": {
+            "id": format!("{ACTOR}/statuses/{id}"),
+            "type": "Note",
+            "published": published,
+            "inReplyTo": in_reply_to,
+            "content": content,
+            "to": [PUBLIC],
+            "attachment": [],
+        },
+    })
+}
+
+fn mastodon_export() -> Vec<u8> {
+    let mut with_media = create(1, "2022-11-05T10:00:00Z", "<p>Hello fediverse</p>", None);
+    with_media["object"]["attachment"] = json!([{
+        "type": "Document",
+        "mediaType": "image/png",
+        "url": "/media_attachments/files/109/original/cat.png",
+        "name": "A cat",
+    }]);
+    let thread = create(
+        2,
+        "2022-11-05T10:01:00Z",
+        "<p>Second part</p>",
+        Some(&format!("{ACTOR}/statuses/1")),
+    );
+    let reply = create(
+        3,
+        "2022-11-06T10:00:00Z",
+        "<p>Nice!</p>",
+        Some("https://other.example/users/bob/statuses/9"),
+    );
+    let mut direct = create(4, "2022-11-07T10:00:00Z", "<p>Secret</p>", None);
+    direct["to"] = json!(["https://other.example/users/bob"]);
+    direct["cc"] = json!([]);
+    direct["object"]["to"] = json!(["https://other.example/users/bob"]);
+    let boost = json!({
+        "id": format!("{ACTOR}/statuses/5/activity"),
+        "type": "Announce",
+        "actor": ACTOR,
+        "published": "2022-11-08T10:00:00Z",
+        "to": [PUBLIC],
+        "object": "https://other.example/users/bob/statuses/10",
+    });
+    let outbox = json!({
+        "@context": "https://www.w3.org/ns/activitystreams",
+        "type": "OrderedCollection",
+        "orderedItems": [thread, with_media, reply, direct, boost],
+    });
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default();
+    zip.start_file("outbox.json", options).unwrap();
+    zip.write_all(outbox.to_string().as_bytes()).unwrap();
+    zip.start_file("media_attachments/files/109/original/cat.png", options)
+        .unwrap();
+    zip.write_all(b"not really a png").unwrap();
+    zip.finish().unwrap().into_inner()
+}
+
+async fn post_import(
+    ctx: &ServerContext,
+    auth: &str,
+    platform: &str,
+    archive: &[u8],
+) -> (StatusCode, String) {
+    let boundary = "fx-test-boundary";
+    let mut body = Vec::new();
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"source\"\r\n\r\n\
+            {platform}\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(
+        format!(
+            "--{boundary}\r\n\
+            Content-Disposition: form-data; name=\"archive\"; filename=\"archive.zip\"\r\n\
+            Content-Type: application/zip\r\n\r\n"
+        )
+        .as_bytes(),
+    );
+    body.extend_from_slice(archive);
+    body.extend_from_slice(format!("\r\n--{boundary}--\r\n").as_bytes());
+    let req = Request::builder()
+        .method("POST")
+        .uri("/import")
+        .header("Cookie", format!("auth={auth}"))
+        .header(
+            "Content-Type",
+            format!("multipart/form-data; boundary={boundary}"),
+        )
+        .body(Body::from(body))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_import_mastodon() {
+    let (ctx, auth) = request_cookie().await;
+    let (status, _body) = request_body("/import").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, body) = request_body_logged_in("/import").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(String::from_utf8(body).unwrap().contains("name='archive'"));
+
+    let archive = mastodon_export();
+    let (status, body) = post_import(&ctx, &auth, "mastodon", &archive).await;
+    assert_eq!(status, StatusCode::OK);
+    println!("{body}");
+    assert!(body.contains("posts: 2"));
+    assert!(body.contains("skipped: 3"));
+    assert!(body.contains("statuses/3: reply to"));
+    assert!(body.contains("statuses/4: not public"));
+    assert!(body.contains("statuses/5/activity: boost"));
+
+    let posts = Post::list(&ctx.conn()).unwrap();
+    assert_eq!(posts.len(), 4);
+    let first = posts.iter().find(|p| p.content.contains("Hello")).unwrap();
+    assert_eq!(first.created.to_rfc3339(), "2022-11-05T10:00:00+00:00");
+    let thread = posts.iter().find(|p| p.content.contains("Second")).unwrap();
+    assert!(first.id < thread.id);
+    let link = first.content.lines().last().unwrap();
+    assert!(link.starts_with("![A cat](/files/"));
+    let sha = link
+        .trim_start_matches("![A cat](/files/")
+        .trim_end_matches(')');
+    let req = Request::builder()
+        .uri(format!("/files/{sha}"))
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Importing again does not create duplicates.
+    let (status, body) = post_import(&ctx, &auth, "mastodon", &archive).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("posts: 0"));
+    assert!(body.contains("posts already present: 2"));
+    assert_eq!(Post::list(&ctx.conn()).unwrap().len(), 4);
+
+    let (status, _) = post_import(&ctx, &auth, "mastodon", b"not a zip").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+const WXR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0"
+    xmlns:content="http://purl.org/rss/1.0/modules/content/"
+    xmlns:wp="http://wordpress.org/export/1.2/">
+<channel>
+    <title>Old blog</title>
+    <item>
+        <title>Hello &amp; welcome</title>
+        <link>https://blog.example/2020/01/hello/</link>
+        <wp:post_date_gmt>2020-01-02 03:04:05</wp:post_date_gmt>
+        <wp:post_modified_gmt>2020-02-03 04:05:06</wp:post_modified_gmt>
+        <wp:status>publish</wp:status>
+        <wp:post_type>post</wp:post_type>
+        <content:encoded><![CDATA[First <em>post</em>.
+
+<img src="https://blog.example/wp-content/uploads/2020/01/cat.png" alt="Cat" />]]></content:encoded>
+        <wp:comment><wp:comment_content>Nice</wp:comment_content></wp:comment>
+    </item>
+    <item>
+        <title>About</title>
+        <link>https://blog.example/about/</link>
+        <wp:post_date_gmt>2019-06-07 08:09:10</wp:post_date_gmt>
+        <wp:status>publish</wp:status>
+        <wp:post_type>page</wp:post_type>
+        <content:encoded><![CDATA[<p>About me</p>]]></content:encoded>
+    </item>
+    <item>
+        <title>Unfinished</title>
+        <wp:post_date_gmt>0000-00-00 00:00:00</wp:post_date_gmt>
+        <wp:status>draft</wp:status>
+        <wp:post_type>post</wp:post_type>
+        <content:encoded><![CDATA[Soon]]></content:encoded>
+    </item>
+    <item>
+        <title>cat</title>
+        <wp:post_type>attachment</wp:post_type>
+        <wp:status>inherit</wp:status>
+        <wp:attachment_url>https://blog.example/wp-content/uploads/2020/01/cat.png</wp:attachment_url>
+    </item>
+    <item>
+        <title>Home</title>
+        <wp:post_type>nav_menu_item</wp:post_type>
+    </item>
+</channel>
+</rss>
+"#;
+
+#[tokio::test]
+async fn test_import_wordpress() {
+    let (ctx, auth) = request_cookie().await;
+    let (status, body) = post_import(&ctx, &auth, "wordpress", WXR.as_bytes()).await;
+    assert_eq!(status, StatusCode::OK);
+    println!("{body}");
+    assert!(body.contains("posts: 2"));
+    assert!(body.contains("post &quot;Unfinished&quot;: draft"));
+    assert!(body.contains("post &quot;Hello &amp; welcome&quot;: 1 comments"));
+    assert!(body.contains("upload 2020/01/cat.png: no uploads directory given"));
+    assert!(body.contains("nav_menu_item &quot;Home&quot;: unsupported type"));
+
+    let posts = Post::list(&ctx.conn()).unwrap();
+    assert_eq!(posts.len(), 4);
+    let about = posts.iter().find(|p| p.content.contains("About")).unwrap();
+    let hello = posts.iter().find(|p| p.content.contains("Hello")).unwrap();
+    // Oldest first.
+    assert!(about.id < hello.id);
+    assert_eq!(hello.created.to_rfc3339(), "2020-01-02T03:04:05+00:00");
+    assert_eq!(hello.updated.to_rfc3339(), "2020-02-03T04:05:06+00:00");
+    assert!(
+        hello
+            .content
+            .starts_with("# Hello & welcome\n\nFirst *post*.")
+    );
+    let redirect = format!(
+        "https://blog.example/2020/01/hello/ -&gt; /posts/{}",
+        hello.id
+    );
+    assert!(body.contains(&redirect));
+
+    let (status, _) = post_import(&ctx, &auth, "wordpress", b"<rss><channel>").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+fn write(root: &std::path::Path, path: &str, content: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, content).unwrap();
+}
+
+#[tokio::test]
+async fn test_import_markdown_directory() {
+    let root = std::env::temp_dir().join(format!("fx-import-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    write(
+        &root,
+        "content/posts/bundle/index.md",
+        "+++\ntitle = \"Bundle\"\ndate = 2021-03-04T05:06:07Z\n+++\n![Cat](cat.png)\n",
+    );
+    write(&root, "content/posts/bundle/cat.png", "cat");
+    write(
+        &root,
+        "content/posts/draft.md",
+        "+++\ndraft = true\n+++\nSoon\n",
+    );
+    write(&root, "content/_index.md", "---\ntitle: Posts\n---\n");
+    write(
+        &root,
+        "_posts/2019-05-06-old.md",
+        "---\nlayout: post\ntitle: Old\n---\nSee [the logo](/img/logo.svg).\n",
+    );
+    write(&root, "static/img/logo.svg", "<svg/>");
+    write(&root, "static/unused.css", "body {}");
+    write(&root, "public/index.md", "---\ntitle: Generated\n---\n");
+
+    let (ctx, _auth) = request_cookie().await;
+    let source = ImportSource::Markdown {
+        directory: root.to_string_lossy().to_string(),
+    };
+    let import = source.source().unwrap().read().unwrap();
+    let report = fx::import::apply(&mut ctx.conn(), import, false).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+    let text = report.to_string();
+    println!("{text}");
+    assert_eq!(report.posts_imported, 2);
+    assert_eq!(report.files_added.len(), 2);
+    assert!(text.contains("content/posts/draft.md: draft"));
+    assert!(text.contains("content/_index.md: section page"));
+    assert!(!text.contains("Generated"));
+
+    let posts = Post::list(&ctx.conn()).unwrap();
+    let old = posts.iter().find(|p| p.content.contains("# Old")).unwrap();
+    assert_eq!(old.created.to_rfc3339(), "2019-05-06T00:00:00+00:00");
+    assert!(old.content.contains("[the logo](/files/"));
+    let bundle = posts
+        .iter()
+        .find(|p| p.content.contains("# Bundle"))
+        .unwrap();
+    assert!(bundle.content.contains("![Cat](/files/"));
+    assert!(old.id < bundle.id);
+    let redirect = format!("/2019/05/06/old.html -> /posts/{}", old.id);
+    assert!(text.contains(&redirect));
+    let redirect = format!("/posts/bundle/ -> /posts/{}", bundle.id);
+    assert!(text.contains(&redirect));
+    assert!(text.contains("/img/logo.svg -> /files/"));
+
+    // The old URLs now redirect to the imported posts.
+    let req = Request::builder()
+        .uri("/2019/05/06/old.html")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()["Location"], format!("/posts/{}", old.id));
+}
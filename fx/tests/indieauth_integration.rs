@@ -0,0 +1,236 @@
+mod common;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use common::*;
+use fx::serve::ServerContext;
+use fx::serve::app;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use tower::util::ServiceExt;
+
+const CLIENT_ID: &str = "https://app.example.com/";
+const REDIRECT_URI: &str = "https://app.example.com/callback";
+// Example from RFC 7636 Appendix B.
+const VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+const CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+async fn request(
+    ctx: &ServerContext,
+    method: &str,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: &str,
+) -> (StatusCode, HeaderMap, String) {
+    let mut req = Request::builder().method(method).uri(uri);
+    if !headers.iter().any(|(key, _)| *key == "Content-Type") {
+        req = req.header("Content-Type", "application/x-www-form-urlencoded");
+    }
+    for (key, value) in headers {
+        req = req.header(*key, *value);
+    }
+    let req = req.body(Body::from(body.to_string())).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, String::from_utf8_lossy(&body).to_string())
+}
+
+fn encode(pairs: &[(&str, &str)]) -> String {
+    serde_urlencoded::to_string(pairs).unwrap()
+}
+
+/// Approve the request on the consent page and return the code.
+async fn authorize(ctx: &ServerContext, auth: &str, scope: &str) -> String {
+    let query = encode(&[
+        ("response_type", "code"),
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", REDIRECT_URI),
+        ("state", "1234"),
+        ("code_challenge", CHALLENGE),
+        ("code_challenge_method", "S256"),
+        ("scope", scope),
+    ]);
+    let uri = format!("/auth?{query}");
+    let cookie = format!("auth={auth}");
+    let (status, _, body) = request(ctx, "GET", &uri, &[("Cookie", &cookie)], "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Approve"));
+    assert!(body.contains(CLIENT_ID));
+
+    let form = encode(&[
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", REDIRECT_URI),
+        ("state", "1234"),
+        ("code_challenge", CHALLENGE),
+        ("scope", scope),
+    ]);
+    let cookie = [("Cookie", cookie.as_str())];
+    let (status, headers, _) = request(ctx, "POST", "/auth/approve", &cookie, &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let location = headers["Location"].to_str().unwrap();
+    let location = reqwest::Url::parse(location).unwrap();
+    assert_eq!(location.path(), "/callback");
+    let pairs = location.query_pairs().collect::<Vec<_>>();
+    assert!(pairs.iter().any(|(k, v)| k == "state" && v == "1234"));
+    let code = pairs.iter().find(|(k, _)| k == "code").unwrap();
+    code.1.to_string()
+}
+
+fn redeem_form(code: &str, verifier: &str) -> String {
+    encode(&[
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", REDIRECT_URI),
+        ("code_verifier", verifier),
+    ])
+}
+
+#[tokio::test]
+async fn test_metadata_and_links() {
+    let ctx = server_context().await;
+    let uri = "/.well-known/oauth-authorization-server";
+    let (status, _, body) = request(&ctx, "GET", uri, &[], "").await;
+    assert_eq!(status, StatusCode::OK);
+    let metadata: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(metadata["authorization_endpoint"], "/auth");
+    assert_eq!(metadata["code_challenge_methods_supported"][0], "S256");
+
+    let (_, _, body) = request(&ctx, "GET", "/", &[], "").await;
+    assert!(body.contains("<link rel='authorization_endpoint' href='/auth'>"));
+}
+
+#[tokio::test]
+async fn test_consent_requires_login() {
+    let ctx = server_context().await;
+    let query = encode(&[
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", REDIRECT_URI),
+        ("state", "1234"),
+        ("code_challenge", CHALLENGE),
+        ("code_challenge_method", "S256"),
+    ]);
+    let uri = format!("/auth?{query}");
+    let (status, _, body) = request(&ctx, "GET", &uri, &[], "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.contains("Approve"));
+
+    // The login returns to the consent page.
+    let login = format!("/login?{}", encode(&[("next", &uri)]));
+    assert!(body.contains(&format!("href='{}'", login.replace('&', "&amp;"))));
+    let (status, _, body) = request(&ctx, "GET", &login, &[], "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(&format!("action='{}'", login.replace('&', "&amp;"))));
+    let form = encode(&[("username", "test-admin"), ("password", "test-password")]);
+    let (status, headers, _) = request(&ctx, "POST", &login, &[], &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers["Location"], uri.as_str());
+    let cookie = headers["Set-Cookie"].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap();
+    let (status, _, body) = request(&ctx, "GET", &uri, &[("Cookie", cookie)], "").await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Approve"));
+
+    // The login does not redirect to other sites.
+    let login = format!("/login?{}", encode(&[("next", "//evil.example.com")]));
+    let (status, headers, _) = request(&ctx, "POST", &login, &[], &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    assert_eq!(headers["Location"], "/");
+
+    let form = encode(&[
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", REDIRECT_URI),
+        ("state", "1234"),
+        ("code_challenge", CHALLENGE),
+    ]);
+    let (status, _, _) = request(&ctx, "POST", "/auth/approve", &[], &form).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // Redirects to other hosts are not allowed.
+    let query = encode(&[
+        ("client_id", CLIENT_ID),
+        ("redirect_uri", "https://evil.example.com/callback"),
+        ("state", "1234"),
+        ("code_challenge", CHALLENGE),
+        ("code_challenge_method", "S256"),
+    ]);
+    let uri = format!("/auth?{query}");
+    let (status, _, _) = request(&ctx, "GET", &uri, &[], "").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_sign_in() {
+    let (ctx, auth) = request_cookie().await;
+    let code = authorize(&ctx, &auth, "").await;
+
+    let form = redeem_form(&code, "wrong-verifier");
+    let (status, _, _) = request(&ctx, "POST", "/auth", &[], &form).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // The code was used by the failed attempt.
+    let form = redeem_form(&code, VERIFIER);
+    let (status, _, _) = request(&ctx, "POST", "/auth", &[], &form).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let code = authorize(&ctx, &auth, "").await;
+    let form = redeem_form(&code, VERIFIER);
+    let (status, _, body) = request(&ctx, "POST", "/auth", &[], &form).await;
+    assert_eq!(status, StatusCode::OK);
+    let profile: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(profile["me"], "/");
+}
+
+#[tokio::test]
+async fn test_token() {
+    let (ctx, auth) = request_cookie().await;
+    let code = authorize(&ctx, &auth, "create update").await;
+    let form = redeem_form(&code, VERIFIER);
+    let (status, _, body) = request(&ctx, "POST", "/token", &[], &form).await;
+    assert_eq!(status, StatusCode::OK);
+    let token: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(token["token_type"], "Bearer");
+    assert_eq!(token["scope"], "create update");
+    let access_token = token["access_token"].as_str().unwrap();
+    let bearer = format!("Bearer {access_token}");
+
+    let headers = [("Authorization", bearer.as_str())];
+    let (status, _, body) = request(&ctx, "GET", "/token", &headers, "").await;
+    assert_eq!(status, StatusCode::OK);
+    let info: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(info["client_id"], CLIENT_ID);
+
+    // The token works for Micropub but not for the admin API.
+    let (status, _, _) = request(&ctx, "GET", "/api/download/all.tar.xz", &headers, "").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = request(&ctx, "GET", "/micropub?q=config", &headers, "").await;
+    assert_eq!(status, StatusCode::OK);
+    let form = "h=entry&content=Hello+from+an+app";
+    let (status, _, _) = request(&ctx, "POST", "/micropub", &headers, form).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    // Actions outside the granted scope are refused.
+    let form = "action=delete&url=/posts/1";
+    let (status, _, body) = request(&ctx, "POST", "/micropub", &headers, form).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body.contains("insufficient_scope"));
+    let multipart = [
+        ("Authorization", bearer.as_str()),
+        ("Content-Type", "multipart/form-data; boundary=x"),
+    ];
+    let body = "--x\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\na\r\n--x--\r\n";
+    let (status, _, _) = request(&ctx, "POST", "/micropub/media", &multipart, body).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let form = encode(&[("token", access_token)]);
+    let (status, _, _) = request(&ctx, "POST", "/token/revoke", &[], &form).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = request(&ctx, "GET", "/token", &headers, "").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = request(&ctx, "GET", "/micropub?q=config", &headers, "").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
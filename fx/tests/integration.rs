@@ -242,6 +242,8 @@ async fn test_post_edit() {
     let (ctx, auth) = request_cookie().await;
     let form = fx::serve::EditPostForm {
         content: "Lorem https://example.com".to_string(),
+        version: None,
+        slug: None,
     };
     let form_data = serde_urlencoded::to_string(&form).unwrap();
     let req = Request::builder()
@@ -262,6 +264,251 @@ async fn test_post_edit() {
     assert!(body.contains("https://example.com"), "text not updated");
 }
 
+#[tokio::test]
+async fn test_post_edit_slug() {
+    let (ctx, auth) = request_cookie().await;
+    let edit = |content: &str, slug: &str| {
+        let version = fx::data::Post::get(&ctx.conn(), 2).unwrap().version();
+        let form = [
+            ("content", content),
+            ("slug", slug),
+            ("version", &version),
+            ("publish", "Publish"),
+        ];
+        let form = serde_urlencoded::to_string(form).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/posts/edit/2")
+            .header("Cookie", format!("auth={auth}"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .unwrap()
+    };
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let location = |response: &axum::response::Response| {
+        let location = response.headers().get("Location").unwrap();
+        location.to_str().unwrap().to_string()
+    };
+
+    let response = app(ctx.clone())
+        .oneshot(edit("# Code\n\nText", "Über Code"))
+        .await;
+    assert_eq!(response.unwrap().status(), StatusCode::SEE_OTHER);
+    let response = app(ctx.clone()).oneshot(get("/posts/2/uber-code")).await;
+    assert_eq!(response.unwrap().status(), StatusCode::OK);
+    // The slug stays the same when the title changes.
+    let response = app(ctx.clone())
+        .oneshot(edit("# Other\n\nText", "uber-code"))
+        .await;
+    assert_eq!(response.unwrap().status(), StatusCode::SEE_OTHER);
+    let response = app(ctx.clone()).oneshot(get("/posts/2")).await.unwrap();
+    assert_eq!(location(&response), "/posts/2/uber-code");
+    // Links with the old slug redirect to the new one.
+    let response = app(ctx.clone())
+        .oneshot(get("/posts/2/code"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(location(&response), "/posts/2/uber-code");
+
+    // An empty slug goes back to the slug from the title.
+    let response = app(ctx.clone()).oneshot(edit("# Other\n\nText", "")).await;
+    assert_eq!(response.unwrap().status(), StatusCode::SEE_OTHER);
+    let response = app(ctx.clone())
+        .oneshot(get("/posts/2/uber-code"))
+        .await
+        .unwrap();
+    assert_eq!(location(&response), "/posts/2/other");
+}
+
+#[tokio::test]
+async fn test_post_front_matter() {
+    let (ctx, auth) = request_cookie().await;
+    let content = indoc::indoc! {"
+        ---
+        title: 'Explicit title'
+        description: 'Explicit description'
+        image: '/files/69b83ddf8f65695f'
+        canonical: 'https://example.com/original'
+        lang: 'nl'
+        created: '2020-01-02 03:04:05 UTC'
+        ---
+
+        # Code
+
+        Body text.
+    "};
+    let version = fx::data::Post::get(&ctx.conn(), 2).unwrap().version();
+    let form = [
+        ("content", content),
+        ("version", &version),
+        ("publish", "Publish"),
+    ];
+    let req = Request::builder()
+        .method("POST")
+        .uri("/posts/edit/2")
+        .header("Cookie", format!("auth={auth}"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(serde_urlencoded::to_string(form).unwrap()))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    let post = fx::data::Post::get(&ctx.conn(), 2).unwrap();
+    assert_eq!(post.created.to_rfc3339(), "2020-01-02T03:04:05+00:00");
+    // The slug is derived from the explicit title.
+    let req = Request::builder()
+        .uri("/posts/2/explicit-title")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    println!("body:\n{body}");
+    assert!(body.contains("<html lang='nl'"));
+    assert!(body.contains("<title>Explicit title - "));
+    assert!(body.contains("<meta name='description' content='Explicit description'/>"));
+    assert!(body.contains("<meta property='og:image' content='/files/69b83ddf8f65695f'/>"));
+    assert!(body.contains("<link rel='canonical' href='https://example.com/original'/>"));
+    assert!(body.contains("Body text."));
+    assert!(!body.contains("canonical:"), "front matter is not rendered");
+}
+
+#[tokio::test]
+async fn test_post_og_image() {
+    let ctx = server_context().await;
+    let req = Request::builder()
+        .uri("/posts/2/og.png")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["Content-Type"], "image/png");
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert!(body.starts_with(b"\x89PNG"));
+
+    let (_, body) = request_body("/posts/2/code").await;
+    assert!(body.contains("<meta property='og:image' content='/posts/2/og.png'/>"));
+    assert!(body.contains("<meta name='twitter:card' content='summary_large_image'/>"));
+
+    // The first image in the post overrides the generated card.
+    let mut post = fx::data::Post::get(&ctx.conn(), 2).unwrap();
+    post.content = "# Code\n\n![Diagram](/files/69b83ddf8f65695f)\n".to_string();
+    post.update(&ctx.conn()).unwrap();
+    let req = Request::builder()
+        .uri("/posts/2/code")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("<meta property='og:image' content='/files/69b83ddf8f65695f'/>"));
+
+    let req = Request::builder()
+        .uri("/posts/999/og.png")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_post_structured_data() {
+    let (status, body) = request_body("/posts/2/code").await;
+    assert_eq!(status, StatusCode::OK);
+    let start = "<script type='application/ld+json'>";
+    let json = body.split_once(start).unwrap().1;
+    let json = json.split_once("</script>").unwrap().0;
+    let json: serde_json::Value = serde_json::from_str(json).unwrap();
+    assert_eq!(json["@type"], "BlogPosting");
+    assert_eq!(json["headline"], "Code");
+    assert_eq!(json["url"], "/posts/2/code");
+    assert_eq!(json["image"], "/posts/2/og.png");
+    assert_eq!(json["author"]["@type"], "Person");
+    assert!(json["datePublished"].as_str().unwrap().ends_with('Z'));
+
+    assert!(body.contains("class='post h-entry'"));
+    assert!(body.contains("<data class='p-name' value='Code'></data>"));
+    assert!(body.contains("class='dt-published' datetime='"));
+    assert!(body.contains("class='post-content e-content "));
+    assert!(body.contains("class='unstyled-link u-url' id='long-url'"));
+
+    let (_, body) = request_body("/").await;
+    assert!(body.contains("class='introduction h-card'"));
+    assert!(body.contains("class='unstyled-link p-name u-url u-uid' href='/'"));
+    assert!(body.contains("class='about p-note'"));
+}
+
+#[tokio::test]
+async fn test_post_edit_conflict() {
+    let (ctx, auth) = request_cookie().await;
+    let version = fx::data::Post::get(&ctx.conn(), 2).unwrap().version();
+    let edit = |content: &str, version: &str| {
+        let form = [
+            ("content", content),
+            ("version", version),
+            ("publish", "Publish"),
+        ];
+        let form = serde_urlencoded::to_string(form).unwrap();
+        Request::builder()
+            .method("POST")
+            .uri("/posts/edit/2")
+            .header("Cookie", format!("auth={auth}"))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(form))
+            .unwrap()
+    };
+    let (status, body) = request_body_logged_in("/posts/edit/2").await;
+    assert_eq!(status, StatusCode::OK);
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains("type='hidden' name='version' value='"));
+
+    let req = edit("Edited on the laptop", &version);
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    let req = edit("Edited on the phone", &version);
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(body.contains("Edited on the laptop"));
+    assert!(body.contains("Edited on the phone"));
+    let post = fx::data::Post::get(&ctx.conn(), 2).unwrap();
+    assert_eq!(post.content.trim(), "Edited on the laptop");
+
+    // Publishing from the conflict page overwrites the saved version.
+    let req = edit("Edited on the phone", &post.version());
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    // Without a version, the edit could be based on anything.
+    let form = [("content", "No version"), ("publish", "Publish")];
+    let req = Request::builder()
+        .method("POST")
+        .uri("/posts/edit/2")
+        .header("Cookie", format!("auth={auth}"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(serde_urlencoded::to_string(form).unwrap()))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let mut post = fx::data::Post::get(&ctx.conn(), 2).unwrap();
+    post.content = "</textarea><script>alert(1)</script>".to_string();
+    post.update(&ctx.conn()).unwrap();
+    let response = app(ctx.clone())
+        .oneshot(edit("Mine", &version))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let body = String::from_utf8(body.to_vec()).unwrap();
+    assert!(!body.contains("<script>alert(1)"));
+    assert!(body.contains("&lt;/textarea&gt;&lt;script&gt;alert(1)"));
+}
+
 #[tokio::test]
 async fn test_no_crash_on_unknown_file() {
     let (status, _body) = request_body("/files/2323").await;
@@ -286,8 +533,8 @@ async fn test_sitemap() {
     assert!(body.contains("xml version"));
     assert!(body.contains("<urlset xmlns"));
     assert!(body.contains("<loc>/</loc>"));
-    assert!(body.contains("<loc>/posts/1</loc>"));
-    assert!(body.contains("<loc>/posts/2</loc>"));
+    assert!(body.contains("<loc>/posts/1/lorem-ipsum"));
+    assert!(body.contains("<loc>/posts/2/code</loc>"));
     assert!(body.contains("<lastmod>"));
 }
 
@@ -306,9 +553,78 @@ async fn test_rss() {
     let (status, body) = request_body("/feed.xml").await;
     assert_eq!(status, StatusCode::OK);
     println!("body:\n{body}");
-    assert!(body.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>"));
-    assert!(body.contains("<description><![CDATA[<p><a href='https://example.com"),);
-    assert!(body.contains("<description><![CDATA[<h1>Code</h1>"));
+    let channel = body.parse::<rss::Channel>().unwrap();
+    assert_eq!(channel.items.len(), 2);
+    let code = channel
+        .items
+        .iter()
+        .find(|item| item.title() == Some("Code"))
+        .unwrap();
+    assert_eq!(code.link(), Some("/posts/2/code"));
+    assert_eq!(code.guid().unwrap().value(), "/posts/2");
+    assert!(code.description().unwrap().starts_with("<h1>Code</h1>"));
+    let links = &channel.atom_ext().unwrap().links;
+    assert_eq!(links[0].href, "/feed.xml");
+}
+
+#[tokio::test]
+async fn test_atom() {
+    let ctx = server_context().await;
+    let mut post = fx::data::Post::get(&ctx.conn(), 2).unwrap();
+    post.updated = post.created + chrono::Duration::days(1);
+    post.update(&ctx.conn()).unwrap();
+    let req = Request::builder()
+        .uri("/atom.xml")
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/atom+xml; charset=utf-8"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let feed = atom_syndication::Feed::read_from(&body[..]).unwrap();
+    assert_eq!(feed.entries.len(), 2);
+    let code = feed.entries.iter().find(|e| e.id == "/posts/2").unwrap();
+    assert_eq!(code.title.as_str(), "Code");
+    assert_eq!(code.links[0].href, "/posts/2/code");
+    // Edited posts are marked as updated but keep their publication date.
+    let published = code.published.unwrap();
+    assert_eq!(code.updated, published + chrono::Duration::days(1));
+    assert_eq!(feed.updated, code.updated);
+    let content = code.content.as_ref().unwrap();
+    assert_eq!(content.content_type.as_deref(), Some("html"));
+    assert!(content.value.as_ref().unwrap().starts_with("<h1>Code</h1>"));
+}
+
+#[tokio::test]
+async fn test_json_feed() {
+    let (status, body) = request_body("/feed.json").await;
+    assert_eq!(status, StatusCode::OK);
+    let feed: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(feed["version"], "https://jsonfeed.org/version/1.1");
+    assert_eq!(feed["feed_url"], "/feed.json");
+    let items = feed["items"].as_array().unwrap();
+    assert_eq!(items.len(), 2);
+    let code = items.iter().find(|item| item["id"] == "/posts/2").unwrap();
+    assert_eq!(code["url"], "/posts/2/code");
+    assert_eq!(code["title"], "Code");
+    assert!(
+        code["content_html"]
+            .as_str()
+            .unwrap()
+            .starts_with("<h1>Code</h1>")
+    );
+    assert!(code["date_published"].is_string());
+}
+
+#[tokio::test]
+async fn test_feeds_advertised() {
+    let (_, body) = request_body("/").await;
+    assert!(body.contains("type='application/rss+xml' href='/feed.xml'"));
+    assert!(body.contains("type='application/atom+xml' href='/atom.xml'"));
+    assert!(body.contains("type='application/feed+json' href='/feed.json'"));
 }
 
 #[tokio::test]
@@ -316,3 +632,20 @@ async fn test_invalid_post_request() {
     let (status, _body) = request_body("/posts/foo").await;
     assert_eq!(status, StatusCode::NOT_FOUND);
 }
+
+#[tokio::test]
+async fn test_nodeinfo() {
+    let (status, body) = request_body("/.well-known/nodeinfo").await;
+    assert_eq!(status, StatusCode::OK);
+    let links: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(links["links"][0]["href"], "/nodeinfo/2.1");
+
+    let (status, body) = request_body("/nodeinfo/2.1").await;
+    assert_eq!(status, StatusCode::OK);
+    let nodeinfo: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(nodeinfo["version"], "2.1");
+    assert_eq!(nodeinfo["software"]["name"], "fx");
+    assert_eq!(nodeinfo["protocols"][0], "activitypub");
+    assert_eq!(nodeinfo["openRegistrations"], false);
+    assert_eq!(nodeinfo["usage"]["localPosts"], 2);
+}
@@ -0,0 +1,213 @@
+mod common;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::HeaderMap;
+use axum::http::StatusCode;
+use common::*;
+use fx::data::Post;
+use fx::serve::ServerContext;
+use fx::serve::app;
+use http_body_util::BodyExt;
+use serde_json::Value;
+use serde_json::json;
+use tower::util::ServiceExt;
+
+const TOKEN: &str = "Bearer test-password";
+
+async fn micropub(
+    ctx: &ServerContext,
+    method: &str,
+    uri: &str,
+    content_type: &str,
+    body: &str,
+    auth: Option<&str>,
+) -> (StatusCode, HeaderMap, String) {
+    let mut req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Content-Type", content_type);
+    if let Some(auth) = auth {
+        req = req.header("Authorization", auth);
+    }
+    let req = req.body(Body::from(body.to_string())).unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+const FORM: &str = "application/x-www-form-urlencoded";
+const JSON: &str = "application/json";
+
+#[tokio::test]
+async fn test_micropub_auth() {
+    let ctx = server_context().await;
+    let form = "h=entry&content=Hello";
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", FORM, form, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let wrong = Some("Bearer wrong");
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", FORM, form, wrong).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _, _) = micropub(&ctx, "GET", "/micropub?q=config", FORM, "", None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // The token can also be passed in the body.
+    let form = "h=entry&content=Hello&access_token=test-password";
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", FORM, form, None).await;
+    assert_eq!(status, StatusCode::CREATED);
+}
+
+#[tokio::test]
+async fn test_micropub_config() {
+    let ctx = server_context().await;
+    let uri = "/micropub?q=config";
+    let (status, _, body) = micropub(&ctx, "GET", uri, FORM, "", Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::OK);
+    let config: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(config["media-endpoint"], "/micropub/media");
+    let uri = "/micropub?q=unknown";
+    let (status, _, _) = micropub(&ctx, "GET", uri, FORM, "", Some(TOKEN)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_micropub_form() {
+    let ctx = server_context().await;
+    let form = "h=entry&name=Hello&content=From+my+phone&category[]=a&category[]=b";
+    let auth = Some(TOKEN);
+    let (status, headers, _) = micropub(&ctx, "POST", "/micropub", FORM, form, auth).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(headers["Location"], "/posts/3/hello");
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert_eq!(post.content.trim(), "# Hello\n\nFrom my phone");
+
+    let form = "action=delete&url=/posts/3/hello";
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", FORM, form, auth).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert_eq!(post.content, "<DELETED>");
+}
+
+#[tokio::test]
+async fn test_micropub_json() {
+    let ctx = server_context().await;
+    let auth = Some(TOKEN);
+    let body = json!({
+        "type": ["h-entry"],
+        "properties": {
+            "content": ["A note"],
+            "published": ["2024-03-01T10:00:00Z"],
+            "photo": [{ "value": "/files/69b83ddf8f65695f", "alt": "Example" }],
+        },
+    })
+    .to_string();
+    let (status, headers, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let location = headers["Location"].to_str().unwrap().to_string();
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert_eq!(
+        post.content.trim(),
+        "A note\n\n![Example](/files/69b83ddf8f65695f)"
+    );
+    assert_eq!(post.created.to_rfc3339(), "2024-03-01T10:00:00+00:00");
+
+    let body = json!({
+        "action": "update",
+        "url": location,
+        "replace": { "content": ["An edited note"], "name": ["Title"] },
+    })
+    .to_string();
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert_eq!(post.content.trim(), "# Title\n\nAn edited note");
+
+    let uri = format!("/micropub?q=source&url={location}");
+    let (status, _, body) = micropub(&ctx, "GET", &uri, FORM, "", auth).await;
+    assert_eq!(status, StatusCode::OK);
+    let source: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(source["properties"]["name"][0], "Title");
+    assert_eq!(source["properties"]["content"][0], "An edited note");
+
+    let body = json!({ "action": "update", "url": location, "delete": ["name"] }).to_string();
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert_eq!(post.content.trim(), "An edited note");
+
+    let mut post = post;
+    post.content = "---\ntitle: 'Front matter'\n---\n\nAn edited note".to_string();
+    post.update(&ctx.conn()).unwrap();
+    let (_, _, body) = micropub(&ctx, "GET", &uri, FORM, "", auth).await;
+    let source: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(source["properties"]["name"][0], "Front matter");
+    assert_eq!(source["properties"]["content"][0], "An edited note");
+    let body = json!({
+        "action": "update",
+        "url": location,
+        "replace": { "content": ["Edited again"] },
+    })
+    .to_string();
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert_eq!(
+        post.content.trim(),
+        "---\ntitle: 'Front matter'\n---\n\nEdited again"
+    );
+
+    let body = json!({
+        "action": "update",
+        "url": location,
+        "add": { "content": ["And more"], "photo": ["/files/69b83ddf8f65695f"] },
+    })
+    .to_string();
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let post = Post::get(&ctx.conn(), 3).unwrap();
+    assert!(
+        post.content
+            .trim()
+            .ends_with("Edited again\n\nAnd more\n\n![](/files/69b83ddf8f65695f)")
+    );
+    let body = json!({ "action": "update", "url": location, "add": { "category": ["rust"] } });
+    let body = body.to_string();
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let body = json!({ "action": "update", "url": "/posts/99" }).to_string();
+    let (status, _, _) = micropub(&ctx, "POST", "/micropub", JSON, &body, auth).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_micropub_media() {
+    let ctx = server_context().await;
+    let boundary = "XBOUNDARY";
+    let body = format!(
+        "--{boundary}\r\n\
+         Content-Disposition: form-data; name=\"file\"; filename=\"photo.png\"\r\n\
+         Content-Type: image/png\r\n\r\n\
+         not really a png\r\n\
+         --{boundary}--\r\n"
+    );
+    let content_type = format!("multipart/form-data; boundary={boundary}");
+    let uri = "/micropub/media";
+    let (status, _, _) = micropub(&ctx, "POST", uri, &content_type, &body, None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let auth = Some(TOKEN);
+    let (status, headers, _) = micropub(&ctx, "POST", uri, &content_type, &body, auth).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let location = headers["Location"].to_str().unwrap();
+    assert!(location.starts_with("/files/"));
+    let req = Request::builder()
+        .uri(location)
+        .body(Body::empty())
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    assert_eq!(body.as_ref(), b"not really a png");
+}
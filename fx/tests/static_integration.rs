@@ -0,0 +1,50 @@
+mod common;
+
+use chrono::Utc;
+use common::*;
+use fx::data::Post;
+
+#[tokio::test]
+async fn test_export_static() {
+    let ctx = server_context().await;
+    for i in 0..10 {
+        let now = Utc::now();
+        Post::insert(&ctx.conn(), now, now, &format!("Post number {i}"), None).unwrap();
+    }
+    let now = Utc::now();
+    let content = "# Attachment\n\n[Example](/files/69b83ddf8f65695f)";
+    Post::insert(&ctx.conn(), now, now, content, None).unwrap();
+    let out_dir = std::env::temp_dir().join(format!("fx-static-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&out_dir);
+    let report = fx::static_site::export(&ctx, &out_dir).await.unwrap();
+    println!("{report}");
+    let read = |path: &str| std::fs::read_to_string(out_dir.join(path)).unwrap();
+
+    let index = read("index.html");
+    assert!(index.contains("/page/2/"));
+    assert!(!index.contains("?page="));
+    assert!(!index.contains("/login"));
+    let page = read("page/2/index.html");
+    assert!(page.contains("/posts/"));
+    assert!(!out_dir.join("page/3").exists());
+
+    let post = read("posts/2/code/index.html");
+    assert!(post.contains("<h1"));
+    assert!(read("posts/2/index.html").contains("url=/posts/2/code"));
+    // Written with the extension so that static hosts set the Content-Type.
+    assert_eq!(read("files/69b83ddf8f65695f/example.txt"), "example");
+    let url = "/files/69b83ddf8f65695f/example.txt";
+    assert!(read("files/69b83ddf8f65695f/index.html").contains(&format!("url={url}")));
+    assert!(read("posts/13/attachment/index.html").contains(&format!("href=\"{url}\"")));
+    let og = std::fs::read(out_dir.join("posts/2/og.png")).unwrap();
+    assert!(og.starts_with(b"\x89PNG"));
+    assert!(read("feed.xml").contains("<rss"));
+    assert!(read("atom.xml").contains("<feed"));
+    assert!(read("feed.json").contains("jsonfeed.org"));
+    assert!(read("sitemap.xml").contains("/posts/1"));
+    assert!(read("robots.txt").contains("Sitemap"));
+    assert!(!read("static/style.css").is_empty());
+    assert!(read("404.html").contains("<html"));
+    assert_eq!(report.files, 1);
+    std::fs::remove_dir_all(&out_dir).unwrap();
+}
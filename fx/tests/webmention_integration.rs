@@ -0,0 +1,288 @@
+mod common;
+
+use axum::Router;
+use axum::body::Body;
+use axum::extract::Request;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::routing::post;
+use common::*;
+use fx::serve::ServerContext;
+use fx::serve::app;
+use http_body_util::BodyExt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use tower::util::ServiceExt;
+
+const DOMAIN: &str = "fx.example.com";
+
+/// A minimal site with a Webmention endpoint that records what it receives.
+#[derive(Clone)]
+struct Stub {
+    base: String,
+    received: Arc<Mutex<Vec<String>>>,
+}
+
+fn html(body: &str) -> ([(&'static str, &'static str); 1], String) {
+    (
+        [("Content-Type", "text/html; charset=utf-8")],
+        body.to_string(),
+    )
+}
+
+async fn start_stub() -> Stub {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let stub = Stub {
+        base: format!("http://{addr}"),
+        received: Arc::new(Mutex::new(Vec::new())),
+    };
+    let target = format!("https://{DOMAIN}/posts/1");
+    let source = format!("<title>A reply</title><a href='{target}'>Nice post</a>");
+    let router = Router::new()
+        .route(
+            "/article",
+            get(async || html("<link rel='webmention' href='/webmention'>")),
+        )
+        .route(
+            "/header",
+            get(async || ([("Link", "</webmention>; rel=\"webmention\"")], "")),
+        )
+        .route("/plain", get(async || html("No endpoint")))
+        .route(
+            "/source",
+            get(move || {
+                let source = source.clone();
+                async move { html(&source) }
+            }),
+        )
+        .route("/unrelated", get(async || html("<title>Other</title>")))
+        .route(
+            "/large",
+            get(move || {
+                // Streamed, so without a Content-Length, and linking at the end.
+                let chunks = (0..2048)
+                    .map(|_| " ".repeat(1024))
+                    .chain([format!("<a href='https://{DOMAIN}/posts/1'>Nice post</a>")])
+                    .map(Ok::<_, std::io::Error>);
+                async move { Body::from_stream(futures_util::stream::iter(chunks)) }
+            }),
+        )
+        .route(
+            "/webmention",
+            post(async |State(stub): State<Stub>, body: String| {
+                stub.received.lock().unwrap().push(body);
+                StatusCode::ACCEPTED
+            }),
+        )
+        .with_state(stub.clone());
+    tokio::spawn(async move {
+        axum::serve(listener, router).await.unwrap();
+    });
+    stub
+}
+
+async fn received(stub: &Stub, n: usize) -> Vec<String> {
+    for _ in 0..100 {
+        let received = stub.received.lock().unwrap().clone();
+        if n <= received.len() {
+            return received;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    stub.received.lock().unwrap().clone()
+}
+
+/// Wait until the background verification stored `n` webmentions.
+async fn webmentions(ctx: &ServerContext, n: i64) -> i64 {
+    let count = || {
+        let stmt = "SELECT COUNT(*) FROM replies WHERE object_id LIKE 'webmention:%'";
+        ctx.conn().query_row(stmt, [], |row| row.get(0)).unwrap()
+    };
+    for _ in 0..100 {
+        if count() == n {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    count()
+}
+
+async fn send(
+    ctx: &ServerContext,
+    auth: &str,
+    method: &str,
+    uri: &str,
+    form: &str,
+) -> (StatusCode, String) {
+    let req = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Cookie", format!("auth={auth}"))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(Body::from(form.to_string()))
+        .unwrap();
+    let response = app(ctx.clone()).oneshot(req).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn test_send_webmentions() {
+    let (mut ctx, auth) = request_cookie().await;
+    ctx.args.domain = DOMAIN.to_string();
+    let stub = start_stub().await;
+    let base = &stub.base;
+    let content = format!(
+        "# Links\n\nSee [this]({base}/article), [that]({base}/header) and [other]({base}/plain)."
+    );
+    let form =
+        serde_urlencoded::to_string([("content", &*content), ("publish", "Publish")]).unwrap();
+    let (status, _body) = send(&ctx, &auth, "POST", "/posts/add", &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+
+    let mut received = received(&stub, 2).await;
+    received.sort();
+    let source = format!("https://{DOMAIN}/posts/3/links");
+    let expected = [format!("{base}/article"), format!("{base}/header")]
+        .iter()
+        .map(|target| {
+            serde_urlencoded::to_string([("source", &source), ("target", target)]).unwrap()
+        })
+        .collect::<Vec<String>>();
+    assert_eq!(received, expected);
+
+    // Links that an edit removes are notified too, so the mention can be removed.
+    let content = format!("# Links\n\nSee [this]({base}/article).");
+    let version = fx::data::Post::get(&ctx.conn(), 3).unwrap().version();
+    let form = [
+        ("content", &*content),
+        ("version", &version),
+        ("publish", "Publish"),
+    ];
+    let form = serde_urlencoded::to_string(form).unwrap();
+    let (status, _body) = send(&ctx, &auth, "POST", "/posts/edit/3", &form).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let mut received = self::received(&stub, 4).await;
+    received.sort();
+    let mut expected = [expected.clone(), expected].concat();
+    expected.sort();
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn test_receive_webmention() {
+    let (mut ctx, auth) = request_cookie().await;
+    ctx.args.domain = DOMAIN.to_string();
+    let mut post = fx::data::Post::get(&ctx.conn(), 1).unwrap();
+    post.slug = Some("lorem".to_string());
+    post.update(&ctx.conn()).unwrap();
+    let stub = start_stub().await;
+    let target = format!("https://{DOMAIN}/posts/1");
+    let form = |source: &str, target: &str| {
+        serde_urlencoded::to_string([("source", source), ("target", target)]).unwrap()
+    };
+
+    let (_status, body) = send(&ctx, "", "GET", "/", "").await;
+    assert!(body.contains("<link rel='webmention' href='/webmention'>"));
+
+    let source = format!("{}/source", stub.base);
+    let other = "https://other.example.com/posts/1";
+    let (status, _body) = send(&ctx, "", "POST", "/webmention", &form(&source, other)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _body) = send(&ctx, "", "POST", "/webmention", &form(&source, &target)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(webmentions(&ctx, 1).await, 1);
+    let (_status, body) = send(&ctx, "", "GET", "/posts/1/lorem", "").await;
+    assert!(!body.contains("A reply"));
+    let (_status, body) = send(&ctx, &auth, "GET", "/replies", "").await;
+    assert!(body.contains("A reply"));
+
+    let (status, _body) = send(&ctx, &auth, "POST", "/replies/approve/1", "").await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let (_status, body) = send(&ctx, "", "GET", "/posts/1/lorem", "").await;
+    assert!(body.contains(&format!("Mentioned in <a href='{source}'")));
+    assert!(body.contains("A reply"));
+
+    // A mention is removed once the source no longer links to the post.
+    let stmt = "UPDATE replies SET object_id = ? WHERE object_id = ?";
+    let unrelated = format!("{}/unrelated", stub.base);
+    let object_ids = [
+        format!("webmention:1:{unrelated}"),
+        format!("webmention:1:{source}"),
+    ];
+    ctx.conn().execute(stmt, object_ids).unwrap();
+    let (status, _body) = send(&ctx, "", "POST", "/webmention", &form(&unrelated, &target)).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    assert_eq!(webmentions(&ctx, 0).await, 0);
+}
+
+#[tokio::test]
+async fn test_receive_webmention_large_source() {
+    let mut ctx = server_context().await;
+    ctx.args.domain = DOMAIN.to_string();
+    let stub = start_stub().await;
+    let target = format!("https://{DOMAIN}/posts/1");
+    for source in ["large", "source"] {
+        let source = format!("{}/{source}", stub.base);
+        let form = serde_urlencoded::to_string([("source", &source), ("target", &target)]);
+        let (status, _body) = send(&ctx, "", "POST", "/webmention", &form.unwrap()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+    }
+    assert_eq!(webmentions(&ctx, 1).await, 1);
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let stmt = "SELECT object_id FROM replies WHERE object_id LIKE 'webmention:%'";
+    let object_id: String = ctx.conn().query_row(stmt, [], |row| row.get(0)).unwrap();
+    assert_eq!(object_id, format!("webmention:1:{}/source", stub.base));
+}
+
+#[tokio::test]
+async fn test_receive_webmention_private_source() {
+    let mut ctx = server_context().await;
+    ctx.args.domain = DOMAIN.to_string();
+    ctx.args.allow_private_addresses = false;
+    let stub = start_stub().await;
+    let source = format!("{}/source", stub.base);
+    let target = format!("https://{DOMAIN}/posts/1");
+    let form = serde_urlencoded::to_string([("source", &source), ("target", &target)]).unwrap();
+    let (status, body) = send(&ctx, "", "POST", "/webmention", &form).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("not a public address"));
+}
+
+#[tokio::test]
+async fn test_webmention_does_not_touch_replies() {
+    let mut ctx = server_context().await;
+    ctx.args.domain = DOMAIN.to_string();
+    let stub = start_stub().await;
+    let target = format!("https://{DOMAIN}/posts/1");
+    // Replies from ActivityPub are stored with the URL of the reply as id.
+    for path in ["/source", "/unrelated"] {
+        let stmt = "
+            INSERT INTO replies
+              (object_id, post_id, author_name, author_url, content, published, status)
+            VALUES (?, 1, 'alice', ?, '<p>Original</p>', '2025-01-01 00:00:00', 'approved');
+        ";
+        let object_id = format!("{}{path}", stub.base);
+        ctx.conn().execute(stmt, [&object_id, &object_id]).unwrap();
+    }
+    for path in ["/source", "/unrelated"] {
+        let source = format!("{}{path}", stub.base);
+        let form = serde_urlencoded::to_string([("source", &source), ("target", &target)]);
+        send(&ctx, "", "POST", "/webmention", &form.unwrap()).await;
+    }
+    assert_eq!(webmentions(&ctx, 1).await, 1);
+    let originals: i64 = ctx
+        .conn()
+        .query_row(
+            "SELECT COUNT(*) FROM replies WHERE content = '<p>Original</p>' AND status = 'approved'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(originals, 2);
+}